@@ -1,4 +1,9 @@
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+use fyin::dates::Recency;
+use fyin::sanitize::InjectionPolicy;
+use fyin::search::{SafeSearch, Source, TimeRange};
+use std::path::PathBuf;
+use std::time::Duration;
 
 /// fyin.app - Open source CLI alternative to Perplexity AI.
 #[derive(Parser, Debug)]
@@ -6,9 +11,227 @@ use clap::Parser;
 pub struct Args {
     /// Search Query
     #[arg(short, long)]
-    pub query: String,
+    pub query: Option<String>,
 
+    /// Answer from the most relevant chunks, summarize whole documents, or
+    /// compare the things the query names in a table
+    #[arg(long, value_enum, default_value_t = Mode::Answer)]
+    pub mode: Mode,
+
+    /// Research over several rounds of searching and write a long-form report
+    #[arg(long, conflicts_with_all = ["urls", "files", "collection", "chat"])]
+    pub deep: bool,
+
+    /// Deep research: most search-and-read rounds [default: 3]
+    #[arg(long, requires = "deep")]
+    pub rounds: Option<usize>,
+
+    /// Deep research: most pages to find across all rounds [default: 30]
+    #[arg(long, requires = "deep")]
+    pub max_pages: Option<usize>,
+
+    /// Deep research: most tokens for planning, estimated [default: 20000]
+    #[arg(long, requires = "deep")]
+    pub max_tokens: Option<usize>,
+
+    /// Deep research: seconds before writing the report [default: 300]
+    #[arg(long, requires = "deep", value_parser = seconds)]
+    pub max_time: Option<Duration>,
+
+    /// Compare mode: something to compare, instead of the ones the query names (repeatable)
+    #[arg(long = "entity")]
+    pub entities: Vec<String>,
+
+    /// Compare mode: a dimension to compare on, instead of ones the model picks (repeatable)
+    #[arg(long = "dimension")]
+    pub dimensions: Vec<String>,
+
+    /// Compare mode: print the table as JSON instead of Markdown
+    #[arg(long)]
+    pub json: bool,
+
+    /// Page to answer from or summarize instead of searching (repeatable)
+    #[arg(long = "url")]
+    pub urls: Vec<String>,
+
+    /// Local file to answer from or summarize: PDF, HTML or text (repeatable)
+    #[arg(long = "file")]
+    pub files: Vec<PathBuf>,
+
+    /// Keep asking: after each answer, pick a related question by number or type a new one
+    #[arg(long)]
+    pub chat: bool,
+
+    /// Don't suggest related questions after the answer
+    #[arg(long)]
+    pub no_follow_ups: bool,
+
+    /// Persistent collection to add this query's sources to and answer from
+    #[arg(short, long)]
+    pub collection: Option<String>,
+
+    #[command(flatten)]
+    pub pipeline: PipelineArgs,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Pipeline settings, accepted before or after any subcommand so that
+/// `check`, `serve` and `mcp` can be configured like a query.
+#[derive(clap::Args, Debug)]
+pub struct PipelineArgs {
     /// Number of search results to parse
-    #[arg(short, long, default_value_t = 10)]
+    #[arg(short, long, default_value_t = 10, global = true)]
     pub search: usize,
+
+    /// Start answering once this many chunks are embedded instead of waiting for every page
+    #[arg(long, global = true)]
+    pub min_chunks: Option<usize>,
+
+    /// Start answering after this many seconds with whatever pages have loaded
+    #[arg(long, global = true, value_parser = seconds)]
+    pub deadline: Option<Duration>,
+
+    /// Search Wikipedia, arXiv or the web; by default picked from the query
+    #[arg(long, value_enum, global = true)]
+    pub source: Option<Source>,
+
+    /// Only use results from this domain (repeatable)
+    #[arg(long = "site", global = true)]
+    pub sites: Vec<String>,
+
+    /// Never use results from this domain (repeatable)
+    #[arg(long = "exclude-site", global = true)]
+    pub exclude_sites: Vec<String>,
+
+    /// Market/language for search results, e.g. en-US or de-DE
+    #[arg(long, global = true)]
+    pub market: Option<String>,
+
+    /// Only search results from the past day, week, month or year
+    #[arg(long, value_enum, global = true)]
+    pub time_range: Option<TimeRange>,
+
+    /// Safe-search level passed to the search engine
+    #[arg(long, value_enum, global = true)]
+    pub safe_search: Option<SafeSearch>,
+
+    /// Skip this many search results, for paging
+    #[arg(long, default_value_t = 0, global = true)]
+    pub offset: usize,
+
+    /// Prefer recent sources: for time-sensitive queries (auto), always, or never
+    #[arg(long, value_enum, default_value_t = Recency::Auto, global = true)]
+    pub recency: Recency,
+
+    /// For queries not in English, also search with an English translation.
+    /// Answers stay in the query's language either way
+    #[arg(long, global = true)]
+    pub translate: bool,
+
+    /// Let the chat model use a calculator, a date utility and new searches
+    /// while answering
+    #[arg(long, global = true)]
+    pub tools: bool,
+
+    /// What to do with retrieved chunks that look like prompt injection
+    #[arg(long, value_enum, default_value_t = InjectionPolicy::Flag, global = true)]
+    pub injection: InjectionPolicy,
+
+    /// Print how long each pipeline stage took
+    #[arg(long, global = true)]
+    pub timings: bool,
+
+    /// Write every pipeline span to this file as JSON lines
+    #[arg(long, global = true)]
+    pub trace: Option<PathBuf>,
+
+    /// Answer style: concise, detailed, bullets, eli5, how-to, a template name or a template file
+    #[arg(long, global = true)]
+    pub style: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Mode {
+    Answer,
+    /// Map-reduce summary of every section of the documents
+    Summarize,
+    /// Table comparing "X vs Y" on several dimensions, searched per entity
+    Compare,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Fact-check a claim against sources that support and refute it
+    Check {
+        /// The claim to check
+        claim: String,
+
+        /// Print the verdict and evidence as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Run a Model Context Protocol server on stdin/stdout
+    Mcp,
+
+    /// Serve an OpenAI-compatible chat completions API backed by the pipeline
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8000")]
+        addr: String,
+    },
+
+    /// Manage persistent vector collections
+    Collections {
+        #[command(subcommand)]
+        action: CollectionAction,
+    },
+
+    /// Score answer quality over a QA dataset with frozen source snapshots
+    Eval {
+        /// Dataset JSON file
+        #[arg(short, long)]
+        dataset: PathBuf,
+
+        /// Config JSON for the baseline run (models, top_k, chunk_size)
+        #[arg(short, long)]
+        baseline: Option<PathBuf>,
+
+        /// Config JSON to compare against the baseline
+        #[arg(short = 'C', long)]
+        candidate: Option<PathBuf>,
+
+        /// Chat model used as LLM-as-judge for answer correctness
+        #[arg(short, long)]
+        judge_model: Option<String>,
+
+        /// Where to write the Markdown report
+        #[arg(short, long, default_value = "eval-report.md")]
+        report: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CollectionAction {
+    /// List stored collections
+    List,
+
+    /// Delete a collection and everything stored in it
+    Delete {
+        /// Collection name
+        name: String,
+    },
+}
+
+// A positive, finite number of seconds
+fn seconds(text: &str) -> Result<Duration, String> {
+    let seconds: f64 = text
+        .parse()
+        .map_err(|_| format!("'{}' is not a number of seconds", text))?;
+    if !seconds.is_finite() || seconds <= 0.0 {
+        return Err(format!("'{}' is not a positive number of seconds", text));
+    }
+    Duration::try_from_secs_f64(seconds).map_err(|e| e.to_string())
 }
@@ -0,0 +1,186 @@
+//! Fact-checking claims: the model weighs each retrieved chunk for or against
+//! a claim and gives a verdict, which is only kept if the evidence backs it.
+
+use crate::data::Chunk;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Supported,
+    Refuted,
+    Mixed,
+    InsufficientEvidence,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stance {
+    Supports,
+    Refutes,
+    Irrelevant,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Evidence {
+    /// 1-based citation number into `FactCheck::chunks`
+    pub source: usize,
+    pub reason: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FactCheck {
+    pub claim: String,
+    pub verdict: Verdict,
+    /// The model's reasoning, with in-text citations
+    pub explanation: String,
+    pub supporting: Vec<Evidence>,
+    pub refuting: Vec<Evidence>,
+    /// Retrieved chunks in citation order: `chunks[0]` is `[1]`
+    pub chunks: Vec<Chunk>,
+}
+
+// The shape the fact-check prompt asks for
+#[derive(Deserialize)]
+struct Reply {
+    #[serde(default)]
+    evidence: Vec<ReplyEvidence>,
+    #[serde(default)]
+    verdict: String,
+    #[serde(default)]
+    explanation: String,
+}
+
+#[derive(Deserialize)]
+struct ReplyEvidence {
+    source: usize,
+    stance: String,
+    #[serde(default)]
+    reason: String,
+}
+
+impl Verdict {
+    /// Reads labels like "supported" or "Insufficient evidence".
+    pub fn parse(label: &str) -> Option<Self> {
+        match label
+            .trim()
+            .to_lowercase()
+            .replace(['_', '-'], " ")
+            .as_str()
+        {
+            "supported" => Some(Verdict::Supported),
+            "refuted" => Some(Verdict::Refuted),
+            "mixed" => Some(Verdict::Mixed),
+            "insufficient evidence" | "insufficient" => Some(Verdict::InsufficientEvidence),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Verdict::Supported => "Supported",
+            Verdict::Refuted => "Refuted",
+            Verdict::Mixed => "Mixed",
+            Verdict::InsufficientEvidence => "Insufficient evidence",
+        }
+    }
+
+    // The verdict the classified evidence alone allows
+    fn from_evidence(supporting: usize, refuting: usize) -> Self {
+        match (supporting > 0, refuting > 0) {
+            (true, true) => Verdict::Mixed,
+            (true, false) => Verdict::Supported,
+            (false, true) => Verdict::Refuted,
+            (false, false) => Verdict::InsufficientEvidence,
+        }
+    }
+
+    // Whether the evidence leaves room for this verdict
+    fn is_backed(&self, supporting: usize, refuting: usize) -> bool {
+        match self {
+            Verdict::Supported => supporting > 0,
+            Verdict::Refuted => refuting > 0,
+            Verdict::Mixed => supporting > 0 && refuting > 0,
+            Verdict::InsufficientEvidence => true,
+        }
+    }
+}
+
+impl Stance {
+    fn parse(label: &str) -> Option<Self> {
+        match label.trim().to_lowercase().as_str() {
+            "supports" | "support" | "supporting" => Some(Stance::Supports),
+            "refutes" | "refute" | "refuting" | "contradicts" => Some(Stance::Refutes),
+            "irrelevant" | "neutral" => Some(Stance::Irrelevant),
+            _ => None,
+        }
+    }
+}
+
+impl FactCheck {
+    /// Nothing could be read, so nothing was asked of the model.
+    pub fn without_sources(claim: &str) -> Self {
+        FactCheck {
+            claim: claim.to_string(),
+            verdict: Verdict::InsufficientEvidence,
+            explanation: "No sources about the claim could be read.".to_string(),
+            supporting: vec![],
+            refuting: vec![],
+            chunks: vec![],
+        }
+    }
+
+    /// Reads the model's JSON reply. Evidence must cite one of `chunks`, and
+    /// a verdict the evidence does not back is replaced by the one it does.
+    pub fn parse(claim: &str, chunks: Vec<Chunk>, reply: &str) -> Result<Self> {
+        let json = match (reply.find('{'), reply.rfind('}')) {
+            (Some(start), Some(end)) if start < end => &reply[start..=end],
+            _ => return Err(anyhow!("The model did not reply with a verdict")),
+        };
+        let reply: Reply = serde_json::from_str(json)
+            .map_err(|e| anyhow!("The model's verdict is not valid: {}", e))?;
+
+        let mut supporting = vec![];
+        let mut refuting = vec![];
+        let mut seen = vec![];
+        for evidence in reply.evidence {
+            if !(1..=chunks.len()).contains(&evidence.source) || seen.contains(&evidence.source) {
+                continue;
+            }
+            seen.push(evidence.source);
+            let side = match Stance::parse(&evidence.stance) {
+                Some(Stance::Supports) => &mut supporting,
+                Some(Stance::Refutes) => &mut refuting,
+                _ => continue,
+            };
+            side.push(Evidence {
+                source: evidence.source,
+                reason: evidence.reason.trim().to_string(),
+            });
+        }
+
+        let backed = Verdict::from_evidence(supporting.len(), refuting.len());
+        let verdict = match Verdict::parse(&reply.verdict) {
+            Some(verdict) if verdict.is_backed(supporting.len(), refuting.len()) => verdict,
+            Some(verdict) => {
+                tracing::warn!(
+                    "Verdict '{}' is not backed by the evidence, using '{}'",
+                    verdict.label(),
+                    backed.label()
+                );
+                backed
+            }
+            None => backed,
+        };
+
+        Ok(FactCheck {
+            claim: claim.to_string(),
+            verdict,
+            explanation: reply.explanation.trim().to_string(),
+            supporting,
+            refuting,
+            chunks,
+        })
+    }
+}
@@ -0,0 +1,180 @@
+use crate::data::{Request, SearchResult};
+use crate::vector::VectorDB;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+const DEFAULT_DATA_DIR: &str = ".fyin";
+const INDEX_FILE: &str = "index.bin";
+const STORE_FILE: &str = "collection.json";
+
+// Everything except the vectors themselves; the vectors live in the hora dump
+// next to it, keyed by the same chunk ids.
+#[derive(Serialize, Deserialize, Default)]
+pub struct CollectionStore {
+    pub dimension: usize,
+    pub queries: Vec<String>,
+    pub search_map: HashMap<String, SearchResult>,
+    pub chunk_id_chunk_map: HashMap<usize, String>,
+    pub chunk_id_to_search_id: HashMap<usize, String>,
+}
+
+pub struct Collection {
+    pub name: String,
+    pub store: CollectionStore,
+    dir: PathBuf,
+}
+
+pub struct CollectionInfo {
+    pub name: String,
+    pub sources: usize,
+    pub chunks: usize,
+    pub queries: usize,
+}
+
+// FYIN_DATA_DIR overrides the default of ~/.fyin
+pub fn data_dir() -> PathBuf {
+    match env::var("FYIN_DATA_DIR") {
+        Ok(value) if !value.trim().is_empty() => PathBuf::from(value),
+        _ => {
+            let home = env::var("HOME").unwrap_or_else(|_| ".".to_string());
+            PathBuf::from(home).join(DEFAULT_DATA_DIR)
+        }
+    }
+}
+
+fn collections_dir() -> PathBuf {
+    data_dir().join("collections")
+}
+
+fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Invalid collection name '{}': use letters, digits, '-' or '_'",
+            name
+        ))
+    }
+}
+
+impl Collection {
+    /// Opens a collection by name, starting an empty one if it does not exist yet.
+    /// Nothing is written to disk until `save` is called.
+    pub fn open(name: &str) -> Result<Self> {
+        validate_name(name)?;
+        let dir = collections_dir().join(name);
+        let store_path = dir.join(STORE_FILE);
+
+        let store = if store_path.exists() {
+            serde_json::from_str(&fs::read_to_string(&store_path)?)?
+        } else {
+            CollectionStore::default()
+        };
+
+        Ok(Collection {
+            name: name.to_string(),
+            store,
+            dir,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.store.chunk_id_chunk_map.is_empty()
+    }
+
+    /// Loads the stored index, or creates a fresh one for an empty collection.
+    pub async fn vector_db(&self, dimension: usize) -> Result<VectorDB> {
+        let index_path = self.dir.join(INDEX_FILE);
+        if self.is_empty() || !index_path.exists() {
+            return VectorDB::init(Some(dimension)).await;
+        }
+
+        if self.store.dimension != dimension {
+            return Err(anyhow!(
+                "Collection '{}' was built with {}-dimensional embeddings, but the current embedding model produces {}",
+                self.name,
+                self.store.dimension,
+                dimension
+            ));
+        }
+        VectorDB::load(&index_path).await
+    }
+
+    /// Copies the stored sources and chunks into the request so that retrieval
+    /// can resolve ids from earlier queries, and new chunk ids continue after them.
+    pub fn load_into(&self, request: &mut Request) {
+        request.search_map.extend(self.store.search_map.clone());
+        request
+            .chunk_id_chunk_map
+            .extend(self.store.chunk_id_chunk_map.clone());
+        request
+            .chunk_id_to_search_id
+            .extend(self.store.chunk_id_to_search_id.clone());
+    }
+
+    pub async fn save(&mut self, request: &Request, vector_db: &mut VectorDB) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+
+        self.store.dimension = vector_db.dimension();
+        self.store.queries.push(request.query.clone());
+        self.store.search_map = request.search_map.clone();
+        self.store.chunk_id_chunk_map = request.chunk_id_chunk_map.clone();
+        self.store.chunk_id_to_search_id = request.chunk_id_to_search_id.clone();
+
+        vector_db.dump(&self.dir.join(INDEX_FILE)).await?;
+        fs::write(
+            self.dir.join(STORE_FILE),
+            serde_json::to_string(&self.store)?,
+        )?;
+
+        tracing::info!(
+            "Saved collection '{}' with {} chunks",
+            self.name,
+            self.store.chunk_id_chunk_map.len()
+        );
+        Ok(())
+    }
+
+    pub fn list() -> Result<Vec<CollectionInfo>> {
+        let dir = collections_dir();
+        if !dir.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut infos = vec![];
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if !entry.path().join(STORE_FILE).exists() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            let collection = Collection::open(&name)?;
+            infos.push(CollectionInfo {
+                name,
+                sources: collection.store.search_map.len(),
+                chunks: collection.store.chunk_id_chunk_map.len(),
+                queries: collection.store.queries.len(),
+            });
+        }
+        infos.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(infos)
+    }
+
+    pub fn delete(name: &str) -> Result<()> {
+        validate_name(name)?;
+        let dir = collections_dir().join(name);
+        if !dir.exists() {
+            return Err(anyhow!("Collection '{}' does not exist", name));
+        }
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
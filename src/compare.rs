@@ -0,0 +1,231 @@
+//! Comparison tables for "X vs Y" questions. Each entity gets its own search
+//! and retrieval, so one well-covered entity cannot crowd out the others, and
+//! the model fills in a table whose every cell cites its evidence.
+
+use crate::data::Chunk;
+use crate::llm::MAX_ENTITIES;
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::sync::OnceLock;
+
+/// Cell text when the sources say nothing about an entity on a dimension.
+pub const NOT_COVERED: &str = "Not covered by the sources";
+
+const VERSUS: &str = r"(?i)\s+(?:vs\.?|versus)\s+";
+const LEADING_WORDS: &str = r"(?i)^(?:compare|comparing|comparison of|differences? between|which is better|what is better)\s+";
+// context after the last entity, as in "tokio vs async-std for web servers"
+const CONTEXT: &str = r"(?i)\s+(?:for|in|on|when|with|as|under|to)\s+";
+const FILLER: &str = r"(?i)\bvs\.|\b(?:vs|versus|compare[ds]?|comparing|comparison|differences?|between|and|or)\b|[,;:?!]";
+const CITATION: &str = r"\[(\d+(?:\s*,\s*\d+)*)\]";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Comparison {
+    pub query: String,
+    /// Column headings, in the order of each row's cells
+    pub entities: Vec<String>,
+    pub rows: Vec<Row>,
+    /// Retrieved chunks in citation order: `chunks[0]` is `[1]`
+    pub chunks: Vec<Chunk>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Row {
+    pub dimension: String,
+    /// One cell per entity
+    pub cells: Vec<Cell>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Cell {
+    /// Text with its in-text citations, like `Multi-threaded [2]`
+    pub text: String,
+    /// 1-based citation numbers into `Comparison::chunks`
+    pub citations: Vec<usize>,
+}
+
+// The shape the comparison prompt asks for
+#[derive(Deserialize)]
+struct Reply {
+    dimensions: Vec<ReplyRow>,
+}
+
+#[derive(Deserialize)]
+struct ReplyRow {
+    name: String,
+    #[serde(default)]
+    cells: Vec<String>,
+}
+
+fn regex(cell: &'static OnceLock<Regex>, pattern: &str) -> &'static Regex {
+    cell.get_or_init(|| Regex::new(pattern).unwrap())
+}
+
+/// The entities of an "A vs B" question, without asking the model; empty
+/// when the query is not phrased that way.
+pub fn detect_entities(query: &str) -> Vec<String> {
+    static SPLIT: OnceLock<Regex> = OnceLock::new();
+    static LEADING: OnceLock<Regex> = OnceLock::new();
+    static TAIL: OnceLock<Regex> = OnceLock::new();
+    let query = query.trim().trim_end_matches(['?', '.', '!']);
+    let parts: Vec<&str> = regex(&SPLIT, VERSUS).split(query).collect();
+    if parts.len() < 2 {
+        return vec![];
+    }
+
+    let last = parts.len() - 1;
+    let mut entities: Vec<String> = vec![];
+    for (i, part) in parts.into_iter().enumerate() {
+        let mut part = part.trim();
+        if i == 0 {
+            // "Which is faster, A vs B"
+            part = part.rsplit([',', ':']).next().unwrap_or(part).trim();
+            part = match regex(&LEADING, LEADING_WORDS).find(part) {
+                Some(leading) => &part[leading.end()..],
+                None => part,
+            };
+        }
+        if i == last {
+            part = regex(&TAIL, CONTEXT).split(part).next().unwrap_or(part);
+        }
+        let entity = part.trim().trim_matches(['"', '\'']).trim();
+        if !entity.is_empty() && !entities.iter().any(|e| e.eq_ignore_ascii_case(entity)) {
+            entities.push(entity.to_string());
+        }
+    }
+    entities.truncate(MAX_ENTITIES);
+    if entities.len() < 2 {
+        return vec![];
+    }
+    entities
+}
+
+/// The search query for one entity: the entity plus whatever the question
+/// says after the last entity it names, e.g. "tokio for web servers".
+pub fn entity_query(query: &str, entity: &str, entities: &[String]) -> String {
+    static FILLER_WORDS: OnceLock<Regex> = OnceLock::new();
+    let end = entities
+        .iter()
+        .filter_map(|name| {
+            let name = Regex::new(&format!("(?i){}", regex::escape(name))).unwrap();
+            name.find_iter(query).last().map(|found| found.end())
+        })
+        .max()
+        // entities given by the caller need not appear in the question
+        .unwrap_or(0);
+    let rest = regex(&FILLER_WORDS, FILLER).replace_all(&query[end..], " ");
+    let rest = rest.split_whitespace().collect::<Vec<_>>().join(" ");
+    if rest.is_empty() {
+        entity.to_string()
+    } else {
+        format!("{} {}", entity, rest)
+    }
+}
+
+impl Comparison {
+    /// Reads the model's JSON reply. Each row gets exactly one cell per
+    /// entity, and a cell without a valid citation becomes `NOT_COVERED`.
+    pub fn parse(
+        query: &str,
+        entities: Vec<String>,
+        chunks: Vec<Chunk>,
+        reply: &str,
+    ) -> Result<Self> {
+        let json = match (reply.find('{'), reply.rfind('}')) {
+            (Some(start), Some(end)) if start < end => &reply[start..=end],
+            _ => return Err(anyhow!("The model did not reply with a comparison table")),
+        };
+        let reply: Reply = serde_json::from_str(json)
+            .map_err(|e| anyhow!("The model's comparison table is not valid: {}", e))?;
+
+        let rows: Vec<Row> = reply
+            .dimensions
+            .into_iter()
+            .filter(|row| !row.name.trim().is_empty())
+            .map(|row| {
+                let mut cells = row.cells.into_iter();
+                Row {
+                    dimension: row.name.trim().to_string(),
+                    cells: entities
+                        .iter()
+                        .map(|_| cell(&cells.next().unwrap_or_default(), chunks.len()))
+                        .collect(),
+                }
+            })
+            .collect();
+        if rows.is_empty() {
+            return Err(anyhow!("The model's comparison table has no rows"));
+        }
+
+        Ok(Comparison {
+            query: query.to_string(),
+            entities,
+            rows,
+            chunks,
+        })
+    }
+
+    /// A Markdown table followed by the sources its cells cite.
+    pub fn to_markdown(&self) -> String {
+        let mut lines = vec![
+            format!("| | {} |", escape_cells(&self.entities).join(" | ")),
+            format!("| --- |{}", " --- |".repeat(self.entities.len())),
+        ];
+        for row in &self.rows {
+            let cells: Vec<String> = row.cells.iter().map(|cell| cell.text.clone()).collect();
+            lines.push(format!(
+                "| {} | {} |",
+                escape_cell(&row.dimension),
+                escape_cells(&cells).join(" | ")
+            ));
+        }
+
+        let cited: BTreeSet<usize> = self
+            .rows
+            .iter()
+            .flat_map(|row| row.cells.iter())
+            .flat_map(|cell| cell.citations.iter().copied())
+            .collect();
+        if !cited.is_empty() {
+            lines.push(String::new());
+            lines.push("Sources:".to_string());
+            for id in cited {
+                let chunk = &self.chunks[id - 1];
+                lines.push(format!("[{}] {} - {}", id, chunk.name, chunk.url));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+// Keeps the citations that point at a chunk
+fn cell(text: &str, chunks: usize) -> Cell {
+    static CITATIONS: OnceLock<Regex> = OnceLock::new();
+    let mut citations: Vec<usize> = vec![];
+    for captures in regex(&CITATIONS, CITATION).captures_iter(text) {
+        for id in captures[1].split(',').filter_map(|n| n.trim().parse().ok()) {
+            if (1..=chunks).contains(&id) && !citations.contains(&id) {
+                citations.push(id);
+            }
+        }
+    }
+    if citations.is_empty() {
+        return Cell {
+            text: NOT_COVERED.to_string(),
+            citations,
+        };
+    }
+    Cell {
+        text: text.trim().to_string(),
+        citations,
+    }
+}
+
+pub(crate) fn escape_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+fn escape_cells(texts: &[String]) -> Vec<String> {
+    texts.iter().map(|text| escape_cell(text)).collect()
+}
@@ -1,3 +1,6 @@
+use crate::language;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
@@ -10,7 +13,7 @@ pub struct Request {
     pub chunk_id_to_search_id: HashMap<usize, String>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SearchResult {
     // name
     pub name: String,
@@ -19,16 +22,28 @@ pub struct SearchResult {
     pub url: String,
 
     // content of the webiste
+    #[serde(skip)]
     pub content: Option<String>,
+
+    // when the page was published or last updated, if it says
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub published: Option<NaiveDate>,
+
+    // ISO 639-3 code of the content's language, detected once it is known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Chunk {
     pub content: String,
 
     pub name: String,
 
     pub url: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub published: Option<NaiveDate>,
 }
 
 pub fn hash_string(input: &str) -> String {
@@ -46,15 +61,29 @@ impl Request {
         }))
     }
 
-    pub fn add_search_result(&mut self, search_result: SearchResult) {
+    pub fn add_search_result(&mut self, mut search_result: SearchResult) {
+        if search_result.language.is_none() {
+            search_result.language = search_result
+                .content
+                .as_deref()
+                .and_then(language::detect)
+                .map(str::to_string);
+        }
         let url_hash = hash_string(&search_result.url);
         self.search_map.insert(url_hash, search_result);
     }
 
-    pub fn add_webpage_content(&mut self, url: &str, content: String) {
-        let url_hash = hash_string(&url);
+    pub fn add_webpage_content(
+        &mut self,
+        url: &str,
+        content: String,
+        published: Option<NaiveDate>,
+    ) {
+        let url_hash = hash_string(url);
         if let Some(search_result) = self.search_map.get_mut(&url_hash) {
+            search_result.language = language::detect(&content).map(str::to_string);
             search_result.content = Some(content.to_string());
+            search_result.published = published.or(search_result.published);
         }
     }
 
@@ -64,17 +93,24 @@ impl Request {
             .insert(id, search_result_id.to_string());
     }
 
+    // ids are 1-based, so 0 means no chunks yet
+    pub fn max_chunk_id(&self) -> usize {
+        self.chunk_id_chunk_map.keys().copied().max().unwrap_or(0)
+    }
+
     pub fn get_chunks(&self, ids: Vec<usize>) -> Vec<Chunk> {
         ids.iter()
-            .filter_map(|id| {
+            .map(|id| {
                 let chunk_content = self.chunk_id_chunk_map.get(id).unwrap();
                 let search_id = self.chunk_id_to_search_id.get(id).unwrap();
+                let search_result = self.search_map.get(search_id).unwrap();
 
-                Some(Chunk {
+                Chunk {
                     content: chunk_content.to_string(),
-                    name: self.search_map.get(search_id).unwrap().name.clone(),
-                    url: self.search_map.get(search_id).unwrap().url.clone(),
-                })
+                    name: search_result.name.clone(),
+                    url: search_result.url.clone(),
+                    published: search_result.published,
+                }
             })
             .collect()
     }
@@ -0,0 +1,137 @@
+//! Publication dates of scraped pages, and how much recency counts.
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// When to favour recent sources.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Recency {
+    /// Only for queries with words like "latest" or a recent year
+    #[default]
+    Auto,
+    /// Like `Auto`, but asks the chat model about the remaining queries
+    /// before searching
+    Model,
+    /// For every query
+    Always,
+    /// Never
+    Off,
+}
+
+// meta tags carrying a publication or modification date, by attribute and value
+const META_DATES: &[(&str, &str)] = &[
+    ("property", "article:published_time"),
+    ("property", "article:modified_time"),
+    ("property", "og:updated_time"),
+    ("itemprop", "datePublished"),
+    ("itemprop", "dateModified"),
+    ("name", "date"),
+    ("name", "pubdate"),
+    ("name", "publishdate"),
+    ("name", "last-modified"),
+    ("name", "dc.date"),
+    ("name", "dc.date.issued"),
+];
+
+const JSON_LD_DATES: &[&str] = &["datePublished", "dateModified", "dateCreated"];
+
+// age at which a page gets half of the full recency boost
+const RECENCY_HALF_LIFE_DAYS: f64 = 90.0;
+const RECENCY_BOOST: f64 = 0.5;
+
+/// Most recent date a page declares in its meta tags or JSON-LD, falling back
+/// to the first `<time datetime>`. Dates in the future are ignored.
+pub fn published_date(html: &str) -> Option<NaiveDate> {
+    let document = Html::parse_document(html);
+    let today = Utc::now().date_naive();
+    let valid = |date: &NaiveDate| *date <= today;
+
+    let mut declared: Vec<NaiveDate> = META_DATES
+        .iter()
+        .flat_map(|(attribute, value)| meta_contents(&document, attribute, value))
+        .filter_map(|content| parse_date(&content))
+        .collect();
+
+    let json_ld = Selector::parse(r#"script[type="application/ld+json"]"#).unwrap();
+    for script in document.select(&json_ld) {
+        if let Ok(json) = serde_json::from_str::<Value>(&script.text().collect::<String>()) {
+            collect_json_ld_dates(&json, &mut declared);
+        }
+    }
+
+    if let Some(date) = declared.into_iter().filter(valid).max() {
+        return Some(date);
+    }
+
+    let time = Selector::parse("time[datetime]").unwrap();
+    document
+        .select(&time)
+        .filter_map(|element| parse_date(element.value().attr("datetime")?))
+        .find(valid)
+}
+
+// Meta tag names are matched case-insensitively, as publishers are inconsistent
+fn meta_contents(document: &Html, attribute: &str, value: &str) -> Vec<String> {
+    let meta = Selector::parse("meta[content]").unwrap();
+    document
+        .select(&meta)
+        .filter(|element| {
+            element
+                .value()
+                .attr(attribute)
+                .is_some_and(|v| v.eq_ignore_ascii_case(value))
+        })
+        .filter_map(|element| element.value().attr("content").map(str::to_string))
+        .collect()
+}
+
+fn collect_json_ld_dates(json: &Value, dates: &mut Vec<NaiveDate>) {
+    match json {
+        Value::Array(items) => items
+            .iter()
+            .for_each(|item| collect_json_ld_dates(item, dates)),
+        Value::Object(object) => {
+            for (key, value) in object {
+                if JSON_LD_DATES.contains(&key.as_str()) {
+                    dates.extend(value.as_str().and_then(parse_date));
+                } else {
+                    // `@graph` and nested entities such as the main article
+                    collect_json_ld_dates(value, dates);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Parses the date formats pages use: RFC 3339, RFC 2822 (HTTP headers),
+/// and plain `YYYY-MM-DD` or `YYYY/MM/DD`, optionally followed by a time.
+pub fn parse_date(text: &str) -> Option<NaiveDate> {
+    let text = text.trim();
+    if let Ok(date) = DateTime::parse_from_rfc3339(text) {
+        return Some(date.date_naive());
+    }
+    if let Ok(date) = DateTime::parse_from_rfc2822(text) {
+        return Some(date.date_naive());
+    }
+    if let Ok(date) = NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S") {
+        return Some(date.date());
+    }
+    let prefix = text.get(..10)?;
+    NaiveDate::parse_from_str(prefix, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(prefix, "%Y/%m/%d"))
+        .ok()
+}
+
+/// Retrieval score multiplier for a source published on `published`: up to
+/// 1.5 for today, decaying towards 1.0; undated sources get 1.0.
+pub fn recency_weight(published: Option<NaiveDate>, today: NaiveDate) -> f64 {
+    let Some(published) = published else {
+        return 1.0;
+    };
+    let age = (today - published).num_days().max(0) as f64;
+    1.0 + RECENCY_BOOST * 0.5_f64.powf(age / RECENCY_HALF_LIFE_DAYS)
+}
@@ -0,0 +1,127 @@
+//! Which domains results may come from, and how much each is trusted.
+//!
+//! The policy filters search results before they enter the request, and its
+//! weights boost (or sink) chunks from a domain during retrieval. A domain
+//! entry also covers its subdomains: `wikipedia.org` matches `en.wikipedia.org`.
+
+use anyhow::{anyhow, Result};
+use reqwest::Url;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+
+#[derive(Clone, Debug, Default)]
+pub struct DomainPolicy {
+    /// When non-empty, only results from these domains are kept
+    pub allow: Vec<String>,
+    /// Results from these domains are always dropped
+    pub deny: Vec<String>,
+    /// Retrieval score multiplier per domain; unlisted domains weigh 1.0
+    pub weights: HashMap<String, f64>,
+}
+
+impl DomainPolicy {
+    /// Reads FYIN_ALLOW_DOMAINS, FYIN_DENY_DOMAINS (comma separated),
+    /// FYIN_DENY_DOMAINS_FILE (one domain per line, `#` comments) and
+    /// FYIN_DOMAIN_WEIGHTS (`domain=weight` pairs, comma separated).
+    pub fn from_env() -> Result<Self> {
+        let mut policy = DomainPolicy::default();
+        if let Some(allow) = env_var("FYIN_ALLOW_DOMAINS") {
+            policy.allow = parse_list(&allow);
+        }
+        if let Some(deny) = env_var("FYIN_DENY_DOMAINS") {
+            policy.deny = parse_list(&deny);
+        }
+        if let Some(path) = env_var("FYIN_DENY_DOMAINS_FILE") {
+            let list = fs::read_to_string(&path)
+                .map_err(|e| anyhow!("Failed to read FYIN_DENY_DOMAINS_FILE {}: {}", path, e))?;
+            policy.deny.extend(
+                list.lines()
+                    .map(|line| line.split('#').next().unwrap_or_default().trim())
+                    .filter(|domain| !domain.is_empty())
+                    .map(normalize),
+            );
+        }
+        if let Some(weights) = env_var("FYIN_DOMAIN_WEIGHTS") {
+            for pair in weights.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+                let (domain, weight) = pair.split_once('=').ok_or_else(|| {
+                    anyhow!("Expected domain=weight in FYIN_DOMAIN_WEIGHTS: {}", pair)
+                })?;
+                let weight = weight
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid weight in FYIN_DOMAIN_WEIGHTS: {}", pair))?;
+                policy.weights.insert(normalize(domain), weight);
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Whether a result from `url` may enter the request.
+    pub fn is_allowed(&self, url: &str) -> bool {
+        let Some(host) = host(url) else {
+            return false;
+        };
+        if matches_any(&host, &self.deny) {
+            return false;
+        }
+        self.allow.is_empty() || matches_any(&host, &self.allow)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty() && self.weights.is_empty()
+    }
+
+    /// Weight of the most specific listed domain `url` belongs to.
+    pub fn weight(&self, url: &str) -> f64 {
+        let Some(host) = host(url) else {
+            return 1.0;
+        };
+        self.weights
+            .iter()
+            .filter(|(domain, _)| is_within(&host, domain))
+            .max_by_key(|(domain, _)| domain.len())
+            .map(|(_, weight)| *weight)
+            .unwrap_or(1.0)
+    }
+}
+
+/// Lowercased host of `url`, without a leading `www.`.
+pub fn host(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    Some(normalize(url.host_str()?))
+}
+
+/// True when `host` is `domain` or one of its subdomains.
+pub fn is_within(host: &str, domain: &str) -> bool {
+    host == domain
+        || host
+            .strip_suffix(domain)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+pub fn matches_any(host: &str, domains: &[String]) -> bool {
+    domains.iter().any(|domain| is_within(host, domain))
+}
+
+/// Accepts `example.com`, `www.example.com` or `https://example.com/path`.
+pub fn normalize(domain: &str) -> String {
+    let domain = domain.trim().to_lowercase();
+    let domain = domain
+        .split_once("://")
+        .map_or(domain.as_str(), |(_, rest)| rest);
+    let domain = domain.split(['/', ':']).next().unwrap_or_default();
+    domain.strip_prefix("www.").unwrap_or(domain).to_string()
+}
+
+fn parse_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(normalize)
+        .collect()
+}
+
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.trim().is_empty())
+}
@@ -1,93 +1,90 @@
-use crate::data::Request;
-use crate::llm;
-use crate::pretty_print;
-use crate::vector::VectorDB;
-
 use anyhow::Result;
-use futures::stream::{FuturesUnordered, StreamExt};
+use async_trait::async_trait;
+use futures::future::{BoxFuture, FutureExt};
 
-use std::sync::{Arc, Mutex};
-use tokio::sync;
-use tokio::task::JoinHandle;
+use std::sync::Arc;
+use tracing::Instrument;
 
 static CHUNK_SIZE: usize = 1000;
 
-async fn insert_embedding(
-    vector_client: Arc<sync::Mutex<VectorDB>>,
-    embedding: Vec<f64>,
-    // id: &Uuid,
-    id: usize,
-) -> Result<()> {
-    vector_client
-        .lock()
-        .await
-        .upsert_embedding(embedding, id)
-        .await?;
-    Ok(())
+/// Chunk stage: splits page content into pieces that get embedded separately.
+pub trait Chunker: Send + Sync {
+    fn chunk(&self, content: &str) -> Vec<String>;
 }
 
-pub async fn generate_upsert_embeddings(
-    request: Arc<Mutex<Request>>,
-    vector_client: Arc<sync::Mutex<VectorDB>>,
-) -> Result<()> {
-    let mut tasks: FuturesUnordered<JoinHandle<Result<()>>> = FuturesUnordered::new();
+/// Embed stage: maps text to a vector. Queries and chunks use the same embedder.
+#[async_trait]
+pub trait Embedder: Send + Sync {
+    async fn embed(&self, text: &str) -> Result<Vec<f64>>;
+}
 
-    // chunk the content into CHUNK_SIZE words
-    let search_map = request.lock().unwrap().search_map.clone();
-    let shared_counter = Arc::new(Mutex::new(0usize));
+// chunk the content into `size` words
+pub struct WordChunker {
+    pub size: usize,
+}
 
-    for (url_hash, result) in search_map.into_iter() {
-        let content = result.content.unwrap_or("".to_string()).clone();
+impl Default for WordChunker {
+    fn default() -> Self {
+        WordChunker { size: CHUNK_SIZE }
+    }
+}
 
-        let chunks = content.split_whitespace().collect::<Vec<&str>>();
-        let chunks = chunks
-            .chunks(CHUNK_SIZE)
+impl Chunker for WordChunker {
+    fn chunk(&self, content: &str) -> Vec<String> {
+        let words = content.split_whitespace().collect::<Vec<&str>>();
+        words
+            .chunks(self.size)
             .map(|chunk| chunk.join(" "))
-            .collect::<Vec<String>>();
-
-        log::info!(
-            "Chunked content into {} chunks for url: {}",
-            chunks.len(),
-            result.url
-        );
-        pretty_print::print_yellow(&format!("Generating embedding for url: {}", result.url));
-
-        // parallely process chunks and store it
-        for chunk in chunks.into_iter() {
-            let request_clone = request.clone();
-            let qdrant_client_clone = vector_client.clone();
-            let shared_counter_clone = shared_counter.clone();
-            let url_hash_clone = url_hash.clone();
-            tasks.push(tokio::spawn(async move {
-                let llm_agent = llm::LlmAgent::init().await;
-                let embedding = llm_agent.embed_string(&chunk).await.unwrap();
+            .collect::<Vec<String>>()
+    }
+}
 
-                // increment the counter
-                let map_index = {
-                    let mut counter = shared_counter_clone.lock().unwrap();
-                    *counter += 1;
-                    *counter
-                };
+pub struct EmbeddedChunk {
+    pub search_id: String,
+    pub url: String,
+    pub content: String,
+    pub embedding: Result<Vec<f64>>,
+}
 
-                // store chunks to id mapping
-                request_clone
-                    .lock()
-                    .unwrap()
-                    .add_id_to_chunk(&chunk, &url_hash_clone, map_index);
+/// Chunks a scraped page and returns one embedding future per chunk, so the
+/// chunks can be embedded in parallel with each other and with other pages.
+pub fn embed_page(
+    chunker: &dyn Chunker,
+    embedder: Arc<dyn Embedder>,
+    search_id: &str,
+    url: &str,
+    content: &str,
+) -> Vec<BoxFuture<'static, EmbeddedChunk>> {
+    let chunks = chunker.chunk(content);
 
-                insert_embedding(qdrant_client_clone, embedding, map_index)
-                    .await
-                    .unwrap();
-                Ok(())
-            }));
-        }
-    }
+    tracing::info!(
+        "Chunked content into {} chunks for url: {}",
+        chunks.len(),
+        url
+    );
 
-    while let Some(result) = tasks.next().await {
-        match result {
-            Ok(_r) => {}
-            Err(e) => return Err(anyhow::Error::new(e)),
-        }
-    }
-    Ok(())
+    chunks
+        .into_iter()
+        .map(|chunk| {
+            let embedder = embedder.clone();
+            let search_id = search_id.to_string();
+            let url = url.to_string();
+            let span = tracing::info_span!(
+                "embed",
+                url = %url,
+                words = chunk.split_whitespace().count()
+            );
+            async move {
+                let embedding = embedder.embed(&chunk).await;
+                EmbeddedChunk {
+                    search_id,
+                    url,
+                    content: chunk,
+                    embedding,
+                }
+            }
+            .instrument(span)
+            .boxed()
+        })
+        .collect()
 }
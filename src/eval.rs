@@ -0,0 +1,528 @@
+//! Answer-quality evaluation over a local QA dataset with frozen source snapshots.
+
+use crate::compare::escape_cell;
+use crate::data::{Chunk, SearchResult};
+use crate::embedding::WordChunker;
+use crate::llm::{ChatProvider, LlmAgent};
+use crate::pipeline::{Answer, Pipeline, PipelineBuilder};
+use crate::scraper::{self, Page, Scraper};
+use crate::search::{SearchOptions, SearchProvider};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Fraction of a sentence's content words that must appear in the retrieved
+// chunks for the sentence to count as grounded.
+const GROUNDED_OVERLAP: f64 = 0.5;
+
+#[derive(Serialize, Deserialize)]
+pub struct Dataset {
+    pub questions: Vec<EvalQuestion>,
+    #[serde(skip)]
+    base_dir: PathBuf,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EvalQuestion {
+    pub id: String,
+    pub question: String,
+    /// Reference answer used for exact match, F1 and the judge
+    pub answer: String,
+    /// Sources that actually contain the answer
+    pub relevant_urls: Vec<String>,
+    /// What the search stage returns for this question
+    pub sources: Vec<SnapshotSource>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SnapshotSource {
+    pub name: String,
+    pub url: String,
+    /// HTML file, relative to the dataset file
+    pub snapshot: String,
+}
+
+/// How a pipeline is configured for one side of a comparison.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct EvalConfig {
+    pub name: String,
+    pub chat_model: Option<String>,
+    pub embedding_model: Option<String>,
+    pub top_k: Option<usize>,
+    pub chunk_size: Option<usize>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Scores {
+    pub retrieval_recall: f64,
+    pub citation_accuracy: f64,
+    pub groundedness: f64,
+    pub exact_match: f64,
+    pub f1: f64,
+    /// Only set when a judge model is used
+    pub judge: Option<f64>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct QuestionResult {
+    pub id: String,
+    pub answer: String,
+    pub scores: Scores,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EvalRun {
+    pub config: String,
+    pub results: Vec<QuestionResult>,
+}
+
+impl Dataset {
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut dataset: Dataset = serde_json::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| anyhow!("Invalid dataset {:?}: {}", path, e))?;
+        dataset.base_dir = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+        Ok(dataset)
+    }
+
+    fn snapshot_path(&self, source: &SnapshotSource) -> PathBuf {
+        self.base_dir.join(&source.snapshot)
+    }
+}
+
+impl EvalConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut config: EvalConfig = serde_json::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| anyhow!("Invalid eval config {:?}: {}", path, e))?;
+        if config.name.is_empty() {
+            config.name = path
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default();
+        }
+        Ok(config)
+    }
+
+    /// A pipeline builder with this config's models, top-k and chunk size.
+    pub async fn builder(&self) -> PipelineBuilder {
+        let mut chat = LlmAgent::init().await;
+        if let Some(model) = &self.chat_model {
+            chat = chat.with_chat_model(model);
+        }
+        let mut embedder = LlmAgent::init().await;
+        if let Some(model) = &self.embedding_model {
+            embedder = embedder.with_embed_model(model);
+        }
+
+        let mut builder = Pipeline::builder().chat(chat).embedder(embedder);
+        if let Some(top_k) = self.top_k {
+            builder = builder.top_k(top_k);
+        }
+        if let Some(size) = self.chunk_size {
+            builder = builder.chunker(WordChunker { size });
+        }
+        builder
+    }
+}
+
+/// Search stage that returns each question's frozen sources.
+pub struct SnapshotSearch {
+    sources: HashMap<String, Vec<SearchResult>>,
+}
+
+#[async_trait]
+impl SearchProvider for SnapshotSearch {
+    async fn search(
+        &self,
+        query: &str,
+        count: usize,
+        _options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>> {
+        let mut results = self.sources.get(query).cloned().unwrap_or_default();
+        results.truncate(count);
+        Ok(results)
+    }
+}
+
+/// Scrape stage that reads snapshot files instead of fetching.
+pub struct SnapshotScraper {
+    snapshots: HashMap<String, PathBuf>,
+}
+
+#[async_trait]
+impl Scraper for SnapshotScraper {
+    async fn scrape(&self, url: &str) -> Result<Page> {
+        let path = self
+            .snapshots
+            .get(url)
+            .ok_or_else(|| anyhow!("No snapshot for {}", url))?;
+        scraper::parse_page(&fs::read_to_string(path)?)
+    }
+}
+
+fn snapshot_stages(dataset: &Dataset) -> (SnapshotSearch, SnapshotScraper) {
+    let mut sources = HashMap::new();
+    let mut snapshots = HashMap::new();
+    for question in &dataset.questions {
+        let results = question
+            .sources
+            .iter()
+            .map(|source| {
+                snapshots.insert(source.url.clone(), dataset.snapshot_path(source));
+                SearchResult {
+                    name: source.name.clone(),
+                    url: source.url.clone(),
+                    content: None,
+                    published: None,
+                    language: None,
+                }
+            })
+            .collect();
+        sources.insert(question.question.clone(), results);
+    }
+    (SnapshotSearch { sources }, SnapshotScraper { snapshots })
+}
+
+/// Runs every question through a pipeline whose search and scrape stages are
+/// replaced by the dataset's snapshots. The rest of `builder` is used as is.
+pub async fn run(
+    dataset: &Dataset,
+    config: &EvalConfig,
+    builder: PipelineBuilder,
+    judge: Option<&dyn ChatProvider>,
+) -> Result<EvalRun> {
+    let (search, scraper) = snapshot_stages(dataset);
+    let max_sources = dataset
+        .questions
+        .iter()
+        .map(|q| q.sources.len())
+        .max()
+        .unwrap_or(0);
+    let pipeline = builder
+        .search_provider(search)
+        .scraper(scraper)
+        .search_count(max_sources)
+        .build()
+        .await?;
+
+    let mut results = vec![];
+    for question in &dataset.questions {
+        tracing::info!("Evaluating {}: {}", question.id, question.question);
+        let answer = match pipeline.run(&question.question).await {
+            Ok(output) => output.collect().await,
+            Err(e) => Err(e),
+        };
+
+        // a failed judge call fails this question, not the run
+        let result = match answer {
+            Ok(answer) => match score(question, &answer, judge).await {
+                Ok(scores) => QuestionResult {
+                    id: question.id.clone(),
+                    scores,
+                    answer: answer.text,
+                    error: None,
+                },
+                Err(e) => QuestionResult {
+                    id: question.id.clone(),
+                    scores: Scores::default(),
+                    answer: answer.text,
+                    error: Some(format!("Judging failed: {}", e)),
+                },
+            },
+            Err(e) => QuestionResult {
+                id: question.id.clone(),
+                answer: String::new(),
+                scores: Scores::default(),
+                error: Some(e.to_string()),
+            },
+        };
+        results.push(result);
+    }
+
+    Ok(EvalRun {
+        config: config.name.clone(),
+        results,
+    })
+}
+
+pub async fn score(
+    question: &EvalQuestion,
+    answer: &Answer,
+    judge: Option<&dyn ChatProvider>,
+) -> Result<Scores> {
+    let judge = match judge {
+        Some(judge) => Some(judge_answer(judge, question, &answer.text).await?),
+        None => None,
+    };
+
+    Ok(Scores {
+        retrieval_recall: retrieval_recall(&question.relevant_urls, &answer.chunks),
+        citation_accuracy: citation_accuracy(&answer.text, &question.relevant_urls, &answer.chunks),
+        groundedness: groundedness(&answer.text, &answer.chunks),
+        exact_match: exact_match(&answer.text, &question.answer),
+        f1: f1(&answer.text, &question.answer),
+        judge,
+    })
+}
+
+fn normalize(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn tokens(text: &str) -> Vec<String> {
+    normalize(text)
+        .split(' ')
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_string())
+        .collect()
+}
+
+/// Share of relevant sources that made it into the retrieved chunks.
+pub fn retrieval_recall(relevant_urls: &[String], chunks: &[Chunk]) -> f64 {
+    if relevant_urls.is_empty() {
+        return 1.0;
+    }
+    let retrieved: HashSet<&str> = chunks.iter().map(|c| c.url.as_str()).collect();
+    let found = relevant_urls
+        .iter()
+        .filter(|url| retrieved.contains(url.as_str()))
+        .count();
+    found as f64 / relevant_urls.len() as f64
+}
+
+/// Citation numbers (`[n]`, `[n, m]`) found in an answer, in order.
+pub fn citations(text: &str) -> Vec<usize> {
+    let re = Regex::new(r"\[(\d+(?:\s*,\s*\d+)*)\]").unwrap();
+    re.captures_iter(text)
+        .flat_map(|cap| {
+            cap[1]
+                .split(',')
+                .filter_map(|n| n.trim().parse().ok())
+                .collect::<Vec<usize>>()
+        })
+        .collect()
+}
+
+/// Share of citations that point at a retrieved chunk from a relevant source.
+pub fn citation_accuracy(text: &str, relevant_urls: &[String], chunks: &[Chunk]) -> f64 {
+    let cited = citations(text);
+    if cited.is_empty() {
+        return 0.0;
+    }
+    let correct = cited
+        .iter()
+        .filter(|&&n| {
+            n >= 1
+                && chunks
+                    .get(n - 1)
+                    .is_some_and(|chunk| relevant_urls.contains(&chunk.url))
+        })
+        .count();
+    correct as f64 / cited.len() as f64
+}
+
+/// Share of answer sentences whose content words mostly appear in the sources.
+pub fn groundedness(text: &str, chunks: &[Chunk]) -> f64 {
+    let source_words: HashSet<String> = chunks.iter().flat_map(|c| tokens(&c.content)).collect();
+    let sentences: Vec<Vec<String>> = text
+        .split(['.', '!', '?', '\n'])
+        .map(|sentence| {
+            tokens(sentence)
+                .into_iter()
+                .filter(|t| t.len() > 3 && t.parse::<usize>().is_err())
+                .collect::<Vec<_>>()
+        })
+        .filter(|words| !words.is_empty())
+        .collect();
+    if sentences.is_empty() {
+        return 0.0;
+    }
+
+    let grounded = sentences
+        .iter()
+        .filter(|words| {
+            let supported = words.iter().filter(|w| source_words.contains(*w)).count();
+            supported as f64 / words.len() as f64 >= GROUNDED_OVERLAP
+        })
+        .count();
+    grounded as f64 / sentences.len() as f64
+}
+
+/// 1.0 when the normalized reference answer appears in the generated answer.
+pub fn exact_match(text: &str, expected: &str) -> f64 {
+    let expected = normalize(expected);
+    if !expected.is_empty() && normalize(text).contains(&expected) {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// Token-level F1 between the generated and the reference answer.
+pub fn f1(text: &str, expected: &str) -> f64 {
+    let predicted = tokens(text);
+    let expected = tokens(expected);
+    if predicted.is_empty() || expected.is_empty() {
+        return 0.0;
+    }
+
+    let mut remaining: HashMap<&str, usize> = HashMap::new();
+    for token in &expected {
+        *remaining.entry(token).or_default() += 1;
+    }
+    let mut common = 0;
+    for token in &predicted {
+        if let Some(count) = remaining.get_mut(token.as_str()) {
+            if *count > 0 {
+                *count -= 1;
+                common += 1;
+            }
+        }
+    }
+    if common == 0 {
+        return 0.0;
+    }
+
+    let precision = common as f64 / predicted.len() as f64;
+    let recall = common as f64 / expected.len() as f64;
+    2.0 * precision * recall / (precision + recall)
+}
+
+async fn judge_answer(
+    judge: &dyn ChatProvider,
+    question: &EvalQuestion,
+    answer: &str,
+) -> Result<f64> {
+    let prompt = format!(
+        "You are grading an answer to a question against a reference answer.
+
+QUESTION:
+{question}
+
+REFERENCE ANSWER:
+{reference}
+
+ANSWER TO GRADE:
+{answer}
+
+Reply with exactly one word: CORRECT if the answer agrees with the reference answer, otherwise INCORRECT.",
+        question = question.question,
+        reference = question.answer,
+        answer = answer
+    );
+    let verdict = judge.complete(&prompt).await?.to_uppercase();
+    Ok(if verdict.contains("INCORRECT") {
+        0.0
+    } else if verdict.contains("CORRECT") {
+        1.0
+    } else {
+        tracing::warn!("Judge gave no verdict for {}: {}", question.id, verdict);
+        0.0
+    })
+}
+
+impl EvalRun {
+    /// Mean of every metric over all questions.
+    pub fn summary(&self) -> Scores {
+        let n = self.results.len().max(1) as f64;
+        let mean =
+            |f: &dyn Fn(&Scores) -> f64| self.results.iter().map(|r| f(&r.scores)).sum::<f64>() / n;
+        let judged: Vec<f64> = self.results.iter().filter_map(|r| r.scores.judge).collect();
+
+        Scores {
+            retrieval_recall: mean(&|s| s.retrieval_recall),
+            citation_accuracy: mean(&|s| s.citation_accuracy),
+            groundedness: mean(&|s| s.groundedness),
+            exact_match: mean(&|s| s.exact_match),
+            f1: mean(&|s| s.f1),
+            judge: (!judged.is_empty()).then(|| judged.iter().sum::<f64>() / judged.len() as f64),
+        }
+    }
+}
+
+fn metric_rows(scores: &Scores) -> Vec<(&'static str, Option<f64>)> {
+    vec![
+        ("Retrieval recall", Some(scores.retrieval_recall)),
+        ("Citation accuracy", Some(scores.citation_accuracy)),
+        ("Groundedness", Some(scores.groundedness)),
+        ("Exact match", Some(scores.exact_match)),
+        ("F1", Some(scores.f1)),
+        ("Judge", scores.judge),
+    ]
+}
+
+fn cell(value: Option<f64>) -> String {
+    value.map_or("-".to_string(), |v| format!("{:.3}", v))
+}
+
+/// Markdown report of one run, or of a candidate run against a baseline.
+pub fn report(baseline: &EvalRun, candidate: Option<&EvalRun>) -> String {
+    let mut out = String::from("# fyin eval report\n\n## Summary\n\n");
+    let base = metric_rows(&baseline.summary());
+
+    match candidate {
+        Some(candidate) => {
+            out.push_str(&format!(
+                "| Metric | {} | {} | Δ |\n|---|---|---|---|\n",
+                escape_cell(&baseline.config),
+                escape_cell(&candidate.config)
+            ));
+            for ((name, a), (_, b)) in base.iter().zip(metric_rows(&candidate.summary())) {
+                let delta = match (a, b) {
+                    (Some(a), Some(b)) => format!("{:+.3}", b - a),
+                    _ => "-".to_string(),
+                };
+                out.push_str(&format!(
+                    "| {} | {} | {} | {} |\n",
+                    name,
+                    cell(*a),
+                    cell(b),
+                    delta
+                ));
+            }
+        }
+        None => {
+            out.push_str(&format!(
+                "| Metric | {} |\n|---|---|\n",
+                escape_cell(&baseline.config)
+            ));
+            for (name, value) in &base {
+                out.push_str(&format!("| {} | {} |\n", name, cell(*value)));
+            }
+        }
+    }
+
+    out.push_str("\n## Questions\n\n| Question | Config | Recall | Citations | Grounded | EM | F1 | Judge | Error |\n|---|---|---|---|---|---|---|---|---|\n");
+    let runs: Vec<&EvalRun> = std::iter::once(baseline).chain(candidate).collect();
+    for (i, result) in baseline.results.iter().enumerate() {
+        for run in &runs {
+            let Some(r) = run.results.get(i) else {
+                continue;
+            };
+            out.push_str(&format!(
+                "| {} | {} | {:.2} | {:.2} | {:.2} | {:.0} | {:.2} | {} | {} |\n",
+                escape_cell(&result.id),
+                escape_cell(&run.config),
+                r.scores.retrieval_recall,
+                r.scores.citation_accuracy,
+                r.scores.groundedness,
+                r.scores.exact_match,
+                r.scores.f1,
+                cell(r.scores.judge),
+                escape_cell(r.error.as_deref().unwrap_or(""))
+            ));
+        }
+    }
+    out
+}
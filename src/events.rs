@@ -0,0 +1,84 @@
+use crate::data::Chunk;
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+/// Progress of a pipeline run, in the order the stages produce them.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PipelineEvent {
+    SearchStarted {
+        query: String,
+    },
+    ResultFound {
+        name: String,
+        url: String,
+    },
+    PageScraped {
+        url: String,
+        bytes: usize,
+    },
+    PageFailed {
+        url: String,
+        error: String,
+    },
+    ChunkEmbedded {
+        url: String,
+        chunk_id: usize,
+    },
+    /// A retrieved chunk reads like instructions to the model; `patterns` are
+    /// the labels of what matched
+    InjectionDetected {
+        url: String,
+        patterns: Vec<String>,
+    },
+    /// Chunks in citation order: `chunks[0]` is `[1]` in the answer
+    RetrievalDone {
+        chunks: Vec<Chunk>,
+    },
+    /// The chat model called tool `name` while answering
+    ToolCalled {
+        name: String,
+        arguments: serde_json::Value,
+        result: String,
+    },
+    /// Deep research: round `round` searches for `questions`
+    ResearchRound {
+        round: usize,
+        questions: Vec<String>,
+    },
+    /// Summary mode: section `section` of `total` was condensed
+    SectionSummarized {
+        url: String,
+        section: usize,
+        total: usize,
+    },
+    Token {
+        text: String,
+    },
+    Done,
+}
+
+pub type EventSender = mpsc::UnboundedSender<PipelineEvent>;
+
+/// Sending half handed to each stage; a no-op when nobody is listening.
+#[derive(Clone, Default)]
+pub struct Events(Option<EventSender>);
+
+impl Events {
+    pub fn new(sender: EventSender) -> Self {
+        Events(Some(sender))
+    }
+
+    pub fn send(&self, event: PipelineEvent) {
+        if let Some(sender) = &self.0 {
+            // the receiver going away must not fail the run
+            let _ = sender.send(event);
+        }
+    }
+}
+
+impl From<Option<EventSender>> for Events {
+    fn from(sender: Option<EventSender>) -> Self {
+        Events(sender)
+    }
+}
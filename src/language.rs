@@ -0,0 +1,29 @@
+//! Language detection for queries and pages, so answers come back in the
+//! asker's language while sources can be in any language.
+
+use whatlang::Lang;
+
+/// ISO 639-3 code of English, the language queries are translated into.
+pub const ENGLISH: &str = "eng";
+
+// Short queries are often misread as a related language; below this
+// confidence the language counts as unknown
+const MIN_CONFIDENCE: f64 = 0.3;
+// pages are long, and their first part tells the language well enough
+const SAMPLE_CHARS: usize = 2000;
+
+/// ISO 639-3 code of `text`'s language (`deu`, `jpn`, ...), when it can be
+/// told with some confidence.
+pub fn detect(text: &str) -> Option<&'static str> {
+    let sample = match text.char_indices().nth(SAMPLE_CHARS) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    };
+    let info = whatlang::detect(sample)?;
+    (info.is_reliable() || info.confidence() >= MIN_CONFIDENCE).then(|| info.lang().code())
+}
+
+/// English name of a language code from `detect`, e.g. `deu` is German.
+pub fn name(code: &str) -> Option<&'static str> {
+    Lang::from_code(code).map(Lang::eng_name)
+}
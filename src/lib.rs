@@ -0,0 +1,41 @@
+//! fyin - Open source alternative to Perplexity AI.
+//!
+//! The [`Pipeline`] runs search → scrape → chunk → embed → retrieve → answer.
+//! Each stage is a trait ([`SearchProvider`], [`Scraper`], [`Chunker`],
+//! [`Embedder`], [`Retriever`], [`ChatProvider`]) so callers can swap any of them.
+
+pub mod check;
+pub mod collection;
+pub mod compare;
+pub mod data;
+pub mod dates;
+pub mod domains;
+pub mod embedding;
+pub mod eval;
+pub mod events;
+pub mod language;
+pub mod llm;
+pub mod mcp;
+pub mod pipeline;
+pub mod pretty_print;
+pub mod research;
+pub mod sanitize;
+pub mod scraper;
+pub mod search;
+pub mod server;
+pub mod summarize;
+pub mod telemetry;
+pub mod templates;
+pub mod tools;
+pub mod url_policy;
+pub mod vector;
+
+pub use collection::Collection;
+pub use data::{Chunk, Request, SearchResult};
+pub use embedding::{Chunker, Embedder};
+pub use events::{EventSender, PipelineEvent};
+pub use llm::{ChatProvider, TokenStream};
+pub use pipeline::{Answer, Pipeline, PipelineBuilder, PipelineOutput, RunOptions};
+pub use scraper::Scraper;
+pub use search::SearchProvider;
+pub use vector::Retriever;
@@ -1,111 +1,509 @@
-#![allow(dead_code)]
-
-#[macro_use]
-extern crate lazy_static;
-
 mod args;
-mod data;
-mod embedding;
-mod llm;
-mod pretty_print;
-mod scraper;
-mod vector;
-mod search;
-
-use anyhow::Result;
+mod progress;
+
+use anyhow::{anyhow, Result};
 use clap::Parser;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc;
 
-use std::sync::Arc;
-use tokio::sync;
+use fyin::check::{Evidence, FactCheck, Verdict};
+use fyin::collection::Collection;
+use fyin::eval::{self, Dataset, EvalConfig};
+use fyin::llm::{ChatProvider, LlmAgent};
+use fyin::research::Budget;
+use fyin::search::{self, SearchOptions};
+use fyin::telemetry::{JsonTrace, Timings};
+use fyin::templates::Template;
+use fyin::{domains, Answer, EventSender, Pipeline, PipelineBuilder, RunOptions, SearchResult};
+use fyin::{llm, mcp, pretty_print, scraper, server};
 
-use dotenv;
 use std::env;
+use std::fs;
+use std::future::Future;
+use std::io::Write;
+use std::path::Path;
+use tracing::Level;
+use tracing_subscriber::filter::{EnvFilter, LevelFilter, Targets};
+use tracing_subscriber::prelude::*;
+
+const DEFAULT_SUMMARY_FOCUS: &str = "Summarize the documents.";
 
 async fn init() -> Result<()> {
     // load ENV variables
     dotenv::dotenv().ok();
 
-    // verify required ones are present
-    let env_vars = [
-        "OPENAI_API_KEY",
-        "BING_SUBSCRIPTION_KEY",
-        "EMBEDDING_MODEL_NAME",
-        "CHAT_MODEL_NAME",
-    ];
+    // verify required ones are present; local Ollama and SearXNG need no keys
+    let mut env_vars = vec!["EMBEDDING_MODEL_NAME", "CHAT_MODEL_NAME"];
+    if !llm::is_local_mode() {
+        env_vars.push("OPENAI_API_KEY");
+    }
+    if search::uses_bing() {
+        env_vars.push("BING_SUBSCRIPTION_KEY");
+    }
 
-    for &var_name in &env_vars {
-        assert!(
-            !env::var(var_name)
-                .expect(&format!("Failed to retrieve '{}'", var_name))
-                .is_empty(),
-            "The environment variable '{}' must be set and not empty.",
-            var_name
-        );
+    for var_name in env_vars {
+        if env::var(var_name).unwrap_or_default().is_empty() {
+            return Err(anyhow!(
+                "The environment variable '{}' must be set and not empty.",
+                var_name
+            ));
+        }
     }
+    Ok(())
+}
 
-    pretty_env_logger::init();
+// Logs go to stderr filtered by RUST_LOG (errors only by default); `--timings`
+// and `--trace` see this crate's spans regardless of RUST_LOG.
+fn init_tracing(timings: Option<Timings>, trace: Option<&Path>) -> Result<()> {
+    let spans = Targets::new().with_target("fyin", Level::INFO);
+    let trace = trace.map(JsonTrace::create).transpose()?;
 
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(std::io::stderr)
+                .with_filter(
+                    EnvFilter::builder()
+                        .with_default_directive(LevelFilter::ERROR.into())
+                        .from_env_lossy(),
+                ),
+        )
+        .with(timings.map(|timings| timings.with_filter(spans.clone())))
+        .with(trace.map(|trace| trace.with_filter(spans)))
+        .init();
     Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     init().await?;
-    let args = args::Args::parse();
+    let mut args = args::Args::parse();
+    let timings = args.pipeline.timings.then(Timings::default);
+    init_tracing(timings.clone(), args.pipeline.trace.as_deref())?;
+
+    match args.command.take() {
+        Some(args::Command::Collections { action }) => collections(action)?,
+        Some(args::Command::Eval {
+            dataset,
+            baseline,
+            candidate,
+            judge_model,
+            report,
+        }) => {
+            run_eval(
+                &dataset,
+                baseline.as_deref(),
+                candidate.as_deref(),
+                judge_model.as_deref(),
+                &report,
+            )
+            .await?
+        }
+        Some(args::Command::Check { claim, json }) => {
+            check(&claim, builder(&args.pipeline)?, json).await?;
+            print_timings(timings);
+        }
+        Some(args::Command::Serve { addr }) => serve(&addr, builder(&args.pipeline)?).await?,
+        Some(args::Command::Mcp) => {
+            // stdout carries the protocol, so nothing else may print to it
+            let pipeline = builder(&args.pipeline)?.build().await?;
+            let stdin = tokio::io::BufReader::new(tokio::io::stdin());
+            mcp::serve(&pipeline, stdin, tokio::io::stdout()).await?
+        }
+        None => {
+            let query = args.query.take();
+            if args.deep && args.mode != args::Mode::Answer {
+                return Err(anyhow!("--deep only works with --mode answer"));
+            }
+            let builder = builder(&args.pipeline)?;
+            match args.mode {
+                args::Mode::Answer => {
+                    let query = query.ok_or_else(|| {
+                        anyhow!("--query is required unless a subcommand is given")
+                    })?;
+                    if args.deep {
+                        research(&query, builder, &args).await?;
+                    } else {
+                        let documents = documents(&args).await?;
+                        prompt(&query, documents, builder, &args).await?;
+                    }
+                }
+                args::Mode::Summarize => {
+                    let documents = documents(&args).await?;
+                    if query.is_none() && documents.is_empty() {
+                        return Err(anyhow!("--mode summarize needs --url, --file or --query"));
+                    }
+                    let focus = query.as_deref().unwrap_or(DEFAULT_SUMMARY_FOCUS);
+                    summarize(focus, documents, builder).await?;
+                }
+                args::Mode::Compare => {
+                    let query = query.ok_or_else(|| anyhow!("--mode compare needs --query"))?;
+                    // every entity gets its own search, so there is no place for given pages
+                    if !args.urls.is_empty() || !args.files.is_empty() {
+                        return Err(anyhow!("--mode compare searches for each entity itself; it can't use --url or --file"));
+                    }
+                    compare(&query, builder, &args).await?;
+                }
+            }
 
-    prompt(&args.query, args.search).await?;
+            print_timings(timings);
+        }
+    }
     Ok(())
 }
 
-async fn prompt(prompt: &str, search_count: usize) -> Result<()> {
-    pretty_print::print_blue(&format!("Searching for: {}", prompt));
-    let request = data::Request::init(prompt);
-    let llm_agent = llm::LlmAgent::init().await;
+fn print_timings(timings: Option<Timings>) {
+    if let Some(timings) = timings {
+        println!();
+        pretty_print::print_blue(&timings.table());
+    }
+}
 
-    // do a test embed and figure out dimension
+// Search settings from the environment, overridden by command line flags.
+fn search_options(args: &args::PipelineArgs) -> Result<SearchOptions> {
+    let mut options = SearchOptions::from_env()?;
+    options.sites = args.sites.iter().map(|s| domains::normalize(s)).collect();
+    options.exclude_sites = args
+        .exclude_sites
+        .iter()
+        .map(|s| domains::normalize(s))
+        .collect();
+    if args.market.is_some() {
+        options.market = args.market.clone();
+    }
+    if args.time_range.is_some() {
+        options.time_range = args.time_range;
+    }
+    if args.safe_search.is_some() {
+        options.safe_search = args.safe_search;
+    }
+    options.offset = args.offset;
+    Ok(options)
+}
 
-    let dimension = llm_agent.embed_string(prompt).await.unwrap().len();
+fn collections(action: args::CollectionAction) -> Result<()> {
+    match action {
+        args::CollectionAction::List => {
+            let infos = Collection::list()?;
+            if infos.is_empty() {
+                pretty_print::print_yellow("No collections found");
+            }
+            for info in infos {
+                println!(
+                    "{}: {} sources, {} chunks, {} queries",
+                    info.name, info.sources, info.chunks, info.queries
+                );
+            }
+        }
+        args::CollectionAction::Delete { name } => {
+            Collection::delete(&name)?;
+            pretty_print::print_green(&format!("Deleted collection: {}", name));
+        }
+    }
+    Ok(())
+}
 
-    // create a new vector client
-    let vector_client = Arc::new(sync::Mutex::new(
-        vector::VectorDB::init(Some(dimension)).await?,
-    ));
+fn eval_config(path: Option<&Path>, default_name: &str) -> Result<EvalConfig> {
+    match path {
+        Some(path) => EvalConfig::load(path),
+        None => Ok(EvalConfig {
+            name: default_name.to_string(),
+            ..Default::default()
+        }),
+    }
+}
 
-    // fetch search results
-    pretty_print::print_blue("Fetching search results from bing...");
-    search::fetch_web_pages(request.clone(), search_count).await?;
+async fn run_eval(
+    dataset: &Path,
+    baseline: Option<&Path>,
+    candidate: Option<&Path>,
+    judge_model: Option<&str>,
+    report: &Path,
+) -> Result<()> {
+    let dataset = Dataset::load(dataset)?;
+    let judge = match judge_model {
+        Some(model) => Some(LlmAgent::init().await.with_chat_model(model)),
+        None => None,
+    };
+    let judge = judge.as_ref().map(|judge| judge as &dyn ChatProvider);
 
-    // scrape content
-    pretty_print::print_blue("Scraping content from search results...");
-    scraper::process_urls(request.clone()).await?;
+    let mut configs = vec![eval_config(baseline, "baseline")?];
+    if let Some(candidate) = candidate {
+        configs.push(eval_config(Some(candidate), "candidate")?);
+    }
 
-    // do embedding on all the scrapped contents.
-    // store in vector DB
-    pretty_print::print_blue("Embedding content...");
-    embedding::generate_upsert_embeddings(request.clone(), vector_client.clone()).await?;
+    let mut runs = vec![];
+    for config in configs {
+        pretty_print::print_blue(&format!(
+            "Evaluating {} questions with config '{}'",
+            dataset.questions.len(),
+            config.name
+        ));
+        runs.push(eval::run(&dataset, &config, config.builder().await, judge).await?);
+    }
 
-    // convert prompt to embedding
-    let prompt_embedding = llm_agent.embed_string(prompt).await?;
+    let report_text = eval::report(&runs[0], runs.get(1));
+    fs::write(report, &report_text)?;
+    println!("{}", report_text);
+    pretty_print::print_green(&format!("Wrote report to {}", report.display()));
+    Ok(())
+}
 
-    // build vector index
-    vector_client.lock().await.build_index().await?;
+fn print_backend() {
+    if llm::is_local_mode() {
+        pretty_print::print_yellow("Running in local mode using ollama");
+    } else {
+        pretty_print::print_yellow("Running using openai");
+    }
+}
 
-    // search across embedding
-    // and get all embedding ids
-    let ids = vector_client
-        .lock()
-        .await
-        .search(&prompt_embedding, 10)
-        .await?;
+// Pipeline settings shared by one-off queries and the server
+fn builder(args: &args::PipelineArgs) -> Result<PipelineBuilder> {
+    let mut builder = Pipeline::builder()
+        .search_count(args.search)
+        .injection_policy(args.injection)
+        .recency(args.recency)
+        .translate_query(args.translate)
+        .tools(args.tools)
+        .search_options(search_options(args)?);
+    if let Some(min_chunks) = args.min_chunks {
+        builder = builder.min_chunks(min_chunks);
+    }
+    if let Some(deadline) = args.deadline {
+        builder = builder.deadline(deadline);
+    }
+    if let Some(source) = args.source {
+        builder = builder.source(source);
+    }
+    if let Some(style) = &args.style {
+        builder = builder.template(Template::find(style)?);
+    }
+    Ok(builder)
+}
 
-    // get content
-    let chunks: Vec<data::Chunk> = request.lock().unwrap().get_chunks(ids);
+async fn serve(addr: &str, builder: PipelineBuilder) -> Result<()> {
+    print_backend();
+    let pipeline = builder.build().await?;
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    let addr = listener.local_addr()?;
+    pretty_print::print_blue(&format!("Web UI on http://{}", addr));
+    pretty_print::print_blue(&format!("Chat completions API on http://{}/v1", addr));
+    server::serve(listener, pipeline).await
+}
 
-    let llm_agent = llm::LlmAgent::init().await;
-    llm_agent.answer_question_stream(prompt, &chunks).await?;
+async fn prompt(
+    prompt: &str,
+    documents: Vec<SearchResult>,
+    builder: PipelineBuilder,
+    args: &args::Args,
+) -> Result<()> {
+    print_backend();
+    let pipeline = builder.build().await?;
+
+    let mut collection = args
+        .collection
+        .as_deref()
+        .map(Collection::open)
+        .transpose()?;
+    if let Some(collection) = collection.as_ref() {
+        pretty_print::print_yellow(&format!(
+            "Using collection '{}' ({} chunks)",
+            collection.name,
+            collection.store.chunk_id_chunk_map.len()
+        ));
+    }
+
+    let mut stdin = BufReader::new(tokio::io::stdin()).lines();
+    let mut query = prompt.to_string();
+    loop {
+        let questions =
+            match answer(&pipeline, &query, documents.clone(), collection.as_mut()).await {
+                Ok(answer) if !args.no_follow_ups => pipeline.follow_ups(&answer).await,
+                Ok(_) => vec![],
+                // one failed question should not end the conversation
+                Err(e) if args.chat => {
+                    pretty_print::print_red(&format!("Error: {:#}", e));
+                    vec![]
+                }
+                Err(e) => return Err(e),
+            };
+        if !questions.is_empty() {
+            pretty_print::print_blue("Related questions:");
+            for (i, question) in questions.iter().enumerate() {
+                pretty_print::print_yellow(&format!("  {}. {}", i + 1, question));
+            }
+        }
+        if !args.chat {
+            return Ok(());
+        }
+
+        print!("\nAsk a follow-up (number or question, empty to quit): ");
+        std::io::stdout().flush()?;
+        let line = stdin.next_line().await?.unwrap_or_default();
+        let line = line.trim();
+        query = match line.parse::<usize>() {
+            _ if line.is_empty() => return Ok(()),
+            Ok(n) if (1..=questions.len()).contains(&n) => questions[n - 1].clone(),
+            _ => line.to_string(),
+        };
+        println!();
+    }
+}
+
+// Runs one query, printing progress and the answer as they arrive
+async fn answer(
+    pipeline: &Pipeline,
+    query: &str,
+    documents: Vec<SearchResult>,
+    collection: Option<&mut Collection>,
+) -> Result<Answer> {
+    // the renderer prints the answer from Token events, so just drain it here
+    with_progress(false, |events| async move {
+        let options = RunOptions {
+            events,
+            collection,
+            documents,
+        };
+        pipeline.run_with(query, options).await?.collect().await
+    })
+    .await
+}
+
+// Runs `run` with an event sender whose events are printed as progress, and
+// waits for the printing to finish. `quiet` runs it without one, keeping
+// stdout clean for JSON output.
+async fn with_progress<T, F, Fut>(quiet: bool, run: F) -> Result<T>
+where
+    F: FnOnce(Option<EventSender>) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    if quiet {
+        return run(None).await;
+    }
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let renderer = tokio::spawn(progress::render(receiver));
+    let result = run(Some(sender)).await;
+    renderer.await?;
+    result
+}
+
+// `--url` pages are scraped by the pipeline; `--file`s are read here.
+async fn documents(args: &args::Args) -> Result<Vec<SearchResult>> {
+    let mut documents: Vec<SearchResult> = args
+        .urls
+        .iter()
+        .map(|url| SearchResult {
+            name: url.clone(),
+            url: url.clone(),
+            content: None,
+            published: None,
+            language: None,
+        })
+        .collect();
+    for file in &args.files {
+        documents.push(scraper::read_file(file).await?);
+    }
+    Ok(documents)
+}
+
+async fn summarize(
+    focus: &str,
+    documents: Vec<SearchResult>,
+    builder: PipelineBuilder,
+) -> Result<()> {
+    print_backend();
+    let pipeline = builder.build().await?;
+
+    with_progress(false, |events| async move {
+        pipeline
+            .summarize(focus, documents, events)
+            .await?
+            .collect()
+            .await?;
+        Ok(())
+    })
+    .await
+}
+
+async fn compare(query: &str, builder: PipelineBuilder, args: &args::Args) -> Result<()> {
+    if !args.json {
+        print_backend();
+    }
+    let pipeline = builder.build().await?;
+    let comparison = with_progress(args.json, |events| {
+        pipeline.compare(
+            query,
+            args.entities.clone(),
+            args.dimensions.clone(),
+            events,
+        )
+    })
+    .await?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&comparison)?);
+    } else {
+        println!("{}", comparison.to_markdown());
+    }
+    Ok(())
+}
+
+async fn check(claim: &str, builder: PipelineBuilder, json: bool) -> Result<()> {
+    if !json {
+        print_backend();
+    }
+    let pipeline = builder.build().await?;
+    let check = with_progress(json, |events| pipeline.check(claim, events)).await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&check)?);
+        return Ok(());
+    }
+    let verdict = format!("Verdict: {}", check.verdict.label());
+    match check.verdict {
+        Verdict::Supported => pretty_print::print_green(&verdict),
+        Verdict::Refuted => pretty_print::print_red(&verdict),
+        Verdict::Mixed | Verdict::InsufficientEvidence => pretty_print::print_yellow(&verdict),
+    }
+    println!("{}", check.explanation);
+    print_evidence("Supporting evidence:", &check.supporting, &check);
+    print_evidence("Refuting evidence:", &check.refuting, &check);
+    Ok(())
+}
+
+fn print_evidence(heading: &str, evidence: &[Evidence], check: &FactCheck) {
+    if evidence.is_empty() {
+        return;
+    }
+    println!();
+    pretty_print::print_blue(heading);
+    for evidence in evidence {
+        let chunk = &check.chunks[evidence.source - 1];
+        pretty_print::print_yellow(&format!(
+            "  [{}] {} - {}",
+            evidence.source, chunk.name, chunk.url
+        ));
+        println!("      {}", evidence.reason);
+    }
+}
 
-    //clean-up vector DB
-    vector_client.lock().await.clean_up().await?;
+async fn research(query: &str, builder: PipelineBuilder, args: &args::Args) -> Result<()> {
+    let defaults = Budget::default();
+    let budget = Budget {
+        rounds: args.rounds.unwrap_or(defaults.rounds),
+        pages: args.max_pages.unwrap_or(defaults.pages),
+        tokens: args.max_tokens.unwrap_or(defaults.tokens),
+        time: args.max_time.unwrap_or(defaults.time),
+    };
 
+    print_backend();
+    let pipeline = builder.build().await?;
+    let trace = with_progress(false, |events| async move {
+        let research = pipeline.research(query, budget, events).await?;
+        research.output.collect().await?;
+        Ok(research.trace)
+    })
+    .await?;
+    println!();
+    pretty_print::print_blue("Research trace:");
+    pretty_print::print_blue(&trace.to_text());
     Ok(())
 }
@@ -0,0 +1,194 @@
+//! Model Context Protocol server over stdio, so other assistants can use fyin
+//! as a research tool.
+//!
+//! Messages are newline-delimited JSON-RPC 2.0. The tools are `web_search`
+//! (search results, not scraped), `fetch_page` (a page's extracted text),
+//! `ask` (a full answer with citations) and `search_collection` (the closest
+//! chunks of a persistent collection).
+
+use crate::collection::Collection;
+use crate::data::SearchResult;
+use crate::pipeline::Pipeline;
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+const PROTOCOL_VERSION: &str = "2024-11-05";
+
+// A search result as `web_search` returns it. Unlike `SearchResult`, it keeps
+// the text that sources like Wikipedia and arXiv send with the result.
+#[derive(Serialize)]
+struct SearchHit<'a> {
+    name: &'a str,
+    url: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    published: Option<NaiveDate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<&'a str>,
+}
+
+impl<'a> From<&'a SearchResult> for SearchHit<'a> {
+    fn from(result: &'a SearchResult) -> Self {
+        SearchHit {
+            name: &result.name,
+            url: &result.url,
+            published: result.published,
+            content: result.content.as_deref(),
+        }
+    }
+}
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// Answers requests from `input` on `output` until `input` is closed.
+pub async fn serve(
+    pipeline: &Pipeline,
+    input: impl AsyncBufRead + Unpin,
+    mut output: impl AsyncWrite + Unpin,
+) -> Result<()> {
+    let mut lines = input.lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Value>(&line) {
+            Ok(message) => handle(pipeline, message).await,
+            Err(e) => Some(error(Value::Null, PARSE_ERROR, &e.to_string())),
+        };
+        if let Some(response) = response {
+            output.write_all(response.to_string().as_bytes()).await?;
+            output.write_all(b"\n").await?;
+            output.flush().await?;
+        }
+    }
+    Ok(())
+}
+
+// Notifications (messages without an id) get no response
+async fn handle(pipeline: &Pipeline, message: Value) -> Option<Value> {
+    let id = message.get("id")?.clone();
+    let params = message.get("params").cloned().unwrap_or(Value::Null);
+    let result = match message["method"].as_str().unwrap_or_default() {
+        "initialize" => json!({
+            "protocolVersion": params["protocolVersion"].as_str().unwrap_or(PROTOCOL_VERSION),
+            "capabilities": { "tools": {} },
+            "serverInfo": { "name": "fyin", "version": env!("CARGO_PKG_VERSION") }
+        }),
+        "ping" => json!({}),
+        "tools/list" => json!({ "tools": tools() }),
+        "tools/call" => {
+            let name = params["name"].as_str().unwrap_or_default();
+            if !tools().iter().any(|tool| tool["name"] == name) {
+                return Some(error(
+                    id,
+                    INVALID_PARAMS,
+                    &format!("Unknown tool: {}", name),
+                ));
+            }
+            // tool failures are results the calling model can see and react to
+            match call_tool(pipeline, name, &params["arguments"]).await {
+                Ok(text) => json!({ "content": [{ "type": "text", "text": text }] }),
+                Err(e) => json!({
+                    "content": [{ "type": "text", "text": format!("{:#}", e) }],
+                    "isError": true
+                }),
+            }
+        }
+        method => {
+            return Some(error(
+                id,
+                METHOD_NOT_FOUND,
+                &format!("Unknown method: {}", method),
+            ))
+        }
+    };
+    Some(json!({ "jsonrpc": "2.0", "id": id, "result": result }))
+}
+
+fn tools() -> Vec<Value> {
+    let query = json!({ "type": "string", "description": "What to look for" });
+    vec![
+        json!({
+            "name": "web_search",
+            "description": "Search the web. Returns a JSON list of results with name, url and, for some sources, content.",
+            "inputSchema": {
+                "type": "object",
+                "properties": { "query": query },
+                "required": ["query"]
+            }
+        }),
+        json!({
+            "name": "fetch_page",
+            "description": "Fetch a web page and return its readable text.",
+            "inputSchema": {
+                "type": "object",
+                "properties": { "url": { "type": "string", "description": "http or https URL" } },
+                "required": ["url"]
+            }
+        }),
+        json!({
+            "name": "ask",
+            "description": "Answer a question from web search results, citing sources as [n].",
+            "inputSchema": {
+                "type": "object",
+                "properties": { "query": query },
+                "required": ["query"]
+            }
+        }),
+        json!({
+            "name": "search_collection",
+            "description": "Find the passages of a saved fyin collection closest to a query. Returns a JSON list of chunks with content, name and url.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "collection": { "type": "string", "description": "Collection name" },
+                    "query": query
+                },
+                "required": ["collection", "query"]
+            }
+        }),
+    ]
+}
+
+async fn call_tool(pipeline: &Pipeline, name: &str, arguments: &Value) -> Result<String> {
+    let argument = |key: &str| {
+        arguments[key]
+            .as_str()
+            .ok_or_else(|| anyhow!("Missing string argument '{}'", key))
+    };
+    match name {
+        "web_search" => {
+            let results = pipeline.search(argument("query")?).await?;
+            let hits: Vec<SearchHit> = results.iter().map(SearchHit::from).collect();
+            Ok(serde_json::to_string_pretty(&hits)?)
+        }
+        "fetch_page" => Ok(pipeline.fetch(argument("url")?).await?.content),
+        "ask" => {
+            let answer = pipeline.run(argument("query")?).await?.collect().await?;
+            let mut text = answer.text;
+            if !answer.chunks.is_empty() {
+                text.push_str("\n\nSources:");
+                for (i, chunk) in answer.chunks.iter().enumerate() {
+                    text.push_str(&format!("\n[{}] {} - {}", i + 1, chunk.name, chunk.url));
+                }
+            }
+            Ok(text)
+        }
+        "search_collection" => {
+            let collection = Collection::open(argument("collection")?)?;
+            let chunks = pipeline
+                .search_collection(&collection, argument("query")?)
+                .await?;
+            Ok(serde_json::to_string_pretty(&chunks)?)
+        }
+        _ => Err(anyhow!("Unknown tool: {}", name)),
+    }
+}
+
+fn error(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
@@ -0,0 +1,1463 @@
+use crate::check::FactCheck;
+use crate::collection::Collection;
+use crate::compare::{self, Comparison};
+use crate::data::{hash_string, Chunk, Request, SearchResult};
+use crate::dates::{self, Recency};
+use crate::domains::DomainPolicy;
+use crate::embedding::{self, Chunker, Embedder, WordChunker};
+use crate::events::{EventSender, Events, PipelineEvent};
+use crate::language;
+use crate::llm::{self, ChatProvider, LlmAgent, TokenStream};
+use crate::research::{self, Budget, Research, Round, Stop, Trace};
+use crate::sanitize::{self, InjectionPolicy};
+use crate::scraper::{self, Page, Scraper, WebScraper};
+use crate::search::{self, SearchOptions, SearchProvider, Source, TimeRange};
+use crate::summarize;
+use crate::templates::Template;
+use crate::tools::{self, ChatMessage, ToolCall};
+use crate::url_policy::UrlPolicy;
+use crate::vector::{Retriever, VectorDB};
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use futures::future::{self, try_join_all};
+use futures::stream::FuturesUnordered;
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync;
+use tokio::time::{self, Instant};
+use tracing::field::Empty;
+use tracing::Instrument;
+
+const DEFAULT_SEARCH_COUNT: usize = 10;
+const DEFAULT_TOP_K: usize = 10;
+// how many candidates per answer chunk to fetch when domain weights re-rank them
+const RERANK_CANDIDATES: usize = 3;
+// search freshness filter for time-sensitive queries, unless one is set already
+const FRESH_TIME_RANGE: TimeRange = TimeRange::Year;
+// a research report covers more ground than an answer, so it gets more chunks
+const REPORT_TOP_K_FACTOR: usize = 2;
+
+/// Creates an empty index for embeddings of the given dimension.
+pub type RetrieverFactory =
+    Arc<dyn Fn(usize) -> Result<Arc<sync::Mutex<dyn Retriever>>> + Send + Sync>;
+
+/// search → scrape → chunk → embed → retrieve → answer, with every stage swappable.
+///
+/// ```no_run
+/// # async fn run() -> anyhow::Result<()> {
+/// let pipeline = fyin::Pipeline::builder().search_count(5).build().await?;
+/// let answer = pipeline.run("What is HNSW?").await?.collect().await?;
+/// println!("{}", answer.text);
+/// # Ok(())
+/// # }
+/// ```
+pub struct Pipeline {
+    search_provider: Arc<dyn SearchProvider>,
+    scraper: Arc<dyn Scraper>,
+    chunker: Arc<dyn Chunker>,
+    embedder: Arc<dyn Embedder>,
+    retriever: RetrieverFactory,
+    chat: Arc<dyn ChatProvider>,
+    search_count: usize,
+    top_k: usize,
+    min_chunks: Option<usize>,
+    deadline: Option<Duration>,
+    injection_policy: InjectionPolicy,
+    search_options: SearchOptions,
+    domains: DomainPolicy,
+    recency: Recency,
+    summary_fan_in: usize,
+    template: Template,
+    translate_query: bool,
+    tools: bool,
+}
+
+#[derive(Default)]
+pub struct PipelineBuilder {
+    search_provider: Option<Arc<dyn SearchProvider>>,
+    source: Option<Source>,
+    scraper: Option<Arc<dyn Scraper>>,
+    chunker: Option<Arc<dyn Chunker>>,
+    embedder: Option<Arc<dyn Embedder>>,
+    retriever: Option<RetrieverFactory>,
+    chat: Option<Arc<dyn ChatProvider>>,
+    search_count: Option<usize>,
+    top_k: Option<usize>,
+    min_chunks: Option<usize>,
+    deadline: Option<Duration>,
+    injection_policy: InjectionPolicy,
+    search_options: Option<SearchOptions>,
+    domains: Option<DomainPolicy>,
+    recency: Recency,
+    summary_fan_in: Option<usize>,
+    template: Option<Template>,
+    translate_query: bool,
+    tools: bool,
+}
+
+/// Result of a run: everything retrieval produced, plus the answer as it streams in.
+pub struct PipelineOutput {
+    pub query: String,
+    /// All search results, whether or not they were scraped successfully
+    pub sources: Vec<SearchResult>,
+    /// Retrieved chunks in citation order: `chunks[0]` is `[1]` in the answer
+    pub chunks: Vec<Chunk>,
+    pub answer: TokenStream,
+}
+
+// What is known about the query before searching
+#[derive(Clone)]
+struct Analysis {
+    /// The query's embedding, then one per other phrasing
+    embeddings: Vec<Vec<f64>>,
+    time_sensitive: bool,
+    /// Other phrasings to search for besides the query, like its translation
+    phrasings: Vec<String>,
+    /// English name of the query's language, when it could be detected
+    language: Option<&'static str>,
+}
+
+impl Analysis {
+    // The same analysis without the other phrasings
+    fn query_only(&self) -> Analysis {
+        Analysis {
+            embeddings: self.embeddings[..1].to_vec(),
+            phrasings: vec![],
+            ..self.clone()
+        }
+    }
+}
+
+/// Per-query settings for `Pipeline::run_with`.
+#[derive(Default)]
+pub struct RunOptions<'a> {
+    /// Receives progress events, including answer tokens and `Done`
+    pub events: Option<EventSender>,
+    /// Answer from everything in the collection and save this query's sources into it
+    pub collection: Option<&'a mut Collection>,
+    /// Answer from these pages or files instead of searching; results without
+    /// content are scraped
+    pub documents: Vec<SearchResult>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Answer {
+    pub query: String,
+    pub text: String,
+    pub sources: Vec<SearchResult>,
+    pub chunks: Vec<Chunk>,
+}
+
+impl PipelineOutput {
+    /// Waits for the whole answer instead of streaming it.
+    pub async fn collect(mut self) -> Result<Answer> {
+        let mut text = String::new();
+        while let Some(token) = self.answer.next().await {
+            text.push_str(&token?);
+        }
+        Ok(Answer {
+            query: self.query,
+            text,
+            sources: self.sources,
+            chunks: self.chunks,
+        })
+    }
+}
+
+impl PipelineBuilder {
+    pub fn search_provider(mut self, search_provider: impl SearchProvider + 'static) -> Self {
+        self.search_provider = Some(Arc::new(search_provider));
+        self
+    }
+
+    /// Where results come from when no search provider is set; defaults to
+    /// SEARCH_SOURCE
+    pub fn source(mut self, source: Source) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    pub fn scraper(mut self, scraper: impl Scraper + 'static) -> Self {
+        self.scraper = Some(Arc::new(scraper));
+        self
+    }
+
+    pub fn chunker(mut self, chunker: impl Chunker + 'static) -> Self {
+        self.chunker = Some(Arc::new(chunker));
+        self
+    }
+
+    pub fn embedder(mut self, embedder: impl Embedder + 'static) -> Self {
+        self.embedder = Some(Arc::new(embedder));
+        self
+    }
+
+    pub fn retriever(
+        mut self,
+        factory: impl Fn(usize) -> Result<Arc<sync::Mutex<dyn Retriever>>> + Send + Sync + 'static,
+    ) -> Self {
+        self.retriever = Some(Arc::new(factory));
+        self
+    }
+
+    pub fn chat(mut self, chat: impl ChatProvider + 'static) -> Self {
+        self.chat = Some(Arc::new(chat));
+        self
+    }
+
+    /// Number of search results to scrape
+    pub fn search_count(mut self, search_count: usize) -> Self {
+        self.search_count = Some(search_count);
+        self
+    }
+
+    /// Number of chunks handed to the answer stage
+    pub fn top_k(mut self, top_k: usize) -> Self {
+        self.top_k = Some(top_k);
+        self
+    }
+
+    /// Start retrieval as soon as this many chunks are embedded, instead of
+    /// waiting for every page
+    pub fn min_chunks(mut self, min_chunks: usize) -> Self {
+        self.min_chunks = Some(min_chunks);
+        self
+    }
+
+    /// Start retrieval with whatever is embedded once this much time has passed
+    /// since scraping started (as long as there is at least one chunk)
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// What to do with retrieved chunks that look like prompt injection
+    pub fn injection_policy(mut self, injection_policy: InjectionPolicy) -> Self {
+        self.injection_policy = injection_policy;
+        self
+    }
+
+    /// Site restrictions and other settings passed to the search engine
+    pub fn search_options(mut self, search_options: SearchOptions) -> Self {
+        self.search_options = Some(search_options);
+        self
+    }
+
+    /// Domain allow/deny lists and trust weights
+    pub fn domain_policy(mut self, domains: DomainPolicy) -> Self {
+        self.domains = Some(domains);
+        self
+    }
+
+    /// When to filter search results by date and boost recent chunks
+    pub fn recency(mut self, recency: Recency) -> Self {
+        self.recency = recency;
+        self
+    }
+
+    /// How many partial summaries `Pipeline::summarize` merges in one step
+    pub fn summary_fan_in(mut self, fan_in: usize) -> Self {
+        self.summary_fan_in = Some(fan_in);
+        self
+    }
+
+    /// Answer prompt template; defaults to FYIN_STYLE or the detailed style.
+    pub fn template(mut self, template: Template) -> Self {
+        self.template = Some(template);
+        self
+    }
+
+    /// Also search with the query translated into English when it is in
+    /// another language. Off by default, since it costs a chat call per query.
+    pub fn translate_query(mut self, translate_query: bool) -> Self {
+        self.translate_query = translate_query;
+        self
+    }
+
+    /// Let the chat model call tools while answering: a calculator, a date
+    /// utility and a new search. The answer then arrives all at once.
+    pub fn tools(mut self, tools: bool) -> Self {
+        self.tools = tools;
+        self
+    }
+
+    /// Fills in any stage that was not set from the environment, like the CLI does.
+    pub async fn build(self) -> Result<Pipeline> {
+        let llm_agent = if self.embedder.is_none() || self.chat.is_none() {
+            Some(Arc::new(LlmAgent::init().await))
+        } else {
+            None
+        };
+
+        let scraper = match self.scraper {
+            Some(scraper) => scraper,
+            None => Arc::new(WebScraper::new(UrlPolicy::from_env()?)),
+        };
+
+        // arXiv PDFs go through the same scraper as pages
+        let search_provider = match (self.search_provider, self.source) {
+            (Some(search_provider), _) => search_provider,
+            (None, Some(source)) => search::for_source(source, scraper.clone())?,
+            (None, None) => search::from_env(scraper.clone())?,
+        };
+
+        let search_options = match self.search_options {
+            Some(search_options) => search_options,
+            None => SearchOptions::from_env()?,
+        };
+
+        let domains = match self.domains {
+            Some(domains) => domains,
+            None => DomainPolicy::from_env()?,
+        };
+
+        let template = match self.template {
+            Some(template) => template,
+            None => Template::from_env()?,
+        };
+
+        Ok(Pipeline {
+            search_provider,
+            scraper,
+            chunker: self
+                .chunker
+                .unwrap_or_else(|| Arc::new(WordChunker::default())),
+            embedder: self.embedder.unwrap_or_else(|| llm_agent.clone().unwrap()),
+            retriever: self.retriever.unwrap_or_else(|| {
+                Arc::new(|dimension| {
+                    let retriever: Arc<sync::Mutex<dyn Retriever>> =
+                        Arc::new(sync::Mutex::new(VectorDB::new(dimension)));
+                    Ok(retriever)
+                })
+            }),
+            chat: self.chat.unwrap_or_else(|| llm_agent.unwrap()),
+            search_count: self.search_count.unwrap_or(DEFAULT_SEARCH_COUNT),
+            top_k: self.top_k.unwrap_or(DEFAULT_TOP_K),
+            min_chunks: self.min_chunks,
+            deadline: self.deadline,
+            injection_policy: self.injection_policy,
+            search_options,
+            domains,
+            recency: self.recency,
+            summary_fan_in: self.summary_fan_in.unwrap_or(summarize::DEFAULT_FAN_IN),
+            template,
+            translate_query: self.translate_query,
+            tools: self.tools,
+        })
+    }
+}
+
+impl Pipeline {
+    pub fn builder() -> PipelineBuilder {
+        PipelineBuilder::default()
+    }
+
+    pub async fn run(&self, query: &str) -> Result<PipelineOutput> {
+        self.run_with(query, RunOptions::default()).await
+    }
+
+    #[tracing::instrument(
+        name = "query",
+        skip_all,
+        fields(query = %query, time_sensitive = Empty, language = Empty)
+    )]
+    pub async fn run_with(&self, query: &str, options: RunOptions<'_>) -> Result<PipelineOutput> {
+        let events = Events::from(options.events);
+        let request = Request::init(query);
+        let analysis = self.analyze(query).await?;
+        let dimension = analysis.embeddings[0].len();
+
+        let collection = match options.collection {
+            Some(collection) => collection,
+            None => {
+                let retriever = (self.retriever)(dimension)?;
+                return self
+                    .execute(request, retriever, &analysis, options.documents, &events)
+                    .await;
+            }
+        };
+
+        collection.load_into(&mut request.lock().unwrap());
+        let vector_db = Arc::new(sync::Mutex::new(collection.vector_db(dimension).await?));
+
+        let output = self
+            .execute(
+                request.clone(),
+                vector_db.clone(),
+                &analysis,
+                options.documents,
+                &events,
+            )
+            .await?;
+
+        let request = request.lock().unwrap().clone();
+        collection
+            .save(&request, &mut *vector_db.lock().await)
+            .await?;
+        Ok(output)
+    }
+
+    /// Search results for `query`, filtered like those of a full run but
+    /// not scraped.
+    pub async fn search(&self, query: &str) -> Result<Vec<SearchResult>> {
+        search::search_allowed(
+            self.search_provider.as_ref(),
+            query,
+            self.search_count,
+            &self.search_options,
+            &self.domains,
+        )
+        .await
+    }
+
+    /// Fetches and extracts one page with the pipeline's scraper.
+    pub async fn fetch(&self, url: &str) -> Result<Page> {
+        self.scraper.scrape(url).await
+    }
+
+    /// The `top_k` chunks of a collection closest to `query`, without
+    /// searching or answering.
+    pub async fn search_collection(
+        &self,
+        collection: &Collection,
+        query: &str,
+    ) -> Result<Vec<Chunk>> {
+        if collection.is_empty() {
+            return Err(anyhow!("Collection '{}' is empty", collection.name));
+        }
+        let query_embedding = self.embedder.embed(query).await?;
+        let mut vector_db = collection.vector_db(query_embedding.len()).await?;
+        vector_db.build().await?;
+        let ids = vector_db.search(&query_embedding, self.top_k).await?;
+
+        let request = Request::init(query);
+        let mut request = request.lock().unwrap();
+        collection.load_into(&mut request);
+        Ok(request.get_chunks(ids))
+    }
+
+    /// Summarizes `documents` in full, or the search results for `query` when
+    /// there are none, with map-reduce instead of retrieval. `query` steers what
+    /// the summary focuses on. Every section is in `chunks`, in citation order.
+    #[tracing::instrument(name = "summarize", skip_all, fields(query = %query, sections = Empty))]
+    pub async fn summarize(
+        &self,
+        query: &str,
+        documents: Vec<SearchResult>,
+        events: Option<EventSender>,
+    ) -> Result<PipelineOutput> {
+        let events = Events::from(events);
+        let request = Request::init(query);
+        let mut order: Vec<String> = documents.iter().map(|d| d.url.clone()).collect();
+        if documents.is_empty() {
+            search::fetch_web_pages(
+                self.search_provider.as_ref(),
+                request.clone(),
+                query,
+                self.search_count,
+                &self.search_options,
+                &self.domains,
+                &events,
+            )
+            .await?;
+            order = request
+                .lock()
+                .unwrap()
+                .search_map
+                .values()
+                .map(|result| result.url.clone())
+                .collect();
+            order.sort();
+        } else {
+            let mut request = request.lock().unwrap();
+            documents
+                .into_iter()
+                .for_each(|document| request.add_search_result(document));
+        }
+
+        // every page is read in full: a summary cannot start early
+        let urls = scraper::pending_urls(&request.lock().unwrap());
+        let mut pages = scraper::scrape_pages(self.scraper.clone(), urls);
+        while let Some((url, page)) = pages.next().await {
+            match page {
+                Ok(page) => {
+                    events.send(PipelineEvent::PageScraped {
+                        url: url.clone(),
+                        bytes: page.content.len(),
+                    });
+                    request
+                        .lock()
+                        .unwrap()
+                        .add_webpage_content(&url, page.content, page.published);
+                }
+                Err(e) => events.send(PipelineEvent::PageFailed {
+                    url,
+                    error: e.to_string(),
+                }),
+            }
+        }
+
+        let (sources, sections) = {
+            let request = request.lock().unwrap();
+            let sources: Vec<SearchResult> = order
+                .iter()
+                .filter_map(|url| request.search_map.get(&hash_string(url)).cloned())
+                .collect();
+            let sections: Vec<Chunk> = sources
+                .iter()
+                .flat_map(|source| {
+                    let content = source.content.as_deref().unwrap_or_default();
+                    self.chunker
+                        .chunk(content)
+                        .into_iter()
+                        .map(|section| Chunk {
+                            content: section,
+                            name: source.name.clone(),
+                            url: source.url.clone(),
+                            published: source.published,
+                        })
+                })
+                .collect();
+            (sources, sections)
+        };
+        let sections = self.screen(sections, &events);
+        if sections.is_empty() {
+            return Err(anyhow!("Nothing to summarize: no document could be read"));
+        }
+        tracing::Span::current().record("sections", sections.len());
+
+        let generate = tracing::info_span!("generate", tokens = Empty, first_token_ms = Empty);
+        let started = Instant::now();
+        let answer = summarize::summarize(
+            self.chat.as_ref(),
+            query,
+            &sections,
+            self.summary_fan_in,
+            &events,
+        )
+        .instrument(generate.clone())
+        .await?;
+        let answer = forward_tokens(answer, events, generate, started);
+
+        Ok(PipelineOutput {
+            query: query.to_string(),
+            sources,
+            chunks: sections,
+            answer,
+        })
+    }
+
+    /// Compares the entities `query` names (or `entities`, when given) on
+    /// `dimensions`, or on ones the model picks. Each entity is searched and
+    /// retrieved on its own, and every cell of the table cites its chunks.
+    #[tracing::instrument(name = "compare", skip_all, fields(query = %query, entities = Empty))]
+    pub async fn compare(
+        &self,
+        query: &str,
+        entities: Vec<String>,
+        dimensions: Vec<String>,
+        events: Option<EventSender>,
+    ) -> Result<Comparison> {
+        let events = Events::from(events);
+        let entities = if entities.is_empty() {
+            self.comparison_entities(query).await?
+        } else {
+            entities
+        };
+        if entities.len() < 2 {
+            return Err(anyhow!(
+                "Found nothing to compare in '{}'; name what to compare with --entity",
+                query
+            ));
+        }
+        tracing::Span::current().record("entities", entities.join(", "));
+
+        let time_sensitive = self.is_time_sensitive(query).await;
+        let evidence = try_join_all(entities.iter().map(|entity| {
+            let entity_query = compare::entity_query(query, entity, &entities);
+            self.evidence(entity_query, time_sensitive, &events)
+        }))
+        .await?;
+        let chunks = evidence.concat();
+        events.send(PipelineEvent::RetrievalDone {
+            chunks: chunks.clone(),
+        });
+
+        let groups: Vec<(&str, &[Chunk])> = entities
+            .iter()
+            .map(String::as_str)
+            .zip(evidence.iter().map(Vec::as_slice))
+            .collect();
+        let language = language::detect(query).and_then(language::name);
+        let reply = self
+            .chat
+            .complete(&llm::comparison_prompt(
+                query,
+                &groups,
+                &dimensions,
+                language,
+            ))
+            .instrument(tracing::info_span!("generate"))
+            .await?;
+        let comparison = Comparison::parse(query, entities, chunks, &reply)?;
+        events.send(PipelineEvent::Done);
+        Ok(comparison)
+    }
+
+    /// Searches for evidence on both sides of `claim`, has the model classify
+    /// each retrieved chunk as supporting, refuting or irrelevant, and returns
+    /// its verdict with the cited evidence.
+    #[tracing::instrument(name = "check", skip_all, fields(claim = %claim, verdict = Empty))]
+    pub async fn check(&self, claim: &str, events: Option<EventSender>) -> Result<FactCheck> {
+        let events = Events::from(events);
+        // the claim alone still finds sources when expansion fails
+        let (phrasings, time_sensitive) = tokio::join!(
+            self.chat.claim_queries(claim),
+            self.is_time_sensitive(claim)
+        );
+        let phrasings = phrasings.unwrap_or_else(|e| {
+            tracing::warn!("Failed expanding claim into search queries: {}", e);
+            vec![]
+        });
+        let embeddings = try_join_all(
+            std::iter::once(claim)
+                .chain(phrasings.iter().map(String::as_str))
+                .map(|text| self.embedder.embed(text)),
+        )
+        .instrument(tracing::info_span!("embed_query"))
+        .await?;
+        let analysis = Analysis {
+            embeddings,
+            time_sensitive,
+            phrasings,
+            language: language::detect(claim).and_then(language::name),
+        };
+
+        let retriever = (self.retriever)(analysis.embeddings[0].len())?;
+        let (_, chunks) = self
+            .retrieve(Request::init(claim), retriever, &analysis, vec![], &events)
+            .await?;
+        let chunks = self.screen(chunks, &events);
+        events.send(PipelineEvent::RetrievalDone {
+            chunks: chunks.clone(),
+        });
+
+        let check = if chunks.is_empty() {
+            FactCheck::without_sources(claim)
+        } else {
+            let reply = self
+                .chat
+                .complete(&llm::fact_check_prompt(claim, &chunks, analysis.language))
+                .instrument(tracing::info_span!("generate"))
+                .await?;
+            FactCheck::parse(claim, chunks, &reply)?
+        };
+        tracing::Span::current().record("verdict", check.verdict.label());
+        events.send(PipelineEvent::Done);
+        Ok(check)
+    }
+
+    /// Researches `query` over several rounds: the chat model plans
+    /// sub-questions, their search results are read into one index, and the
+    /// model asks for more until nothing is missing or `budget` runs out.
+    /// The answer is a long-form report drawn from everything read.
+    #[tracing::instrument(name = "research", skip_all, fields(query = %query, rounds = Empty))]
+    pub async fn research(
+        &self,
+        query: &str,
+        budget: Budget,
+        events: Option<EventSender>,
+    ) -> Result<Research> {
+        let events = Events::from(events);
+        let started = Instant::now();
+        let request = Request::init(query);
+        let (query_embedding, time_sensitive) = tokio::join!(
+            self.embedder
+                .embed(query)
+                .instrument(tracing::info_span!("embed_query")),
+            self.is_time_sensitive(query)
+        );
+        let query_embedding = query_embedding?;
+        let retriever = (self.retriever)(query_embedding.len())?;
+        let search_options = self.search_options_for(time_sensitive);
+        let mut analysis = Analysis {
+            embeddings: vec![query_embedding],
+            time_sensitive,
+            phrasings: vec![],
+            language: language::detect(query).and_then(language::name),
+        };
+
+        let mut tokens = 0;
+        let mut rounds: Vec<Round> = vec![];
+        let mut questions = self
+            .research_questions(query, &[], &[], &mut tokens)
+            .await?;
+        let mut pages = 0;
+        let stop = loop {
+            if questions.is_empty() {
+                break Stop::Answered;
+            }
+            if let Some(stop) = budget.exceeded(rounds.len(), pages, tokens, started.elapsed()) {
+                break stop;
+            }
+
+            let pages_left = budget.pages - pages;
+            questions.truncate(pages_left);
+            let count = (pages_left / questions.len()).min(self.search_count);
+            events.send(PipelineEvent::ResearchRound {
+                round: rounds.len() + 1,
+                questions: questions.clone(),
+            });
+            let found_before = request.lock().unwrap().search_map.len();
+            // one failed search should not end the research
+            let searches = questions.iter().map(|question| {
+                search::fetch_web_pages(
+                    self.search_provider.as_ref(),
+                    request.clone(),
+                    question,
+                    count,
+                    &search_options,
+                    &self.domains,
+                    &events,
+                )
+            });
+            for result in future::join_all(searches).await {
+                if let Err(e) = result {
+                    tracing::warn!("Research search failed: {}", e);
+                }
+            }
+            self.gather(request.clone(), retriever.clone(), &events)
+                .await?;
+
+            for question in &questions {
+                analysis
+                    .embeddings
+                    .push(self.embedder.embed(question).await?);
+            }
+            let (found, chunks) = {
+                let request = request.lock().unwrap();
+                (
+                    request.search_map.len(),
+                    request.chunk_id_to_search_id.len(),
+                )
+            };
+            pages += found - found_before;
+            rounds.push(Round {
+                questions: std::mem::take(&mut questions),
+                pages: found - found_before,
+                chunks,
+            });
+            // no point asking what to search next when no search may follow
+            if let Some(stop) = budget.exceeded(rounds.len(), pages, tokens, started.elapsed()) {
+                break stop;
+            }
+
+            // what the query itself retrieves shows best what is still missing
+            let ids = self
+                .rank(
+                    &request,
+                    &retriever,
+                    &analysis.query_only(),
+                    self.top_k,
+                    true,
+                )
+                .await?;
+            let chunks = request.lock().unwrap().get_chunks(ids);
+            let asked: Vec<String> = rounds.iter().flat_map(|r| r.questions.clone()).collect();
+            questions = self
+                .research_questions(query, &asked, &chunks, &mut tokens)
+                .await?
+                .into_iter()
+                .filter(|question| !asked.contains(question))
+                .collect();
+        };
+        tracing::Span::current().record("rounds", rounds.len());
+        tracing::info!("Research stopped: {}", stop.reason());
+
+        // the report draws on chunks close to the query or any sub-question
+        let ids = self
+            .rank(
+                &request,
+                &retriever,
+                &analysis,
+                self.top_k * REPORT_TOP_K_FACTOR,
+                true,
+            )
+            .await?;
+        let (sources, chunks) = {
+            let request = request.lock().unwrap();
+            (
+                request.search_map.values().cloned().collect(),
+                request.get_chunks(ids),
+            )
+        };
+        let chunks = self.screen(chunks, &events);
+        events.send(PipelineEvent::RetrievalDone {
+            chunks: chunks.clone(),
+        });
+        let trace = Trace {
+            rounds,
+            stop,
+            tokens,
+            elapsed: started.elapsed(),
+        };
+
+        let generate = tracing::info_span!("generate", tokens = Empty, first_token_ms = Empty);
+        let started = Instant::now();
+        let answer = self
+            .chat
+            .stream(&llm::report_prompt(query, &chunks, analysis.language))
+            .instrument(generate.clone())
+            .await?;
+        let answer = forward_tokens(answer, events, generate, started);
+
+        Ok(Research {
+            output: PipelineOutput {
+                query: query.to_string(),
+                sources,
+                chunks,
+                answer,
+            },
+            trace,
+        })
+    }
+
+    // Asks the model what to search for next, counting the tokens it used
+    async fn research_questions(
+        &self,
+        query: &str,
+        asked: &[String],
+        chunks: &[Chunk],
+        tokens: &mut usize,
+    ) -> Result<Vec<String>> {
+        let prompt = llm::research_prompt(query, asked, chunks);
+        let reply = self
+            .chat
+            .complete(&prompt)
+            .instrument(tracing::info_span!("plan"))
+            .await?;
+        *tokens += research::estimate_tokens(&prompt) + research::estimate_tokens(&reply);
+        Ok(llm::parse_sub_questions(&reply))
+    }
+
+    // Entities of an "A vs B" question, or the ones the model finds
+    async fn comparison_entities(&self, query: &str) -> Result<Vec<String>> {
+        let entities = compare::detect_entities(query);
+        if !entities.is_empty() {
+            return Ok(entities);
+        }
+        self.chat.comparison_entities(query).await
+    }
+
+    // Screened chunks about one entity of a comparison, from its own search
+    async fn evidence(
+        &self,
+        query: String,
+        time_sensitive: bool,
+        events: &Events,
+    ) -> Result<Vec<Chunk>> {
+        let analysis = Analysis {
+            embeddings: vec![self.embedder.embed(&query).await?],
+            time_sensitive,
+            phrasings: vec![],
+            language: None,
+        };
+        let retriever = (self.retriever)(analysis.embeddings[0].len())?;
+        let (_, chunks) = self
+            .retrieve(Request::init(&query), retriever, &analysis, vec![], events)
+            .await?;
+        Ok(self.screen(chunks, events))
+    }
+
+    async fn execute(
+        &self,
+        request: Arc<Mutex<Request>>,
+        retriever: Arc<sync::Mutex<dyn Retriever>>,
+        analysis: &Analysis,
+        documents: Vec<SearchResult>,
+        events: &Events,
+    ) -> Result<PipelineOutput> {
+        let query = request.lock().unwrap().query.clone();
+        let (mut sources, chunks) = self
+            .retrieve(
+                request.clone(),
+                retriever.clone(),
+                analysis,
+                documents,
+                events,
+            )
+            .await?;
+        let mut chunks = self.screen(chunks, events);
+
+        events.send(PipelineEvent::RetrievalDone {
+            chunks: chunks.clone(),
+        });
+
+        let generate = tracing::info_span!("generate", tokens = Empty, first_token_ms = Empty);
+        let started = Instant::now();
+        let prompt = llm::answer_prompt(&self.template, &query, &chunks, analysis.language);
+        let answer: TokenStream = if self.tools {
+            let text = self
+                .answer_with_tools(&request, &retriever, analysis, prompt, &mut chunks, events)
+                .instrument(generate.clone())
+                .await?;
+            // search_again may have found more results
+            sources = request
+                .lock()
+                .unwrap()
+                .search_map
+                .values()
+                .cloned()
+                .collect();
+            Box::pin(stream::once(async move { Ok(text) }))
+        } else {
+            self.chat
+                .stream(&prompt)
+                .instrument(generate.clone())
+                .await?
+        };
+        let answer = forward_tokens(answer, events.clone(), generate, started);
+
+        Ok(PipelineOutput {
+            query,
+            sources,
+            chunks,
+            answer,
+        })
+    }
+
+    // Lets the chat model call tools until it answers, for at most
+    // MAX_TOOL_ROUNDS turns. Chunks that search_again finds join `chunks`.
+    async fn answer_with_tools(
+        &self,
+        request: &Arc<Mutex<Request>>,
+        retriever: &Arc<sync::Mutex<dyn Retriever>>,
+        analysis: &Analysis,
+        prompt: String,
+        chunks: &mut Vec<Chunk>,
+        events: &Events,
+    ) -> Result<String> {
+        let specs = tools::specs();
+        let mut messages = vec![ChatMessage::User(prompt + llm::TOOL_INSTRUCTIONS)];
+        for round in 0..=tools::MAX_TOOL_ROUNDS {
+            // the last turn offers no tools, so the model has to answer
+            let offered = if round < tools::MAX_TOOL_ROUNDS {
+                &specs[..]
+            } else {
+                &[]
+            };
+            let reply = self.chat.chat_with_tools(&messages, offered).await?;
+            let calls = match &reply {
+                ChatMessage::Assistant { text, tool_calls } if tool_calls.is_empty() => {
+                    return Ok(text.clone())
+                }
+                ChatMessage::Assistant { tool_calls, .. } => tool_calls.clone(),
+                _ => return Err(anyhow!("The chat model did not reply")),
+            };
+            messages.push(reply);
+            for call in calls {
+                let result = self
+                    .call_tool(&call, request, retriever, analysis, chunks, events)
+                    .await;
+                events.send(PipelineEvent::ToolCalled {
+                    name: call.name.clone(),
+                    arguments: call.arguments.clone(),
+                    result: result.clone(),
+                });
+                messages.push(ChatMessage::Tool {
+                    call_id: call.id,
+                    name: call.name,
+                    content: result,
+                });
+            }
+        }
+        Err(anyhow!(
+            "The chat model kept calling tools instead of answering"
+        ))
+    }
+
+    // Runs one tool call. Failures are reported to the model as the result,
+    // so it can try again or answer without the tool.
+    #[tracing::instrument(
+        name = "tool",
+        skip_all,
+        fields(name = %call.name, arguments = %call.arguments)
+    )]
+    async fn call_tool(
+        &self,
+        call: &ToolCall,
+        request: &Arc<Mutex<Request>>,
+        retriever: &Arc<sync::Mutex<dyn Retriever>>,
+        analysis: &Analysis,
+        chunks: &mut Vec<Chunk>,
+        events: &Events,
+    ) -> String {
+        let result = match call.name.as_str() {
+            tools::CALCULATOR => tools::calculator(&call.arguments),
+            tools::DATE => tools::date(&call.arguments),
+            tools::SEARCH_AGAIN => match call.arguments["query"].as_str() {
+                Some(query) => {
+                    self.search_again(query, request, retriever, analysis, chunks, events)
+                        .await
+                }
+                None => Err(anyhow!("Missing 'query'")),
+            },
+            name => Err(anyhow!("Unknown tool '{}'", name)),
+        };
+        result.unwrap_or_else(|e| format!("Error: {}", e))
+    }
+
+    // Searches for `query` into the same index and returns the chunks it
+    // retrieves that are not cited yet, numbered after `chunks`
+    async fn search_again(
+        &self,
+        query: &str,
+        request: &Arc<Mutex<Request>>,
+        retriever: &Arc<sync::Mutex<dyn Retriever>>,
+        analysis: &Analysis,
+        chunks: &mut Vec<Chunk>,
+        events: &Events,
+    ) -> Result<String> {
+        search::fetch_web_pages(
+            self.search_provider.as_ref(),
+            request.clone(),
+            query,
+            self.search_count,
+            &self.search_options_for(analysis.time_sensitive),
+            &self.domains,
+            events,
+        )
+        .await?;
+        self.gather(request.clone(), retriever.clone(), events)
+            .await?;
+
+        let analysis = Analysis {
+            embeddings: vec![self.embedder.embed(query).await?],
+            phrasings: vec![],
+            ..analysis.clone()
+        };
+        let ids = self
+            .rank(request, retriever, &analysis, self.top_k, true)
+            .await?;
+        let found = request.lock().unwrap().get_chunks(ids);
+        let found: Vec<Chunk> = self
+            .screen(found, events)
+            .into_iter()
+            .filter(|chunk| {
+                !chunks
+                    .iter()
+                    .any(|known| known.url == chunk.url && known.content == chunk.content)
+            })
+            .collect();
+        if found.is_empty() {
+            return Ok("No new sources found.".to_string());
+        }
+
+        let first = chunks.len() + 1;
+        chunks.extend(found.iter().cloned());
+        events.send(PipelineEvent::RetrievalDone {
+            chunks: chunks.clone(),
+        });
+        Ok(llm::numbered_sources(&found, first))
+    }
+
+    // Searches (or takes the given documents), scrapes, embeds and returns
+    // every search result and the retrieved chunks, before screening
+    async fn retrieve(
+        &self,
+        request: Arc<Mutex<Request>>,
+        retriever: Arc<sync::Mutex<dyn Retriever>>,
+        analysis: &Analysis,
+        documents: Vec<SearchResult>,
+        events: &Events,
+    ) -> Result<(Vec<SearchResult>, Vec<Chunk>)> {
+        // documents the caller picked are used as they are, whatever the
+        // domain and site filters say
+        let searched = documents.is_empty();
+        if searched {
+            let search_options = self.search_options_for(analysis.time_sensitive);
+            let query = request.lock().unwrap().query.clone();
+            let queries = std::iter::once(&query).chain(&analysis.phrasings);
+            try_join_all(queries.map(|query| {
+                search::fetch_web_pages(
+                    self.search_provider.as_ref(),
+                    request.clone(),
+                    query,
+                    self.search_count,
+                    &search_options,
+                    &self.domains,
+                    events,
+                )
+            }))
+            .await?;
+        } else {
+            let mut request = request.lock().unwrap();
+            for document in documents {
+                events.send(PipelineEvent::ResultFound {
+                    name: document.name.clone(),
+                    url: document.url.clone(),
+                });
+                request.add_search_result(document);
+            }
+        }
+
+        self.gather(request.clone(), retriever.clone(), events)
+            .await?;
+
+        let ids = self
+            .rank(&request, &retriever, analysis, self.top_k, searched)
+            .await?;
+
+        let request = request.lock().unwrap();
+        Ok((
+            request.search_map.values().cloned().collect(),
+            request.get_chunks(ids),
+        ))
+    }
+
+    // The configured search options, limited to fresh results for
+    // time-sensitive queries unless a time range is set already
+    fn search_options_for(&self, time_sensitive: bool) -> SearchOptions {
+        let mut search_options = self.search_options.clone();
+        if time_sensitive && search_options.time_range.is_none() {
+            search_options.time_range = Some(FRESH_TIME_RANGE);
+        }
+        search_options
+    }
+
+    // Ids of the `top_k` chunks closest to the query, after re-ranking
+    #[tracing::instrument(name = "retrieve", skip_all, fields(chunks = Empty))]
+    async fn rank(
+        &self,
+        request: &Mutex<Request>,
+        retriever: &Arc<sync::Mutex<dyn Retriever>>,
+        analysis: &Analysis,
+        top_k: usize,
+        filter: bool,
+    ) -> Result<Vec<usize>> {
+        let time_sensitive = analysis.time_sensitive;
+        retriever.lock().await.build().await?;
+        let ids = if self.reranks() || time_sensitive || analysis.embeddings.len() > 1 {
+            // a chunk close to any phrasing of the query counts
+            let mut best: HashMap<usize, f64> = HashMap::new();
+            for embedding in &analysis.embeddings {
+                let scored = retriever
+                    .lock()
+                    .await
+                    .search_scored(embedding, top_k * RERANK_CANDIDATES)
+                    .await?;
+                for (id, score) in scored {
+                    let entry = best.entry(id).or_insert(score);
+                    *entry = entry.max(score);
+                }
+            }
+            let scored = best.into_iter().collect();
+            self.rerank(
+                &request.lock().unwrap(),
+                scored,
+                top_k,
+                time_sensitive,
+                filter,
+            )
+        } else {
+            retriever
+                .lock()
+                .await
+                .search(&analysis.embeddings[0], top_k)
+                .await?
+        };
+        tracing::Span::current().record("chunks", ids.len());
+        Ok(ids)
+    }
+
+    fn reranks(&self) -> bool {
+        !self.domains.is_empty()
+            || !self.search_options.sites.is_empty()
+            || !self.search_options.exclude_sites.is_empty()
+    }
+
+    // Weighs each candidate's similarity by its domain's trust weight, and by
+    // how recent its page is for time-sensitive queries, dropping chunks from
+    // domains that are not allowed when `filter` is set (collections can hold
+    // sources from before a domain was blocked), and keeps the best `top_k`.
+    fn rerank(
+        &self,
+        request: &Request,
+        scored: Vec<(usize, f64)>,
+        top_k: usize,
+        time_sensitive: bool,
+        filter: bool,
+    ) -> Vec<usize> {
+        let today = Utc::now().date_naive();
+        let mut weighted: Vec<(usize, f64)> = scored
+            .into_iter()
+            .filter_map(|(id, score)| {
+                let source = request
+                    .search_map
+                    .get(request.chunk_id_to_search_id.get(&id)?)?;
+                let url = &source.url;
+                if filter && (!self.domains.is_allowed(url) || !self.search_options.allows(url)) {
+                    return None;
+                }
+                let mut score = score * self.domains.weight(url);
+                if time_sensitive {
+                    score *= dates::recency_weight(source.published, today);
+                }
+                Some((id, score))
+            })
+            .collect();
+        weighted.sort_by(|a, b| b.1.total_cmp(&a.1));
+        weighted.into_iter().take(top_k).map(|(id, _)| id).collect()
+    }
+
+    /// Related questions grounded in the answer's chunks. Failing to come up
+    /// with any is not an error, since the answer itself is already done.
+    #[tracing::instrument(name = "follow_ups", skip_all, fields(questions = Empty))]
+    pub async fn follow_ups(&self, answer: &Answer) -> Vec<String> {
+        if answer.chunks.is_empty() {
+            return vec![];
+        }
+        let questions = self
+            .chat
+            .follow_up_questions(&answer.query, &answer.text, &answer.chunks)
+            .await
+            .unwrap_or_else(|e| {
+                tracing::warn!("Failed suggesting follow-up questions: {}", e);
+                vec![]
+            });
+        tracing::Span::current().record("questions", questions.len());
+        questions
+    }
+
+    // Embeds the query, and its translation when there is one, and asks
+    // whether it is time-sensitive
+    async fn analyze(&self, query: &str) -> Result<Analysis> {
+        let language = language::detect(query);
+        tracing::Span::current().record("language", language.unwrap_or("unknown"));
+        let (query_embedding, time_sensitive, translation) = tokio::join!(
+            self.embedder
+                .embed(query)
+                .instrument(tracing::info_span!("embed_query")),
+            self.is_time_sensitive(query),
+            self.translate(query, language)
+        );
+        tracing::Span::current().record("time_sensitive", time_sensitive);
+
+        let mut embeddings = vec![query_embedding?];
+        if let Some(translation) = &translation {
+            embeddings.push(
+                self.embedder
+                    .embed(translation)
+                    .instrument(tracing::info_span!("embed_translation"))
+                    .await?,
+            );
+        }
+        Ok(Analysis {
+            embeddings,
+            time_sensitive,
+            phrasings: translation.into_iter().collect(),
+            language: language.and_then(language::name),
+        })
+    }
+
+    // The query in English, when translating is on and it is in another
+    // language. A failed translation only costs the English results.
+    async fn translate(&self, query: &str, language: Option<&str>) -> Option<String> {
+        if !self.translate_query || language.is_none_or(|code| code == language::ENGLISH) {
+            return None;
+        }
+        let english = language::name(language::ENGLISH)?;
+        match self.chat.translate_query(query, english).await {
+            Ok(translation) if !translation.is_empty() && translation != query => {
+                tracing::info!("Also searching for: {}", translation);
+                Some(translation)
+            }
+            Ok(_) => None,
+            Err(e) => {
+                tracing::warn!("Failed translating query: {}", e);
+                None
+            }
+        }
+    }
+
+    // A failing classifier only costs the recency boost, not the answer.
+    // Only `Recency::Model` waits on the chat model.
+    async fn is_time_sensitive(&self, query: &str) -> bool {
+        match self.recency {
+            Recency::Off => false,
+            Recency::Always => true,
+            Recency::Auto => llm::looks_time_sensitive(query),
+            Recency::Model => self
+                .chat
+                .is_time_sensitive(query)
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::warn!("Failed classifying query: {}", e);
+                    false
+                }),
+        }
+    }
+
+    // Reports chunks that look like prompt injection and applies the policy to
+    // them; the result is the final citation order.
+    fn screen(&self, chunks: Vec<Chunk>, events: &Events) -> Vec<Chunk> {
+        let mut clean = vec![];
+        let mut flagged = vec![];
+        for chunk in chunks {
+            let patterns = sanitize::detect_injection(&chunk.content);
+            if patterns.is_empty() {
+                clean.push(chunk);
+                continue;
+            }
+            tracing::warn!("Possible prompt injection in {}: {:?}", chunk.url, patterns);
+            events.send(PipelineEvent::InjectionDetected {
+                url: chunk.url.clone(),
+                patterns,
+            });
+            match self.injection_policy {
+                InjectionPolicy::Flag => clean.push(chunk),
+                InjectionPolicy::DownRank => flagged.push(chunk),
+                InjectionPolicy::Drop => {}
+            }
+        }
+        clean.extend(flagged);
+        clean
+    }
+
+    // Scrape, chunk and embed as one pipelined stage: each page is chunked and
+    // its chunks embedded as soon as its scrape finishes, rather than after all
+    // pages are in. Returns early on `min_chunks` or `deadline`; whatever is
+    // still in flight then is dropped.
+    #[tracing::instrument(name = "gather", skip_all, fields(pages = Empty, chunks = Empty))]
+    async fn gather(
+        &self,
+        request: Arc<Mutex<Request>>,
+        retriever: Arc<sync::Mutex<dyn Retriever>>,
+        events: &Events,
+    ) -> Result<()> {
+        let (urls, prefetched, mut next_id) = {
+            let request = request.lock().unwrap();
+            (
+                scraper::pending_urls(&request),
+                scraper::prefetched_pages(&request),
+                request.max_chunk_id(),
+            )
+        };
+        tracing::Span::current().record("pages", urls.len() + prefetched.len());
+        let mut pages = scraper::scrape_pages(self.scraper.clone(), urls);
+        let mut embeddings = FuturesUnordered::new();
+        let mut embedded = 0;
+
+        // providers like Wikipedia return the text with the result
+        for (url, content) in prefetched {
+            events.send(PipelineEvent::PageScraped {
+                url: url.clone(),
+                bytes: content.len(),
+            });
+            embeddings.extend(embedding::embed_page(
+                self.chunker.as_ref(),
+                self.embedder.clone(),
+                &hash_string(&url),
+                &url,
+                &content,
+            ));
+        }
+
+        let deadline = self.deadline.map(|deadline| Instant::now() + deadline);
+        let mut deadline_passed = false;
+
+        loop {
+            if pages.is_empty() && embeddings.is_empty() {
+                break;
+            }
+            if self.min_chunks.is_some_and(|min| embedded >= min) {
+                tracing::info!("Starting retrieval after {} chunks", embedded);
+                break;
+            }
+            if deadline_passed && embedded > 0 {
+                tracing::info!(
+                    "Deadline passed, starting retrieval with {} chunks",
+                    embedded
+                );
+                break;
+            }
+
+            tokio::select! {
+                Some((url, page)) = pages.next(), if !pages.is_empty() => match page {
+                    Ok(page) => {
+                        events.send(PipelineEvent::PageScraped {
+                            url: url.clone(),
+                            bytes: page.content.len(),
+                        });
+                        let search_id = hash_string(&url);
+                        embeddings.extend(embedding::embed_page(
+                            self.chunker.as_ref(),
+                            self.embedder.clone(),
+                            &search_id,
+                            &url,
+                            &page.content,
+                        ));
+                        request
+                            .lock()
+                            .unwrap()
+                            .add_webpage_content(&url, page.content, page.published);
+                    }
+                    Err(e) => events.send(PipelineEvent::PageFailed {
+                        url,
+                        error: e.to_string(),
+                    }),
+                },
+                Some(chunk) = embeddings.next(), if !embeddings.is_empty() => match chunk.embedding {
+                    Ok(vector) => {
+                        next_id += 1;
+                        retriever.lock().await.insert(next_id, vector).await?;
+                        request
+                            .lock()
+                            .unwrap()
+                            .add_id_to_chunk(&chunk.content, &chunk.search_id, next_id);
+                        events.send(PipelineEvent::ChunkEmbedded {
+                            url: chunk.url,
+                            chunk_id: next_id,
+                        });
+                        embedded += 1;
+                    }
+                    Err(e) => tracing::warn!("Failed embedding chunk: {}", e),
+                },
+                _ = time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() && !deadline_passed => {
+                    deadline_passed = true;
+                }
+            }
+        }
+        tracing::Span::current().record("chunks", embedded);
+        Ok(())
+    }
+}
+
+// Mirrors the answer into `Token` events and sends `Done` once it is exhausted.
+// The `generate` span stays open until the stream is dropped.
+fn forward_tokens(
+    answer: TokenStream,
+    events: Events,
+    span: tracing::Span,
+    started: Instant,
+) -> TokenStream {
+    let done = events.clone();
+    let mut count = 0;
+    let tokens = answer.inspect(move |token| {
+        if let Ok(text) = token {
+            count += 1;
+            if count == 1 {
+                span.record("first_token_ms", started.elapsed().as_secs_f64() * 1000.0);
+            }
+            span.record("tokens", count);
+            events.send(PipelineEvent::Token { text: text.clone() });
+        }
+    });
+    let done = stream::once(async move { done.send(PipelineEvent::Done) })
+        .filter_map(|_| async { None::<Result<String>> });
+    Box::pin(tokens.chain(done))
+}
@@ -1,19 +1,19 @@
 use owo_colors::OwoColorize;
 
 pub fn print_green(s: &str) {
-    println!("{}", s.green().to_string());
+    println!("{}", s.green());
 }
 
 pub fn print_red(s: &str) {
-    println!("{}", s.red().to_string());
+    println!("{}", s.red());
 }
 
 pub fn print_blue(s: &str) {
-    println!("{}", s.blue().to_string());
+    println!("{}", s.blue());
 }
 
 pub fn print_yellow(s: &str) {
-    println!("{}", s.yellow().to_string());
+    println!("{}", s.yellow());
 }
 
 
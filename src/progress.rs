@@ -0,0 +1,96 @@
+use fyin::pretty_print;
+use fyin::PipelineEvent;
+use owo_colors::OwoColorize;
+use std::collections::HashSet;
+use std::io::{stdout, Write};
+use tokio::sync::mpsc::UnboundedReceiver;
+
+// Renders pipeline events as they arrive. Embedding and summarizing progress
+// is a single line that is rewritten in place; everything else gets its own line.
+pub async fn render(mut events: UnboundedReceiver<PipelineEvent>) {
+    let mut embedded_chunks = 0;
+    let mut embedded_pages = HashSet::new();
+    let mut in_progress_line = false;
+
+    while let Some(event) = events.recv().await {
+        let progress = matches!(
+            event,
+            PipelineEvent::ChunkEmbedded { .. } | PipelineEvent::SectionSummarized { .. }
+        );
+        if in_progress_line && !progress {
+            println!();
+            in_progress_line = false;
+        }
+
+        match event {
+            PipelineEvent::SearchStarted { query } => {
+                pretty_print::print_blue(&format!("Searching for: {}", query));
+            }
+            PipelineEvent::ResultFound { name, url } => {
+                pretty_print::print_yellow(&format!("Found: {} - {}", name, url));
+            }
+            PipelineEvent::PageScraped { url, bytes } => {
+                pretty_print::print_yellow(&format!("Scraped: {} ({} bytes)", url, bytes));
+            }
+            PipelineEvent::PageFailed { url, error } => {
+                pretty_print::print_red(&format!("Failed: {} ({})", url, error));
+            }
+            PipelineEvent::ChunkEmbedded { url, .. } => {
+                embedded_chunks += 1;
+                embedded_pages.insert(url);
+                print!(
+                    "\r{}",
+                    format!(
+                        "Embedded {} chunks from {} pages",
+                        embedded_chunks,
+                        embedded_pages.len()
+                    )
+                    .yellow()
+                );
+                stdout().flush().unwrap();
+                in_progress_line = true;
+            }
+            PipelineEvent::SectionSummarized { section, total, .. } => {
+                print!(
+                    "\r{}",
+                    format!("Summarized section {} of {}", section, total).yellow()
+                );
+                stdout().flush().unwrap();
+                in_progress_line = true;
+            }
+            PipelineEvent::InjectionDetected { url, patterns } => {
+                pretty_print::print_red(&format!(
+                    "Possible prompt injection: {} ({})",
+                    url,
+                    patterns.join(", ")
+                ));
+            }
+            PipelineEvent::ToolCalled {
+                name,
+                arguments,
+                result,
+            } => {
+                // search results run to many lines; their first says enough
+                let result = result.lines().next().unwrap_or_default();
+                pretty_print::print_blue(&format!("Tool {}({}): {}", name, arguments, result));
+            }
+            PipelineEvent::ResearchRound { round, questions } => {
+                pretty_print::print_blue(&format!("Research round {}:", round));
+                for question in questions {
+                    pretty_print::print_blue(&format!("  - {}", question));
+                }
+            }
+            PipelineEvent::RetrievalDone { chunks } => {
+                pretty_print::print_blue(&format!(
+                    "Retrieved {} chunks, answering...\n",
+                    chunks.len()
+                ));
+            }
+            PipelineEvent::Token { text } => {
+                print!("{}", text.green());
+                stdout().flush().unwrap();
+            }
+            PipelineEvent::Done => println!(),
+        }
+    }
+}
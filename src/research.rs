@@ -0,0 +1,141 @@
+//! Deep research: instead of one search, the chat model plans sub-questions,
+//! reads what they turn up, judges what is still unknown and searches again,
+//! round after round until it is satisfied or the budget runs out. Every page
+//! read goes into one index, and the final report draws on all of them.
+
+use crate::pipeline::PipelineOutput;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+const DEFAULT_ROUNDS: usize = 3;
+const DEFAULT_PAGES: usize = 30;
+const DEFAULT_TOKENS: usize = 20_000;
+const DEFAULT_TIME: Duration = Duration::from_secs(300);
+
+/// Rough token count of text, for budgeting without a tokenizer.
+pub fn estimate_tokens(text: &str) -> usize {
+    // about four characters per token for English text
+    text.len().div_ceil(4)
+}
+
+/// Limits on one research run. They are checked between rounds, so the last
+/// round can go over a little.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Budget {
+    /// Search-and-read rounds
+    pub rounds: usize,
+    /// Pages found across all rounds
+    pub pages: usize,
+    /// Estimated tokens the planning calls may use, prompts included
+    pub tokens: usize,
+    /// Wall-clock time before the report is written
+    pub time: Duration,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Budget {
+            rounds: DEFAULT_ROUNDS,
+            pages: DEFAULT_PAGES,
+            tokens: DEFAULT_TOKENS,
+            time: DEFAULT_TIME,
+        }
+    }
+}
+
+impl Budget {
+    /// The first limit that has been reached, if any.
+    pub fn exceeded(
+        &self,
+        rounds: usize,
+        pages: usize,
+        tokens: usize,
+        elapsed: Duration,
+    ) -> Option<Stop> {
+        if rounds >= self.rounds {
+            Some(Stop::Rounds)
+        } else if pages >= self.pages {
+            Some(Stop::Pages)
+        } else if tokens >= self.tokens {
+            Some(Stop::Tokens)
+        } else if elapsed >= self.time {
+            Some(Stop::Time)
+        } else {
+            None
+        }
+    }
+}
+
+/// Why research stopped and the report was written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stop {
+    /// The model found nothing left to search for
+    Answered,
+    Rounds,
+    Pages,
+    Tokens,
+    Time,
+}
+
+impl Stop {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Stop::Answered => "nothing left to find out",
+            Stop::Rounds => "round budget used up",
+            Stop::Pages => "page budget used up",
+            Stop::Tokens => "token budget used up",
+            Stop::Time => "time budget used up",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Round {
+    /// What this round searched for
+    pub questions: Vec<String>,
+    /// New pages the searches found
+    pub pages: usize,
+    /// Chunks in the index after the round
+    pub chunks: usize,
+}
+
+/// What each round searched for and found, and why research stopped.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Trace {
+    pub rounds: Vec<Round>,
+    pub stop: Stop,
+    /// Estimated tokens the planning calls used
+    pub tokens: usize,
+    pub elapsed: Duration,
+}
+
+impl Trace {
+    /// One block per round, then the totals.
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![];
+        for (i, round) in self.rounds.iter().enumerate() {
+            lines.push(format!(
+                "Round {}: {} new pages, {} chunks in total",
+                i + 1,
+                round.pages,
+                round.chunks
+            ));
+            lines.extend(round.questions.iter().map(|q| format!("  - {}", q)));
+        }
+        lines.push(format!(
+            "Stopped after {} rounds in {:.1}s ({}), using about {} planning tokens",
+            self.rounds.len(),
+            self.elapsed.as_secs_f64(),
+            self.stop.reason(),
+            self.tokens
+        ));
+        lines.join("\n")
+    }
+}
+
+/// The report as it streams in, and how the research went.
+pub struct Research {
+    pub output: PipelineOutput,
+    pub trace: Trace,
+}
@@ -1,11 +1,67 @@
-use crate::data::Request;
-use crate::pretty_print;
-use anyhow::{Error, Result};
-use futures::stream::{FuturesUnordered, StreamExt};
+use crate::data::{Request, SearchResult};
+use crate::dates;
+use crate::sanitize;
+use crate::url_policy::{PolicyResolver, UrlPolicy};
+use anyhow::{anyhow, Error, Result};
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use futures::future::{BoxFuture, FutureExt};
+use futures::stream::FuturesUnordered;
 use regex::Regex;
-use reqwest::Client;
-use scraper::{Html, Selector};
-use std::sync::{Arc, Mutex};
+use reqwest::header::{CONTENT_TYPE, LAST_MODIFIED};
+use reqwest::{Client, Url};
+use scraper::{ElementRef, Html, Selector};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::field::Empty;
+use tracing::Instrument;
+
+/// Readable text of a page, and when it was published or last updated.
+#[derive(Clone, Debug, Default)]
+pub struct Page {
+    pub content: String,
+    pub published: Option<NaiveDate>,
+}
+
+/// Scrape stage: fetches a page and extracts its readable text.
+#[async_trait]
+pub trait Scraper: Send + Sync {
+    async fn scrape(&self, url: &str) -> Result<Page>;
+}
+
+/// Fetches pages over HTTP, refusing any URL or redirect hop `policy` does not allow.
+pub struct WebScraper {
+    client: Client,
+    policy: Arc<UrlPolicy>,
+}
+
+impl WebScraper {
+    pub fn new(policy: UrlPolicy) -> Self {
+        let policy = Arc::new(policy);
+        let client = Client::builder()
+            .redirect(policy.redirect_policy())
+            .dns_resolver(Arc::new(PolicyResolver::new(policy.clone())))
+            .build()
+            .expect("Failed to build HTTP client");
+        WebScraper { client, policy }
+    }
+}
+
+impl Default for WebScraper {
+    fn default() -> Self {
+        WebScraper::new(UrlPolicy::default())
+    }
+}
+
+#[async_trait]
+impl Scraper for WebScraper {
+    async fn scrape(&self, url: &str) -> Result<Page> {
+        self.policy.check_url(&Url::parse(url)?)?;
+        fetch_url_content(&self.client, url).await
+    }
+}
 
 fn clean_text(text: &str) -> String {
     // Create a regex to match one or more whitespace characters
@@ -14,77 +70,215 @@ fn clean_text(text: &str) -> String {
     re.replace_all(text, " ").to_string()
 }
 
-fn fetch_and_extract_content(body: &str) -> Result<String> {
-    let document = Html::parse_document(&body);
+pub fn fetch_and_extract_content(body: &str) -> Result<String> {
+    let document = Html::parse_document(body);
     let selector_p =
         Selector::parse("body p, body h1, body h2, body h3, article p, div p, span p").unwrap();
 
     let mut main_text = String::new();
     for element in document.select(&selector_p) {
-        if let Some(text) = element.text().next() {
-            main_text.push_str(&clean_text(text));
-            main_text.push_str("\n");
+        if let Some(text) = visible_text(element) {
+            main_text.push_str(&clean_text(&sanitize::strip_invisible(text)));
+            main_text.push('\n');
         }
     }
 
-    log::debug!("Extracted content: {}", main_text);
+    tracing::debug!("Extracted content: {}", main_text);
 
     Ok(main_text)
 }
 
-// Function to fetch content from URL.
-async fn fetch_url_content(client: &Client, url: &str) -> Result<String> {
-    pretty_print::print_yellow(&format!("Scraping content from URL: {}", url));
-    let response = client.get(url).send().await.map_err(Error::new)?;
-    let full_text = response.text().await.map_err(Error::new)?;
-    let content = fetch_and_extract_content(&full_text)?;
-    Ok(content)
+/// Text and date of an HTML page; see `dates::published_date`.
+pub fn parse_page(body: &str) -> Result<Page> {
+    Ok(Page {
+        content: fetch_and_extract_content(body)?,
+        published: dates::published_date(body),
+    })
 }
 
-fn get_urls(request: Arc<Mutex<Request>>) -> Result<Vec<String>> {
-    let request = request.lock().unwrap();
-    let mut urls = vec![];
+// First text node of `element` that a browser would actually show.
+fn visible_text<'a>(element: ElementRef<'a>) -> Option<&'a str> {
+    element
+        .descendants()
+        .filter(|node| {
+            !node
+                .ancestors()
+                .filter_map(ElementRef::wrap)
+                .any(|ancestor| is_hidden(ancestor.value()))
+        })
+        .find_map(|node| node.value().as_text().map(|text| &**text))
+}
 
-    for (_, search_result) in request.search_map.iter() {
-        urls.push(search_result.url.clone());
-    }
-    Ok(urls)
-}
-
-// Function to process a list of URLs in parallel and collect their content
-pub async fn process_urls(request: Arc<Mutex<Request>>) -> Result<()> {
-    let client = Arc::new(Client::new());
-    // let semaphore = Arc::new(Semaphore::new(20)); // Limit to 10 concurrent requests.
-    let mut tasks: FuturesUnordered<tokio::task::JoinHandle<Result<()>>> = FuturesUnordered::new();
-    let urls = get_urls(request.clone())?;
-
-    for url in urls {
-        let client_ref = client.clone();
-        let request_clone = request.clone();
-        // let permit = semaphore.clone().acquire_owned().await.unwrap();
-        tasks.push(tokio::spawn(async move {
-            let webpage_content = fetch_url_content(&client_ref, &url).await;
-            // TODO: handle error here
-            let _ = webpage_content
-                .map(|content| {
-                    request_clone
-                        .lock()
-                        .unwrap()
-                        .add_webpage_content(&url, content);
-                })
-                .map_err(|e| {
-                    log::warn!("Failed fetching content for URL: {}, error: {}", url, e);
-                });
-            Ok(())
-        }));
+fn is_hidden(element: &scraper::node::Element) -> bool {
+    if element.attr("hidden").is_some() || element.attr("aria-hidden") == Some("true") {
+        return true;
     }
+    let style: String = element
+        .attr("style")
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_lowercase();
+    [
+        "display:none",
+        "visibility:hidden",
+        "font-size:0",
+        "opacity:0",
+    ]
+    .iter()
+    .any(|hidden| style.contains(hidden))
+}
+
+// Function to fetch content from URL. Last-Modified is only used when the
+// page itself declares no date, since servers often set it to the request time.
+async fn fetch_url_content(client: &Client, url: &str) -> Result<Page> {
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(Error::new)?;
+    let last_modified = response
+        .headers()
+        .get(LAST_MODIFIED)
+        .and_then(|value| value.to_str().ok())
+        .and_then(dates::parse_date);
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_lowercase();
+
+    let mut page = if content_type.starts_with("application/pdf") {
+        let bytes = response.bytes().await.map_err(Error::new)?;
+        Page {
+            content: pdf_text(bytes.to_vec()).await?,
+            published: None,
+        }
+    } else {
+        let body = response.text().await.map_err(Error::new)?;
+        // e.g. RFCs and READMEs have no markup to extract from, but some
+        // servers label HTML as plain text
+        if content_type.starts_with("text/plain") && !looks_like_html(&body) {
+            Page {
+                content: sanitize::strip_invisible(&body),
+                published: None,
+            }
+        } else {
+            parse_page(&body)?
+        }
+    };
+    page.published = page.published.or(last_modified);
+    Ok(page)
+}
+
+fn looks_like_html(body: &str) -> bool {
+    let start: String = body.trim_start().chars().take(15).collect();
+    let start = start.to_lowercase();
+    start.starts_with("<!doctype html") || start.starts_with("<html")
+}
+
+/// Text of a PDF. Extraction runs on the blocking pool, and a file that makes
+/// the parser panic is reported as an error.
+pub async fn pdf_text(bytes: Vec<u8>) -> Result<String> {
+    let text = tokio::task::spawn_blocking(move || pdf_extract::extract_text_from_mem(&bytes))
+        .await
+        .map_err(|_| anyhow!("PDF extraction panicked"))??;
+    Ok(sanitize::strip_invisible(&text))
+}
+
+/// A local document as a search result whose content is already filled in:
+/// PDFs and HTML are extracted like fetched pages, anything else is read as text.
+pub async fn read_file(path: &Path) -> Result<SearchResult> {
+    let path =
+        fs::canonicalize(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    let page = match extension.as_str() {
+        "pdf" => Page {
+            content: pdf_text(fs::read(&path)?).await?,
+            published: None,
+        },
+        "html" | "htm" => parse_page(&fs::read_to_string(&path)?)?,
+        _ => Page {
+            content: sanitize::strip_invisible(&fs::read_to_string(&path)?),
+            published: None,
+        },
+    };
+    let url = Url::from_file_path(&path)
+        .map_err(|_| anyhow!("Not an absolute path: {}", path.display()))?;
+    Ok(SearchResult {
+        name: path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| url.to_string()),
+        url: url.to_string(),
+        content: Some(page.content),
+        published: page.published,
+        language: None,
+    })
+}
+
+/// Search results that still need scraping, by url.
+pub fn pending_urls(request: &Request) -> Vec<String> {
+    let mut urls = vec![];
+    let embedded: HashSet<&String> = request.chunk_id_to_search_id.values().collect();
 
-    while let Some(result) = tasks.next().await {
-        match result {
-            Ok(_) => {}
-            Err(e) => return Err(anyhow::Error::new(e)),
+    for (url_hash, search_result) in request.search_map.iter() {
+        // sources loaded from a collection were scraped and embedded by an earlier query
+        if !embedded.contains(url_hash) && search_result.content.is_none() {
+            urls.push(search_result.url.clone());
         }
     }
+    urls
+}
+
+/// Search results whose provider already supplied the text, as (url, content).
+pub fn prefetched_pages(request: &Request) -> Vec<(String, String)> {
+    let embedded: HashSet<&String> = request.chunk_id_to_search_id.values().collect();
+    request
+        .search_map
+        .iter()
+        .filter(|(url_hash, _)| !embedded.contains(url_hash))
+        .filter_map(|(_, search_result)| {
+            let content = search_result.content.clone()?;
+            Some((search_result.url.clone(), content))
+        })
+        .collect()
+}
+
+pub type ScrapedPage = (String, Result<Page>);
 
-    Ok(())
+/// Scrapes all urls concurrently and yields each page as soon as it finishes,
+/// so later stages never wait on the slowest page. Dropping the stream cancels
+/// the scrapes still in flight.
+pub fn scrape_pages(
+    scraper: Arc<dyn Scraper>,
+    urls: Vec<String>,
+) -> FuturesUnordered<BoxFuture<'static, ScrapedPage>> {
+    urls.into_iter()
+        .map(|url| {
+            let scraper = scraper.clone();
+            let span = tracing::info_span!("scrape", url = %url, bytes = Empty);
+            async move {
+                let page = scraper.scrape(&url).await;
+                match &page {
+                    Ok(page) => {
+                        tracing::Span::current().record("bytes", page.content.len());
+                    }
+                    Err(e) => {
+                        tracing::warn!("Failed fetching content for URL: {}, error: {}", url, e)
+                    }
+                }
+                (url, page)
+            }
+            .instrument(span)
+            .boxed()
+        })
+        .collect()
 }
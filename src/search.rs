@@ -0,0 +1,788 @@
+use crate::data::{Request, SearchResult};
+use crate::dates;
+use crate::domains::{self, DomainPolicy};
+use crate::events::{Events, PipelineEvent};
+use crate::sanitize;
+use crate::scraper::Scraper;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures::future;
+use regex::Regex;
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::Value;
+use std::env;
+use std::sync::{Arc, Mutex, OnceLock};
+
+const DEFAULT_BING_ENDPOINT: &str = "https://api.bing.microsoft.com/v7.0/search";
+const DEFAULT_SEARXNG_ENDPOINT: &str = "https://searxng.example.com/search";
+const DEFAULT_DUCKDUCKGO_ENDPOINT: &str = "https://api.duckduckgo.com/";
+const DEFAULT_WIKIPEDIA_ENDPOINT: &str = "https://en.wikipedia.org/w/api.php";
+const DEFAULT_ARXIV_ENDPOINT: &str = "https://export.arxiv.org/api/query";
+
+/// Search stage: turns a query into a list of pages to scrape.
+#[async_trait]
+pub trait SearchProvider: Send + Sync {
+    async fn search(
+        &self,
+        query: &str,
+        count: usize,
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>>;
+}
+
+/// Per-query search settings. Each provider translates them into the engine's
+/// own parameters and operators where it has them; site filters are also
+/// enforced locally.
+#[derive(Clone, Debug, Default)]
+pub struct SearchOptions {
+    /// Only return results from these domains (`--site`)
+    pub sites: Vec<String>,
+    /// Never return results from these domains (`--exclude-site`)
+    pub exclude_sites: Vec<String>,
+    /// Market/language as `language-REGION`, e.g. `en-US` or `de-DE`
+    pub market: Option<String>,
+    /// Only results published within this period
+    pub time_range: Option<TimeRange>,
+    pub safe_search: Option<SafeSearch>,
+    /// Number of results to skip, for paging
+    pub offset: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum TimeRange {
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum SafeSearch {
+    Off,
+    Moderate,
+    Strict,
+}
+
+/// Where results come from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Source {
+    /// Pick per query with `route`, falling back to the web
+    #[default]
+    Auto,
+    /// The engine named by SEARCH_ENGINE
+    Web,
+    /// Wikipedia article extracts
+    Wiki,
+    /// arXiv abstracts and paper text
+    Arxiv,
+}
+
+impl SearchOptions {
+    /// Defaults from SEARCH_MARKET, SEARCH_TIME_RANGE (day, week, month, year)
+    /// and SEARCH_SAFE_SEARCH (off, moderate, strict).
+    pub fn from_env() -> Result<Self> {
+        Ok(SearchOptions {
+            market: env_var("SEARCH_MARKET"),
+            time_range: env_var("SEARCH_TIME_RANGE")
+                .map(|value| parse_enum("SEARCH_TIME_RANGE", &value))
+                .transpose()?,
+            safe_search: env_var("SEARCH_SAFE_SEARCH")
+                .map(|value| parse_enum("SEARCH_SAFE_SEARCH", &value))
+                .transpose()?,
+            ..Default::default()
+        })
+    }
+
+    pub fn allows(&self, url: &str) -> bool {
+        let Some(host) = domains::host(url) else {
+            return false;
+        };
+        !domains::matches_any(&host, &self.exclude_sites)
+            && (self.sites.is_empty() || domains::matches_any(&host, &self.sites))
+    }
+}
+
+/// `query` with `site:` and `-site:` operators, for engines that support them.
+pub fn with_site_operators(query: &str, options: &SearchOptions) -> String {
+    let mut query = query.to_string();
+    let sites: Vec<String> = options
+        .sites
+        .iter()
+        .map(|site| format!("site:{}", site))
+        .collect();
+    match sites.len() {
+        0 => {}
+        1 => query.push_str(&format!(" {}", sites[0])),
+        _ => query.push_str(&format!(" ({})", sites.join(" OR "))),
+    }
+    for site in &options.exclude_sites {
+        query.push_str(&format!(" -site:{}", site));
+    }
+    query
+}
+
+fn env_or(name: &str, default: &str) -> String {
+    env_var(name).unwrap_or_else(|| default.to_string())
+}
+
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.trim().is_empty())
+}
+
+fn parse_enum<T: clap::ValueEnum>(name: &str, value: &str) -> Result<T> {
+    T::from_str(value.trim(), true).map_err(|_| anyhow!("Invalid {}: {}", name, value))
+}
+
+/// Provider for SEARCH_SOURCE (auto, web, wiki or arxiv; default auto).
+/// arXiv PDFs are fetched with `scraper`.
+pub fn from_env(scraper: Arc<dyn Scraper>) -> Result<Arc<dyn SearchProvider>> {
+    let source = env_var("SEARCH_SOURCE")
+        .map(|value| parse_enum("SEARCH_SOURCE", &value))
+        .transpose()?
+        .unwrap_or_default();
+    for_source(source, scraper)
+}
+
+/// Provider for `source`, with endpoints from WIKIPEDIA_ENDPOINT and
+/// ARXIV_ENDPOINT so a mirror can stand in. arXiv PDFs are fetched with `scraper`.
+pub fn for_source(source: Source, scraper: Arc<dyn Scraper>) -> Result<Arc<dyn SearchProvider>> {
+    let wikipedia = || Wikipedia::new(&env_or("WIKIPEDIA_ENDPOINT", DEFAULT_WIKIPEDIA_ENDPOINT));
+    let arxiv = || Arxiv::new(&env_or("ARXIV_ENDPOINT", DEFAULT_ARXIV_ENDPOINT), scraper);
+    let provider: Arc<dyn SearchProvider> = match source {
+        Source::Web => web_from_env()?,
+        Source::Wiki => Arc::new(wikipedia()),
+        Source::Arxiv => Arc::new(arxiv()),
+        Source::Auto => Arc::new(Router {
+            web: web_from_env()?,
+            wikipedia: Arc::new(wikipedia()),
+            arxiv: Arc::new(arxiv()),
+        }),
+    };
+    Ok(provider)
+}
+
+/// Whether `web_from_env` picks Bing, the only engine that needs a key.
+pub fn uses_bing() -> bool {
+    !matches!(search_engine().as_str(), "searxng" | "duckduckgo")
+}
+
+fn search_engine() -> String {
+    env::var("SEARCH_ENGINE").unwrap_or_else(|_| "bing".to_string())
+}
+
+/// Picks the web engine named by SEARCH_ENGINE (bing, searxng or duckduckgo).
+pub fn web_from_env() -> Result<Arc<dyn SearchProvider>> {
+    let provider: Arc<dyn SearchProvider> = match search_engine().as_str() {
+        "searxng" => Arc::new(Searxng::new(&env_or(
+            "SEARXNG_ENDPOINT",
+            DEFAULT_SEARXNG_ENDPOINT,
+        ))),
+        "duckduckgo" => Arc::new(DuckDuckGo::new(&env_or(
+            "DUCKDUCKGO_ENDPOINT",
+            DEFAULT_DUCKDUCKGO_ENDPOINT,
+        ))),
+        _ => Arc::new(Bing::new(
+            &env_or("BING_ENDPOINT", DEFAULT_BING_ENDPOINT),
+            &env::var("BING_SUBSCRIPTION_KEY")?,
+        )),
+    };
+    Ok(provider)
+}
+
+async fn get_json(
+    client: &Client,
+    endpoint: &str,
+    params: &[(&str, String)],
+    headers: HeaderMap,
+) -> Result<Value> {
+    let response = client
+        .get(endpoint)
+        .headers(headers)
+        .query(params)
+        .send()
+        .await?;
+
+    if response.status().is_success() {
+        let json: Value = response.json().await?;
+        tracing::debug!(
+            "JSON result from search: {}",
+            serde_json::to_string_pretty(&json)?
+        );
+        Ok(json)
+    } else {
+        Err(anyhow!(
+            "Request failed with status code: {}",
+            response.status()
+        ))
+    }
+}
+
+fn to_search_result(name: &Value, url: &Value) -> Option<SearchResult> {
+    Some(SearchResult {
+        name: name.as_str()?.to_string(),
+        url: url.as_str()?.to_string(),
+        content: None,
+        published: None,
+        language: None,
+    })
+}
+
+// SearXNG pages rather than offsets; most instances return about this many per page
+const SEARXNG_PAGE_SIZE: usize = 10;
+
+impl TimeRange {
+    fn days(self) -> i64 {
+        match self {
+            TimeRange::Day => 1,
+            TimeRange::Week => 7,
+            TimeRange::Month => 31,
+            TimeRange::Year => 365,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            TimeRange::Day => "day",
+            TimeRange::Week => "week",
+            TimeRange::Month => "month",
+            TimeRange::Year => "year",
+        }
+    }
+}
+
+pub struct Bing {
+    client: Client,
+    endpoint: String,
+    subscription_key: String,
+}
+
+impl Bing {
+    pub fn new(endpoint: &str, subscription_key: &str) -> Self {
+        Bing {
+            client: Client::new(),
+            endpoint: endpoint.to_string(),
+            subscription_key: subscription_key.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl SearchProvider for Bing {
+    async fn search(
+        &self,
+        query: &str,
+        count: usize,
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>> {
+        let market = options.market.as_deref().unwrap_or("en-US");
+        let mut params = vec![
+            ("mkt", market.to_string()),
+            ("q", with_site_operators(query, options)),
+            ("count", count.to_string()),
+        ];
+        if options.offset > 0 {
+            params.push(("offset", options.offset.to_string()));
+        }
+        if let Some(time_range) = options.time_range {
+            let freshness = match time_range {
+                TimeRange::Day => "Day".to_string(),
+                TimeRange::Week => "Week".to_string(),
+                TimeRange::Month => "Month".to_string(),
+                // Bing has no "Year", but takes a date range
+                TimeRange::Year => {
+                    let today = chrono::Utc::now().date_naive();
+                    let start = today - chrono::Duration::days(time_range.days());
+                    format!("{}..{}", start, today)
+                }
+            };
+            params.push(("freshness", freshness));
+        }
+        if let Some(safe_search) = options.safe_search {
+            let level = match safe_search {
+                SafeSearch::Off => "Off",
+                SafeSearch::Moderate => "Moderate",
+                SafeSearch::Strict => "Strict",
+            };
+            params.push(("safeSearch", level.to_string()));
+        }
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Ocp-Apim-Subscription-Key",
+            HeaderValue::from_str(&self.subscription_key)?,
+        );
+
+        let json = get_json(&self.client, &self.endpoint, &params, headers).await?;
+        let results = json["webPages"]["value"]
+            .as_array()
+            .map(|pages| {
+                pages
+                    .iter()
+                    .filter_map(|wp| to_search_result(&wp["name"], &wp["url"]))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(results)
+    }
+}
+
+pub struct Searxng {
+    client: Client,
+    endpoint: String,
+}
+
+impl Searxng {
+    pub fn new(endpoint: &str) -> Self {
+        Searxng {
+            client: Client::new(),
+            endpoint: endpoint.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl SearchProvider for Searxng {
+    async fn search(
+        &self,
+        query: &str,
+        count: usize,
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>> {
+        // SearXNG hands the operators on to its upstream engines
+        let mut params = vec![
+            ("q", with_site_operators(query, options)),
+            ("format", "json".to_string()),
+        ];
+        if let Some(market) = &options.market {
+            params.push(("language", market.clone()));
+        }
+        if let Some(time_range) = options.time_range {
+            params.push(("time_range", time_range.name().to_string()));
+        }
+        if let Some(safe_search) = options.safe_search {
+            let level = match safe_search {
+                SafeSearch::Off => "0",
+                SafeSearch::Moderate => "1",
+                SafeSearch::Strict => "2",
+            };
+            params.push(("safesearch", level.to_string()));
+        }
+        let page = options.offset / SEARXNG_PAGE_SIZE;
+        if page > 0 {
+            params.push(("pageno", (page + 1).to_string()));
+        }
+
+        let json = get_json(&self.client, &self.endpoint, &params, HeaderMap::new()).await?;
+        let results = json["results"]
+            .as_array()
+            .map(|results| {
+                results
+                    .iter()
+                    .filter_map(|r| to_search_result(&r["title"], &r["url"]))
+                    .skip(options.offset % SEARXNG_PAGE_SIZE)
+                    .take(count)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(results)
+    }
+}
+
+pub struct DuckDuckGo {
+    client: Client,
+    endpoint: String,
+}
+
+impl DuckDuckGo {
+    pub fn new(endpoint: &str) -> Self {
+        DuckDuckGo {
+            client: Client::new(),
+            endpoint: endpoint.to_string(),
+        }
+    }
+}
+
+// DuckDuckGo regions are `region-language`: en-US becomes us-en
+fn duckduckgo_region(market: &str) -> String {
+    match market.split_once(['-', '_']) {
+        Some((language, region)) => format!("{}-{}", region, language).to_lowercase(),
+        None => market.to_lowercase(),
+    }
+}
+
+// Related topics are either results or groups with their own "Topics" list
+fn collect_topics(topics: &Value, results: &mut Vec<SearchResult>) {
+    for topic in topics.as_array().into_iter().flatten() {
+        if topic["Topics"].is_array() {
+            collect_topics(&topic["Topics"], results);
+        } else if let Some(result) = to_search_result(&topic["Text"], &topic["FirstURL"]) {
+            results.push(result);
+        }
+    }
+}
+
+#[async_trait]
+impl SearchProvider for DuckDuckGo {
+    // the Instant Answer API has no site operators or paging; fetch_web_pages
+    // filters sites and the offset is applied here
+    async fn search(
+        &self,
+        query: &str,
+        count: usize,
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>> {
+        let mut params = vec![("q", query.to_string()), ("format", "json".to_string())];
+        if let Some(market) = &options.market {
+            params.push(("kl", duckduckgo_region(market)));
+        }
+        if let Some(time_range) = options.time_range {
+            params.push(("df", time_range.name()[..1].to_string()));
+        }
+        if let Some(safe_search) = options.safe_search {
+            let level = match safe_search {
+                SafeSearch::Off => "-2",
+                SafeSearch::Moderate => "-1",
+                SafeSearch::Strict => "1",
+            };
+            params.push(("kp", level.to_string()));
+        }
+        let json = get_json(&self.client, &self.endpoint, &params, HeaderMap::new()).await?;
+
+        let mut results = vec![];
+        collect_topics(&json["Results"], &mut results);
+        collect_topics(&json["RelatedTopics"], &mut results);
+        Ok(results
+            .into_iter()
+            .skip(options.offset)
+            .take(count)
+            .collect())
+    }
+}
+
+/// Searches a MediaWiki API and returns each article's plain-text extract as
+/// its content, so the pages need no scraping.
+pub struct Wikipedia {
+    client: Client,
+    endpoint: String,
+}
+
+impl Wikipedia {
+    pub fn new(endpoint: &str) -> Self {
+        Wikipedia {
+            client: Client::new(),
+            endpoint: endpoint.to_string(),
+        }
+    }
+
+    async fn article(&self, title: &str) -> Result<SearchResult> {
+        let params = [
+            ("action", "query".to_string()),
+            ("prop", "extracts|info".to_string()),
+            ("explaintext", "1".to_string()),
+            ("inprop", "url".to_string()),
+            ("redirects", "1".to_string()),
+            ("titles", title.to_string()),
+            ("format", "json".to_string()),
+            ("formatversion", "2".to_string()),
+        ];
+        let json = get_json(&self.client, &self.endpoint, &params, HeaderMap::new()).await?;
+        let page = &json["query"]["pages"][0];
+        let mut result = to_search_result(&page["title"], &page["fullurl"])
+            .ok_or_else(|| anyhow!("No article for {}", title))?;
+        let extract = page["extract"].as_str().unwrap_or_default();
+        result.content = Some(sanitize::strip_invisible(extract));
+        result.published = page["touched"].as_str().and_then(dates::parse_date);
+        Ok(result)
+    }
+}
+
+#[async_trait]
+impl SearchProvider for Wikipedia {
+    // one encyclopedia: site filters, market and dates do not apply
+    async fn search(
+        &self,
+        query: &str,
+        count: usize,
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>> {
+        let params = [
+            ("action", "query".to_string()),
+            ("list", "search".to_string()),
+            ("srsearch", query.to_string()),
+            ("srlimit", count.to_string()),
+            ("sroffset", options.offset.to_string()),
+            ("format", "json".to_string()),
+            ("formatversion", "2".to_string()),
+        ];
+        let json = get_json(&self.client, &self.endpoint, &params, HeaderMap::new()).await?;
+        let titles: Vec<&str> = json["query"]["search"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|hit| hit["title"].as_str())
+            .collect();
+
+        let articles = future::join_all(titles.iter().map(|title| self.article(title))).await;
+        Ok(articles
+            .into_iter()
+            .filter_map(|article| {
+                article
+                    .inspect_err(|e| tracing::warn!("Failed fetching Wikipedia article: {}", e))
+                    .ok()
+            })
+            .collect())
+    }
+}
+
+// Paper text past this point is dropped; the abstract and introduction carry
+// most of what an answer needs
+const MAX_PDF_CHARS: usize = 50_000;
+
+// Question words that would make an arXiv AND-query match nothing
+const ARXIV_STOP_WORDS: &str = "a about an and are arxiv does for how in is of on paper papers \
+     preprint preprints recent research the to what which with";
+
+/// Searches the arXiv API and returns each paper's abstract followed by the
+/// text of its PDF as content. PDFs are fetched with `scraper`, so they obey
+/// the same URL policy as any page; one that fails leaves just the abstract.
+pub struct Arxiv {
+    client: Client,
+    endpoint: String,
+    scraper: Arc<dyn Scraper>,
+}
+
+#[derive(Deserialize)]
+struct ArxivFeed {
+    #[serde(rename = "entry", default)]
+    entries: Vec<ArxivEntry>,
+}
+
+#[derive(Deserialize)]
+struct ArxivEntry {
+    id: String,
+    title: String,
+    summary: String,
+    published: Option<String>,
+    updated: Option<String>,
+    #[serde(rename = "link", default)]
+    links: Vec<ArxivLink>,
+}
+
+#[derive(Deserialize)]
+struct ArxivLink {
+    #[serde(rename = "@href")]
+    href: String,
+    #[serde(rename = "@title")]
+    title: Option<String>,
+}
+
+impl Arxiv {
+    pub fn new(endpoint: &str, scraper: Arc<dyn Scraper>) -> Self {
+        Arxiv {
+            client: Client::new(),
+            endpoint: endpoint.to_string(),
+            scraper,
+        }
+    }
+
+    async fn paper(&self, entry: ArxivEntry) -> SearchResult {
+        let mut content = collapse_whitespace(&entry.summary);
+        let pdf = entry
+            .links
+            .iter()
+            .find(|link| link.title.as_deref() == Some("pdf"));
+        if let Some(pdf) = pdf {
+            match self.pdf_text(&pdf.href).await {
+                Ok(text) => {
+                    content.push('\n');
+                    content.push_str(&text);
+                }
+                Err(e) => tracing::warn!("Failed reading PDF {}: {}", pdf.href, e),
+            }
+        }
+        SearchResult {
+            name: collapse_whitespace(&entry.title),
+            url: entry.id,
+            content: Some(sanitize::strip_invisible(&content)),
+            published: entry
+                .updated
+                .or(entry.published)
+                .as_deref()
+                .and_then(dates::parse_date),
+            language: None,
+        }
+    }
+
+    async fn pdf_text(&self, url: &str) -> Result<String> {
+        let text = self.scraper.scrape(url).await?.content;
+        if text.trim().is_empty() {
+            return Err(anyhow!("No text in the PDF"));
+        }
+        Ok(text.chars().take(MAX_PDF_CHARS).collect())
+    }
+}
+
+/// `query` in arXiv's syntax: every remaining word must match, within the time range.
+pub fn arxiv_query(query: &str, time_range: Option<TimeRange>) -> String {
+    let terms: Vec<String> = query
+        .split(|c: char| !c.is_alphanumeric() && c != '-')
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .filter(|word| !ARXIV_STOP_WORDS.split_whitespace().any(|stop| stop == word))
+        .map(|word| format!("all:{}", word))
+        .collect();
+    let mut search_query = terms.join(" AND ");
+    if let Some(time_range) = time_range {
+        let now = chrono::Utc::now();
+        let start = now - chrono::Duration::days(time_range.days());
+        search_query.push_str(&format!(
+            " AND submittedDate:[{} TO {}]",
+            start.format("%Y%m%d%H%M"),
+            now.format("%Y%m%d%H%M")
+        ));
+    }
+    search_query
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[async_trait]
+impl SearchProvider for Arxiv {
+    async fn search(
+        &self,
+        query: &str,
+        count: usize,
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>> {
+        let params = [
+            ("search_query", arxiv_query(query, options.time_range)),
+            ("start", options.offset.to_string()),
+            ("max_results", count.to_string()),
+            ("sortBy", "relevance".to_string()),
+        ];
+        let response = self
+            .client
+            .get(&self.endpoint)
+            .query(&params)
+            .send()
+            .await?
+            .error_for_status()?;
+        let feed: ArxivFeed = quick_xml::de::from_str(&response.text().await?)?;
+        Ok(future::join_all(feed.entries.into_iter().map(|entry| self.paper(entry))).await)
+    }
+}
+
+const ARXIV_INTENT: &str =
+    r"(?i)\b(arxiv|papers?|preprints?|publications?|literature review|state of the art|sota)\b";
+const WIKI_INTENT: &str =
+    r"(?i)\b(wikipedia|encyclopedia|biography|who (was|were)|history of|definition of|define)\b";
+
+/// The source a query's wording asks for: academic questions go to arXiv,
+/// encyclopedic ones to Wikipedia, everything else to the web.
+pub fn route(query: &str) -> Source {
+    static INTENTS: OnceLock<(Regex, Regex)> = OnceLock::new();
+    let (arxiv, wiki) = INTENTS.get_or_init(|| {
+        (
+            Regex::new(ARXIV_INTENT).unwrap(),
+            Regex::new(WIKI_INTENT).unwrap(),
+        )
+    });
+    if arxiv.is_match(query) {
+        Source::Arxiv
+    } else if wiki.is_match(query) {
+        Source::Wiki
+    } else {
+        Source::Web
+    }
+}
+
+/// Sends each query to the source `route` picks. Site filters always go to
+/// the web, and so does a query the chosen source fails or finds nothing for.
+pub struct Router {
+    pub web: Arc<dyn SearchProvider>,
+    pub wikipedia: Arc<dyn SearchProvider>,
+    pub arxiv: Arc<dyn SearchProvider>,
+}
+
+#[async_trait]
+impl SearchProvider for Router {
+    async fn search(
+        &self,
+        query: &str,
+        count: usize,
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>> {
+        let source = if options.sites.is_empty() {
+            route(query)
+        } else {
+            Source::Web
+        };
+        let provider = match source {
+            Source::Wiki => &self.wikipedia,
+            Source::Arxiv => &self.arxiv,
+            _ => &self.web,
+        };
+        tracing::info!("Routing query to {:?}", source);
+        if source != Source::Web {
+            match provider.search(query, count, options).await {
+                Ok(results) if !results.is_empty() => return Ok(results),
+                Ok(_) => tracing::info!("No {:?} results, searching the web", source),
+                Err(e) => tracing::warn!("{:?} search failed, searching the web: {}", source, e),
+            }
+        }
+        self.web.search(query, count, options).await
+    }
+}
+
+/// Searches for `query`, which may differ from the request's own query (a
+/// translation, say), and adds the allowed results to the request.
+#[tracing::instrument(name = "search", skip_all, fields(query = %query, results))]
+pub async fn fetch_web_pages(
+    provider: &dyn SearchProvider,
+    request: Arc<Mutex<Request>>,
+    query: &str,
+    search_count: usize,
+    options: &SearchOptions,
+    domains: &DomainPolicy,
+    events: &Events,
+) -> Result<()> {
+    events.send(PipelineEvent::SearchStarted {
+        query: query.to_string(),
+    });
+    let results = search_allowed(provider, query, search_count, options, domains).await?;
+    tracing::Span::current().record("results", results.len());
+
+    let mut request = request.lock().unwrap();
+    for result in results {
+        events.send(PipelineEvent::ResultFound {
+            name: result.name.clone(),
+            url: result.url.clone(),
+        });
+        request.add_search_result(result);
+    }
+    Ok(())
+}
+
+/// Searches and drops results the site filters or domain policy exclude.
+pub async fn search_allowed(
+    provider: &dyn SearchProvider,
+    query: &str,
+    search_count: usize,
+    options: &SearchOptions,
+    domains: &DomainPolicy,
+) -> Result<Vec<SearchResult>> {
+    let results = provider.search(query, search_count, options).await?;
+    Ok(results
+        .into_iter()
+        .filter(|result| {
+            let allowed = options.allows(&result.url) && domains.is_allowed(&result.url);
+            if !allowed {
+                tracing::info!("Skipping result from excluded domain: {}", result.url);
+            }
+            allowed
+        })
+        .collect())
+}
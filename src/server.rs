@@ -0,0 +1,366 @@
+//! OpenAI-compatible HTTP API, so chat UIs and editor plugins can use fyin as
+//! if it were a model.
+//!
+//! `POST /v1/chat/completions` runs the last user message through the
+//! pipeline; earlier messages are ignored. Besides the usual fields, responses
+//! and stream chunks carry `citations`: the URL of each retrieved chunk, so
+//! `citations[0]` is `[1]` in the answer. Requests with `"follow_ups": true`
+//! also get `follow_ups`, related questions to ask next, in the response or
+//! the last stream chunk; they cost another model call, so they are opt-in.
+//!
+//! The web UI in `ui/` is embedded in the binary and served from `/`. It asks
+//! through `GET /api/ask?q=`, which streams every `PipelineEvent` as JSON and
+//! then a `follow_ups` event.
+
+use crate::events::PipelineEvent;
+use crate::pipeline::{Answer, Pipeline, RunOptions};
+use anyhow::Result;
+use axum::extract::{Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::sse::{Event, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::env;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tower_http::services::ServeDir;
+
+/// Model name reported when the request does not name one.
+pub const MODEL: &str = "fyin";
+
+const UI: [(&str, &str, &str); 3] = [
+    (
+        "/",
+        "text/html; charset=utf-8",
+        include_str!("../ui/index.html"),
+    ),
+    (
+        "/app.js",
+        "text/javascript; charset=utf-8",
+        include_str!("../ui/app.js"),
+    ),
+    (
+        "/style.css",
+        "text/css; charset=utf-8",
+        include_str!("../ui/style.css"),
+    ),
+];
+
+/// The API and the web UI. Set FYIN_UI_DIR to serve the UI from that
+/// directory instead of the embedded copy, e.g. while editing it.
+pub fn router(pipeline: Arc<Pipeline>) -> Router {
+    let mut router = Router::new()
+        .route("/v1/chat/completions", post(chat_completions))
+        .route("/v1/models", get(models))
+        .route("/api/ask", get(ask));
+    match env::var("FYIN_UI_DIR") {
+        Ok(dir) if !dir.trim().is_empty() => {
+            router = router.fallback_service(ServeDir::new(dir));
+        }
+        _ => {
+            for (path, content_type, body) in UI {
+                router = router.route(
+                    path,
+                    get(move || async move { ([(header::CONTENT_TYPE, content_type)], body) }),
+                );
+            }
+        }
+    }
+    router.with_state(pipeline)
+}
+
+pub async fn serve(listener: TcpListener, pipeline: Pipeline) -> Result<()> {
+    axum::serve(listener, router(Arc::new(pipeline))).await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct ChatRequest {
+    model: Option<String>,
+    messages: Vec<Message>,
+    #[serde(default)]
+    stream: bool,
+    /// Suggest related questions, at the cost of another model call
+    #[serde(default)]
+    follow_ups: bool,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    role: String,
+    content: Option<Content>,
+}
+
+// Either a plain string or a list of parts, of which only text is used
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Content {
+    Text(String),
+    Parts(Vec<Part>),
+}
+
+#[derive(Deserialize)]
+struct Part {
+    text: Option<String>,
+}
+
+impl Content {
+    fn text(&self) -> String {
+        match self {
+            Content::Text(text) => text.clone(),
+            Content::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| part.text.as_deref())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Completion {
+    id: String,
+    object: &'static str,
+    created: i64,
+    model: String,
+    choices: Vec<Choice>,
+    citations: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    follow_ups: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct Choice {
+    index: usize,
+    message: AssistantMessage,
+    finish_reason: &'static str,
+}
+
+#[derive(Serialize)]
+struct AssistantMessage {
+    role: &'static str,
+    content: String,
+}
+
+#[derive(Serialize)]
+struct CompletionChunk<'a> {
+    id: &'a str,
+    object: &'static str,
+    created: i64,
+    model: &'a str,
+    choices: [ChunkChoice; 1],
+    citations: &'a [String],
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    follow_ups: &'a [String],
+}
+
+#[derive(Serialize)]
+struct ChunkChoice {
+    index: usize,
+    delta: Delta,
+    finish_reason: Option<&'static str>,
+}
+
+#[derive(Default, Serialize)]
+struct Delta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AskParams {
+    q: String,
+}
+
+// Streams the run's events, then `follow_ups`, or an `error` event if it failed
+async fn ask(State(pipeline): State<Arc<Pipeline>>, Query(params): Query<AskParams>) -> Response {
+    let (sender, receiver) = mpsc::unbounded_channel::<Result<Event, Infallible>>();
+    tokio::spawn(async move {
+        let (events, mut pipeline_events) = mpsc::unbounded_channel::<PipelineEvent>();
+        let run = async {
+            let options = RunOptions {
+                events: Some(events),
+                ..Default::default()
+            };
+            pipeline.run_with(&params.q, options).await?.collect().await
+        };
+        // ends once the run has dropped every sender
+        let forward = async {
+            while let Some(event) = pipeline_events.recv().await {
+                let data = serde_json::to_string(&event).unwrap();
+                let _ = sender.send(Ok(Event::default().data(data)));
+            }
+        };
+        let data = match tokio::join!(run, forward) {
+            (Ok(answer), ()) => {
+                let questions = pipeline.follow_ups(&answer).await;
+                serde_json::json!({ "type": "follow_ups", "questions": questions })
+            }
+            (Err(e), ()) => {
+                tracing::error!("Query from the web UI failed: {:#}", e);
+                serde_json::json!({ "type": "error", "message": format!("{:#}", e) })
+            }
+        };
+        let _ = sender.send(Ok(Event::default().data(data.to_string())));
+    });
+    Sse::new(UnboundedReceiverStream::new(receiver)).into_response()
+}
+
+async fn models() -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "object": "list",
+        "data": [{ "id": MODEL, "object": "model", "owned_by": MODEL }]
+    }))
+}
+
+async fn chat_completions(
+    State(pipeline): State<Arc<Pipeline>>,
+    Json(request): Json<ChatRequest>,
+) -> Response {
+    let query = request
+        .messages
+        .iter()
+        .rev()
+        .find(|message| message.role == "user")
+        .and_then(|message| message.content.as_ref())
+        .map(Content::text)
+        .filter(|text| !text.trim().is_empty());
+    let Some(query) = query else {
+        return error(
+            StatusCode::BAD_REQUEST,
+            "invalid_request_error",
+            "messages must include a non-empty user message",
+        );
+    };
+
+    let output = match pipeline.run(&query).await {
+        Ok(output) => output,
+        Err(e) => {
+            tracing::error!("Chat completion failed: {:#}", e);
+            return error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "server_error",
+                &format!("{:#}", e),
+            );
+        }
+    };
+
+    let id = format!("chatcmpl-{}", uuid::Uuid::new_v4().simple());
+    let created = chrono::Utc::now().timestamp();
+    let model = request.model.unwrap_or_else(|| MODEL.to_string());
+    let citations: Vec<String> = output.chunks.iter().map(|c| c.url.clone()).collect();
+    let follow_ups = request.follow_ups;
+
+    if !request.stream {
+        return match output.collect().await {
+            Ok(answer) => Json(Completion {
+                id,
+                object: "chat.completion",
+                created,
+                model,
+                follow_ups: if follow_ups {
+                    pipeline.follow_ups(&answer).await
+                } else {
+                    vec![]
+                },
+                choices: vec![Choice {
+                    index: 0,
+                    message: AssistantMessage {
+                        role: "assistant",
+                        content: answer.text,
+                    },
+                    finish_reason: "stop",
+                }],
+                citations,
+            })
+            .into_response(),
+            Err(e) => error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "server_error",
+                &format!("{:#}", e),
+            ),
+        };
+    }
+
+    let (sender, receiver) = mpsc::unbounded_channel::<Result<Event, Infallible>>();
+    let mut tokens = output.answer;
+    let mut answer = Answer {
+        query: output.query,
+        text: String::new(),
+        sources: output.sources,
+        chunks: output.chunks,
+    };
+    tokio::spawn(async move {
+        let chunk = |delta: Delta, finish_reason: Option<&'static str>, follow_ups: &[String]| {
+            serde_json::to_string(&CompletionChunk {
+                id: &id,
+                object: "chat.completion.chunk",
+                created,
+                model: &model,
+                choices: [ChunkChoice {
+                    index: 0,
+                    delta,
+                    finish_reason,
+                }],
+                citations: &citations,
+                follow_ups,
+            })
+            .unwrap()
+        };
+        // stops early once the client has gone away
+        let send = |data: String| sender.send(Ok(Event::default().data(data))).is_ok();
+
+        let role = Delta {
+            role: Some("assistant"),
+            ..Default::default()
+        };
+        if !send(chunk(role, None, &[])) {
+            return;
+        }
+        while let Some(token) = tokens.next().await {
+            let data = match token {
+                Ok(text) => {
+                    answer.text.push_str(&text);
+                    let delta = Delta {
+                        content: Some(text),
+                        ..Default::default()
+                    };
+                    chunk(delta, None, &[])
+                }
+                Err(e) => {
+                    tracing::error!("Chat completion stream failed: {:#}", e);
+                    send(error_body("server_error", &format!("{:#}", e)).to_string());
+                    return;
+                }
+            };
+            if !send(data) {
+                return;
+            }
+        }
+        let follow_ups = if follow_ups {
+            pipeline.follow_ups(&answer).await
+        } else {
+            vec![]
+        };
+        send(chunk(Delta::default(), Some("stop"), &follow_ups));
+        send("[DONE]".to_string());
+    });
+
+    Sse::new(UnboundedReceiverStream::new(receiver)).into_response()
+}
+
+fn error_body(kind: &str, message: &str) -> serde_json::Value {
+    serde_json::json!({ "error": { "message": message, "type": kind } })
+}
+
+fn error(status: StatusCode, kind: &str, message: &str) -> Response {
+    (status, Json(error_body(kind, message))).into_response()
+}
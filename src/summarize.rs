@@ -0,0 +1,92 @@
+//! Map-reduce summaries of whole documents, for questions that need every
+//! section rather than the top-k chunks.
+//!
+//! Every section (chunk) is summarized on its own and cited by its number,
+//! then the summaries are merged `fan_in` at a time until one final merge is
+//! left, which is streamed back as the answer.
+
+use crate::data::Chunk;
+use crate::events::{Events, PipelineEvent};
+use crate::llm::{self, ChatProvider, TokenStream};
+use anyhow::Result;
+use futures::{stream, StreamExt, TryStreamExt};
+use std::collections::HashMap;
+
+pub const DEFAULT_FAN_IN: usize = 8;
+// section summaries requested from the chat provider at once
+const MAP_CONCURRENCY: usize = 4;
+
+/// Summarizes `sections`, where section `i` is cited as `[i + 1]`, and streams
+/// the final merge.
+pub async fn summarize(
+    chat: &dyn ChatProvider,
+    focus: &str,
+    sections: &[Chunk],
+    fan_in: usize,
+    events: &Events,
+) -> Result<TokenStream> {
+    let fan_in = fan_in.max(2);
+    let total = sections.len();
+    let mut summaries: Vec<String> = stream::iter(sections.iter().enumerate())
+        .map(|(i, section)| async move {
+            let summary = chat
+                .complete(&llm::section_summary_prompt(focus, i + 1, section))
+                .await?;
+            events.send(PipelineEvent::SectionSummarized {
+                url: section.url.clone(),
+                section: i + 1,
+                total,
+            });
+            Ok::<_, anyhow::Error>(summary)
+        })
+        .buffered(MAP_CONCURRENCY)
+        .try_collect()
+        .await?;
+
+    // neighbouring summaries are merged together, so each merge stays within
+    // one document as long as possible
+    while summaries.len() > fan_in {
+        tracing::info!("Merging {} summaries", summaries.len());
+        summaries = stream::iter(summaries.chunks(fan_in))
+            .map(|group| async move {
+                chat.complete(&llm::reduce_summaries_prompt(focus, group, None))
+                    .await
+            })
+            .buffered(MAP_CONCURRENCY)
+            .try_collect()
+            .await?;
+    }
+
+    let sources = citation_list(sections);
+    chat.stream(&llm::reduce_summaries_prompt(
+        focus,
+        &summaries,
+        Some(&sources),
+    ))
+    .await
+}
+
+/// `[n] Name, section k of m - url` for every section.
+pub fn citation_list(sections: &[Chunk]) -> Vec<String> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for section in sections {
+        *counts.entry(&section.url).or_default() += 1;
+    }
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    sections
+        .iter()
+        .enumerate()
+        .map(|(i, section)| {
+            let index = seen.entry(&section.url).or_default();
+            *index += 1;
+            format!(
+                "[{}] {}, section {} of {} - {}",
+                i + 1,
+                section.name,
+                index,
+                counts[section.url.as_str()],
+                section.url
+            )
+        })
+        .collect()
+}
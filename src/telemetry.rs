@@ -0,0 +1,212 @@
+//! Tracing layers behind the CLI's `--timings` table and `--trace` export.
+//!
+//! The pipeline opens one span per stage (`query`, `embed_query`, `search`,
+//! `gather`, `retrieve`, `generate`) and one per page (`scrape`) and chunk
+//! (`embed`); these layers only look at the spans, so any other subscriber
+//! setup sees the same data.
+
+use anyhow::Result;
+use serde_json::{json, Map, Value};
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::Subscriber;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Wall time of every span with the same name, added up.
+#[derive(Clone, Debug)]
+pub struct StageTiming {
+    pub name: String,
+    pub count: usize,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+/// Collects a `StageTiming` per span name, in the order the stages started.
+#[derive(Clone, Default)]
+pub struct Timings {
+    stages: Arc<Mutex<Vec<StageTiming>>>,
+}
+
+struct TimingStart(Instant);
+
+impl Timings {
+    pub fn stages(&self) -> Vec<StageTiming> {
+        self.stages.lock().unwrap().clone()
+    }
+
+    /// Plain-text table of the stages; spans that run concurrently (pages,
+    /// chunks) add up to more than the wall time of their parent stage.
+    pub fn table(&self) -> String {
+        let mut table = format!(
+            "{:<12} {:>6} {:>12} {:>12}\n",
+            "stage", "count", "total ms", "max ms"
+        );
+        for stage in self.stages() {
+            table.push_str(&format!(
+                "{:<12} {:>6} {:>12.1} {:>12.1}\n",
+                stage.name,
+                stage.count,
+                millis(stage.total),
+                millis(stage.max)
+            ));
+        }
+        table
+    }
+}
+
+impl<S> Layer<S> for Timings
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let name = attrs.metadata().name();
+        {
+            let mut stages = self.stages.lock().unwrap();
+            if !stages.iter().any(|stage| stage.name == name) {
+                stages.push(StageTiming {
+                    name: name.to_string(),
+                    count: 0,
+                    total: Duration::ZERO,
+                    max: Duration::ZERO,
+                });
+            }
+        }
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(TimingStart(Instant::now()));
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(elapsed) = span
+            .extensions()
+            .get::<TimingStart>()
+            .map(|s| s.0.elapsed())
+        else {
+            return;
+        };
+
+        let mut stages = self.stages.lock().unwrap();
+        if let Some(stage) = stages.iter_mut().find(|stage| stage.name == span.name()) {
+            stage.count += 1;
+            stage.total += elapsed;
+            stage.max = stage.max.max(elapsed);
+        }
+    }
+}
+
+/// Writes every closed span to a file as one JSON object per line, with its
+/// id, parent id, start time, duration and recorded fields.
+pub struct JsonTrace {
+    file: Mutex<File>,
+}
+
+struct TraceSpan {
+    started: Instant,
+    started_at: SystemTime,
+    fields: Map<String, Value>,
+}
+
+impl JsonTrace {
+    pub fn create(path: &Path) -> Result<Self> {
+        Ok(JsonTrace {
+            file: Mutex::new(File::create(path)?),
+        })
+    }
+}
+
+impl<S> Layer<S> for JsonTrace
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = Map::new();
+        attrs.record(&mut FieldVisitor(&mut fields));
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(TraceSpan {
+                started: Instant::now(),
+                started_at: SystemTime::now(),
+                fields,
+            });
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(trace) = span.extensions_mut().get_mut::<TraceSpan>() {
+                values.record(&mut FieldVisitor(&mut trace.fields));
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let extensions = span.extensions();
+        let Some(trace) = extensions.get::<TraceSpan>() else {
+            return;
+        };
+
+        let start_us = trace
+            .started_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        let line = json!({
+            "name": span.name(),
+            "span_id": id.into_u64(),
+            "parent_id": span.parent().map(|parent| parent.id().into_u64()),
+            "start_unix_us": start_us,
+            "duration_ms": millis(trace.started.elapsed()),
+            "fields": trace.fields,
+        });
+
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = writeln!(file, "{}", line) {
+            eprintln!("Failed writing trace: {}", e);
+        }
+    }
+}
+
+struct FieldVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), json!(format!("{:?}", value)));
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,168 @@
+//! Answer prompt templates. A template is plain text with `{sources}`,
+//! `{question}`, `{date}` and `{language}` placeholders. fyin ships a few
+//! answer styles, and teams can add their own as files without recompiling.
+
+use crate::sanitize::UNTRUSTED_SOURCES;
+use anyhow::{anyhow, Result};
+use regex::{Captures, Regex};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+pub const DEFAULT_STYLE: &str = "detailed";
+
+/// Names of the built-in styles.
+pub const STYLES: [&str; 5] = ["concise", "detailed", "bullets", "eli5", "how-to"];
+
+const PLACEHOLDERS: &str = r"\{(sources|question|date|language)\}";
+
+const HEAD: &str = "SOURCES:
+{sources}
+
+QUESTION:
+{question}
+
+INSTRUCTIONS:
+You are a helpful AI assistant that answers questions using only the provided sources. If the answer is not in the sources, say you don't know rather than making one up.
+";
+
+const CONTEXT: &str = "
+Today's date is {date}. Some sources give the date they were published; when sources disagree about something that changes over time, prefer the most recent one and say how current the information is.
+Write the answer in {language}.
+";
+
+const CITATIONS: &str = "
+Include in-text citations like this [1] for each significant fact or statement at the end of the sentence.
+At the end of your response, list all sources in a citation section with the format: [citation number] Name - URL.
+";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template {
+    pub name: String,
+    text: String,
+}
+
+/// Values for a template's placeholders.
+pub struct Variables<'a> {
+    /// The fenced source blocks
+    pub sources: &'a str,
+    pub question: &'a str,
+    pub date: &'a str,
+    pub language: &'a str,
+}
+
+impl Default for Template {
+    fn default() -> Self {
+        Template::built_in(DEFAULT_STYLE).unwrap()
+    }
+}
+
+impl Template {
+    pub fn built_in(style: &str) -> Option<Self> {
+        let instructions = match style {
+            "concise" => "Answer in at most three sentences, leading with the direct answer.",
+            "detailed" => {
+                "Please provide a detailed answer to the question above only using the sources provided."
+            }
+            "bullets" => {
+                "Answer as a short bulleted list of the key points, one fact per bullet, most important first."
+            }
+            "eli5" => {
+                "Explain the answer simply, as if to a curious ten-year-old: short sentences, everyday words and one concrete analogy. Stay correct while keeping it simple."
+            }
+            "how-to" => {
+                "Answer as a step-by-step guide: first any prerequisites, then numbered steps with the exact commands or settings from the sources, then how to check that it worked."
+            }
+            _ => return None,
+        };
+        Some(Template {
+            name: style.to_string(),
+            text: format!(
+                "{}{}{}{}{}",
+                HEAD, UNTRUSTED_SOURCES, CONTEXT, instructions, CITATIONS
+            ),
+        })
+    }
+
+    /// A template must place the sources and the question somewhere.
+    pub fn parse(name: &str, text: &str) -> Result<Self> {
+        for required in ["{sources}", "{question}"] {
+            if !text.contains(required) {
+                return Err(anyhow!(
+                    "Template '{}' has no {} placeholder",
+                    name,
+                    required
+                ));
+            }
+        }
+        Ok(Template {
+            name: name.to_string(),
+            text: text.to_string(),
+        })
+    }
+
+    /// Reads a template file; the file name without extension is its name.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read template {}: {}", path.display(), e))?;
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        Template::parse(&name, &text)
+    }
+
+    /// Resolves `style`: a built-in style, `<style>.txt` in the template
+    /// directory, or a path to a template file.
+    pub fn find(style: &str) -> Result<Self> {
+        if let Some(template) = Template::built_in(style) {
+            return Ok(template);
+        }
+        let named = template_dir().join(format!("{}.txt", style));
+        if named.is_file() {
+            return Template::load(&named);
+        }
+        let path = Path::new(style);
+        if path.is_file() {
+            return Template::load(path);
+        }
+        Err(anyhow!(
+            "Unknown style '{}': use one of {}, a template in {}, or a template file",
+            style,
+            STYLES.join(", "),
+            template_dir().display()
+        ))
+    }
+
+    /// The style named by FYIN_STYLE, or `detailed`.
+    pub fn from_env() -> Result<Self> {
+        match env::var("FYIN_STYLE") {
+            Ok(style) if !style.trim().is_empty() => Template::find(style.trim()),
+            _ => Ok(Template::default()),
+        }
+    }
+
+    /// Fills in the placeholders in one pass, so placeholder-like text inside
+    /// the sources or question is left alone.
+    pub fn render(&self, variables: &Variables) -> String {
+        static PATTERN: OnceLock<Regex> = OnceLock::new();
+        let pattern = PATTERN.get_or_init(|| Regex::new(PLACEHOLDERS).unwrap());
+        pattern
+            .replace_all(&self.text, |captures: &Captures| match &captures[1] {
+                "sources" => variables.sources.to_string(),
+                "question" => variables.question.to_string(),
+                "date" => variables.date.to_string(),
+                _ => variables.language.to_string(),
+            })
+            .to_string()
+    }
+}
+
+// FYIN_TEMPLATE_DIR overrides the default of ~/.fyin/templates
+fn template_dir() -> PathBuf {
+    match env::var("FYIN_TEMPLATE_DIR") {
+        Ok(dir) if !dir.trim().is_empty() => PathBuf::from(dir),
+        _ => crate::collection::data_dir().join("templates"),
+    }
+}
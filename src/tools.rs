@@ -0,0 +1,376 @@
+//! Tools the chat model may call while answering: a calculator, a date
+//! utility and a new web search. The first two run here; the pipeline runs
+//! the search, since it needs the index the answer is drawn from.
+
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, NaiveDate, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+pub const CALCULATOR: &str = "calculator";
+pub const DATE: &str = "date";
+pub const SEARCH_AGAIN: &str = "search_again";
+
+/// Tool-calling turns before the model must answer without tools.
+pub const MAX_TOOL_ROUNDS: usize = 5;
+
+// Longer expressions are refused: the parser recurses per operator and
+// parenthesis, and the model's input may be steered by page text
+const MAX_EXPRESSION_TOKENS: usize = 256;
+
+/// A tool the model may call, with a JSON schema of its arguments.
+#[derive(Clone, Debug)]
+pub struct ToolSpec {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub parameters: Value,
+}
+
+/// A call the model asked for.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    /// Ties the result to the call; providers without ids get made-up ones
+    pub id: String,
+    pub name: String,
+    pub arguments: Value,
+}
+
+/// One message of a tool-calling conversation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChatMessage {
+    User(String),
+    /// The model's turn: text, calls, or both
+    Assistant {
+        text: String,
+        tool_calls: Vec<ToolCall>,
+    },
+    /// The result of the call with id `call_id`
+    Tool {
+        call_id: String,
+        name: String,
+        content: String,
+    },
+}
+
+/// Every built-in tool.
+pub fn specs() -> Vec<ToolSpec> {
+    vec![
+        ToolSpec {
+            name: CALCULATOR,
+            description: "Evaluates an arithmetic expression. Use it for any computation, \
+                          including unit conversions, instead of working numbers out yourself.",
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "expression": {
+                        "type": "string",
+                        "description": "For example (26.2 * 1.609344) / 3. Supports + - * / % ^, \
+                                        parentheses, pi, e and the functions sqrt, abs, ln, \
+                                        log10, exp, round, floor and ceil."
+                    }
+                },
+                "required": ["expression"]
+            }),
+        },
+        ToolSpec {
+            name: DATE,
+            description: "Date arithmetic on YYYY-MM-DD dates: today's date, adding days, \
+                          days between two dates, or the weekday of a date.",
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "operation": {
+                        "type": "string",
+                        "enum": ["today", "add_days", "days_between", "weekday"]
+                    },
+                    "date": {"type": "string", "description": "YYYY-MM-DD"},
+                    "end": {
+                        "type": "string",
+                        "description": "YYYY-MM-DD, the later date for days_between"
+                    },
+                    "days": {
+                        "type": "integer",
+                        "description": "Days to add for add_days, negative to go back"
+                    }
+                },
+                "required": ["operation"]
+            }),
+        },
+        ToolSpec {
+            name: SEARCH_AGAIN,
+            description: "Searches the web with a new query when the sources lack something. \
+                          New sources are numbered after the existing ones and can be cited.",
+            parameters: json!({
+                "type": "object",
+                "properties": {"query": {"type": "string"}},
+                "required": ["query"]
+            }),
+        },
+    ]
+}
+
+/// Runs the `calculator` tool.
+pub fn calculator(arguments: &Value) -> Result<String> {
+    let expression = arguments["expression"]
+        .as_str()
+        .ok_or_else(|| anyhow!("Missing 'expression'"))?;
+    Ok(format_number(calculate(expression)?))
+}
+
+/// Runs the `date` tool. Dates are written with their weekday.
+pub fn date(arguments: &Value) -> Result<String> {
+    let date = |key: &str| -> Result<NaiveDate> {
+        let text = arguments[key]
+            .as_str()
+            .ok_or_else(|| anyhow!("Missing '{}'", key))?;
+        NaiveDate::parse_from_str(text.trim(), "%Y-%m-%d")
+            .map_err(|_| anyhow!("'{}' is not a YYYY-MM-DD date", text))
+    };
+    let with_weekday = |date: NaiveDate| format!("{} ({})", date, date.format("%A"));
+
+    match arguments["operation"].as_str().unwrap_or("today") {
+        "today" => Ok(with_weekday(Utc::now().date_naive())),
+        "add_days" => {
+            let days = arguments["days"]
+                .as_i64()
+                .ok_or_else(|| anyhow!("Missing 'days'"))?;
+            let days =
+                TimeDelta::try_days(days).ok_or_else(|| anyhow!("The date is out of range"))?;
+            let result = date("date")?
+                .checked_add_signed(days)
+                .ok_or_else(|| anyhow!("The date is out of range"))?;
+            Ok(with_weekday(result))
+        }
+        "days_between" => {
+            let days = (date("end")? - date("date")?).num_days();
+            Ok(format!("{} days", days))
+        }
+        "weekday" => Ok(date("date")?.weekday().to_string()),
+        other => Err(anyhow!("Unknown date operation '{}'", other)),
+    }
+}
+
+/// Evaluates an arithmetic expression of at most a few hundred tokens.
+pub fn calculate(expression: &str) -> Result<f64> {
+    let tokens = tokenize(expression)?;
+    if tokens.len() > MAX_EXPRESSION_TOKENS {
+        return Err(anyhow!(
+            "The expression is too long; the limit is {} numbers, operators and parentheses",
+            MAX_EXPRESSION_TOKENS
+        ));
+    }
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+    };
+    let value = parser.expression()?;
+    if let Some(token) = parser.tokens.get(parser.position) {
+        return Err(anyhow!("Unexpected {:?} in the expression", token));
+    }
+    if !value.is_finite() {
+        return Err(anyhow!("The result is not a finite number"));
+    }
+    Ok(value)
+}
+
+/// Whole numbers without decimals, others to at most ten decimal places.
+pub fn format_number(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else if value.abs() >= 1e-6 && value.abs() < 1e15 {
+        let text = format!("{:.10}", value);
+        text.trim_end_matches('0').trim_end_matches('.').to_string()
+    } else {
+        format!("{:e}", value)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Operator(char),
+    Open,
+    Close,
+    Name(String),
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    // one past the limit is enough to refuse the expression
+    while i < chars.len() && tokens.len() <= MAX_EXPRESSION_TOKENS {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || c == '.' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            // an exponent, but not the constant e
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                let digits = match chars.get(i + 1) {
+                    Some('+' | '-') => i + 2,
+                    _ => i + 1,
+                };
+                if chars.get(digits).is_some_and(|c| c.is_ascii_digit()) {
+                    i = digits;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let text: String = chars[start..i].iter().collect();
+            let number = text
+                .parse()
+                .map_err(|_| anyhow!("'{}' is not a number", text))?;
+            tokens.push(Token::Number(number));
+        } else if c.is_alphabetic() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let name: String = chars[start..i].iter().collect();
+            tokens.push(Token::Name(name.to_lowercase()));
+        } else {
+            let token = match c {
+                '(' => Token::Open,
+                ')' => Token::Close,
+                '*' if chars.get(i + 1) == Some(&'*') => {
+                    i += 1;
+                    Token::Operator('^')
+                }
+                '+' | '-' | '*' | '/' | '%' | '^' => Token::Operator(c),
+                '×' => Token::Operator('*'),
+                '÷' => Token::Operator('/'),
+                _ => return Err(anyhow!("Unexpected '{}' in the expression", c)),
+            };
+            tokens.push(token);
+            i += 1;
+        }
+    }
+    Ok(tokens)
+}
+
+// Recursive descent, loosest binding first:
+// expression = term (("+" | "-") term)*
+// term       = unary (("*" | "/" | "%") unary)*
+// unary      = ("-" | "+") unary | power
+// power      = atom ("^" unary)?
+// atom       = number | name | name "(" expression ")" | "(" expression ")"
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn next_is(&mut self, expected: &Token) -> bool {
+        if self.tokens.get(self.position) == Some(expected) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expression(&mut self) -> Result<f64> {
+        let mut value = self.term()?;
+        loop {
+            if self.next_is(&Token::Operator('+')) {
+                value += self.term()?;
+            } else if self.next_is(&Token::Operator('-')) {
+                value -= self.term()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<f64> {
+        let mut value = self.unary()?;
+        loop {
+            if self.next_is(&Token::Operator('*')) {
+                value *= self.unary()?;
+            } else if self.next_is(&Token::Operator('/')) {
+                let divisor = self.unary()?;
+                if divisor == 0.0 {
+                    return Err(anyhow!("Division by zero"));
+                }
+                value /= divisor;
+            } else if self.next_is(&Token::Operator('%')) {
+                let divisor = self.unary()?;
+                if divisor == 0.0 {
+                    return Err(anyhow!("Division by zero"));
+                }
+                value %= divisor;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<f64> {
+        if self.next_is(&Token::Operator('-')) {
+            Ok(-self.unary()?)
+        } else if self.next_is(&Token::Operator('+')) {
+            self.unary()
+        } else {
+            self.power()
+        }
+    }
+
+    fn power(&mut self) -> Result<f64> {
+        let base = self.atom()?;
+        if self.next_is(&Token::Operator('^')) {
+            Ok(base.powf(self.unary()?))
+        } else {
+            Ok(base)
+        }
+    }
+
+    fn atom(&mut self) -> Result<f64> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(value),
+            Some(Token::Open) => {
+                let value = self.expression()?;
+                if !self.next_is(&Token::Close) {
+                    return Err(anyhow!("Missing ')' in the expression"));
+                }
+                Ok(value)
+            }
+            Some(Token::Name(name)) => {
+                if !self.next_is(&Token::Open) {
+                    return match name.as_str() {
+                        "pi" => Ok(std::f64::consts::PI),
+                        "e" => Ok(std::f64::consts::E),
+                        _ => Err(anyhow!("Unknown constant '{}'", name)),
+                    };
+                }
+                let argument = self.expression()?;
+                if !self.next_is(&Token::Close) {
+                    return Err(anyhow!("Missing ')' after {}(", name));
+                }
+                match name.as_str() {
+                    "sqrt" => Ok(argument.sqrt()),
+                    "abs" => Ok(argument.abs()),
+                    "ln" => Ok(argument.ln()),
+                    "log" | "log10" => Ok(argument.log10()),
+                    "exp" => Ok(argument.exp()),
+                    "round" => Ok(argument.round()),
+                    "floor" => Ok(argument.floor()),
+                    "ceil" => Ok(argument.ceil()),
+                    _ => Err(anyhow!("Unknown function '{}'", name)),
+                }
+            }
+            Some(token) => Err(anyhow!("Unexpected {:?} in the expression", token)),
+            None => Err(anyhow!("The expression ends too early")),
+        }
+    }
+}
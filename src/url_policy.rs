@@ -0,0 +1,230 @@
+//! Which URLs the scraper may fetch.
+//!
+//! Search engines hand back arbitrary URLs, so before anything is fetched the
+//! scheme and port are checked, host names are resolved and private, loopback
+//! and link-local addresses are dropped, and every redirect hop goes through
+//! the same checks.
+
+use anyhow::{anyhow, Result};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect;
+use reqwest::Url;
+use std::env;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+
+const DEFAULT_MAX_REDIRECTS: usize = 5;
+
+#[derive(Clone, Debug)]
+pub struct UrlPolicy {
+    pub schemes: Vec<String>,
+    /// Ports that may be fetched; empty allows any port
+    pub ports: Vec<u16>,
+    /// Allow hosts that resolve to private, loopback or link-local addresses
+    pub allow_private: bool,
+    /// Hosts trusted as-is: any address and any port
+    pub allowed_hosts: Vec<String>,
+    pub max_redirects: usize,
+}
+
+impl Default for UrlPolicy {
+    fn default() -> Self {
+        UrlPolicy {
+            schemes: vec!["http".to_string(), "https".to_string()],
+            ports: vec![80, 443, 8080, 8443],
+            allow_private: false,
+            allowed_hosts: vec![],
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+        }
+    }
+}
+
+impl UrlPolicy {
+    /// Default policy, relaxed by FYIN_ALLOW_PRIVATE_NETWORKS, FYIN_ALLOWED_HOSTS,
+    /// FYIN_ALLOWED_PORTS and FYIN_MAX_REDIRECTS.
+    pub fn from_env() -> Result<Self> {
+        let mut policy = UrlPolicy::default();
+        if let Some(value) = env_var("FYIN_ALLOW_PRIVATE_NETWORKS") {
+            policy.allow_private = matches!(value.as_str(), "1" | "true" | "yes");
+        }
+        if let Some(hosts) = env_var("FYIN_ALLOWED_HOSTS") {
+            policy.allowed_hosts = split_list(&hosts).map(str::to_lowercase).collect();
+        }
+        if let Some(ports) = env_var("FYIN_ALLOWED_PORTS") {
+            policy.ports = split_list(&ports)
+                .map(|port| {
+                    port.parse()
+                        .map_err(|_| anyhow!("Invalid port in FYIN_ALLOWED_PORTS: {}", port))
+                })
+                .collect::<Result<_>>()?;
+        }
+        if let Some(max) = env_var("FYIN_MAX_REDIRECTS") {
+            policy.max_redirects = max
+                .parse()
+                .map_err(|_| anyhow!("Invalid FYIN_MAX_REDIRECTS: {}", max))?;
+        }
+        Ok(policy)
+    }
+
+    /// Checks everything that can be checked without DNS: scheme, port, and
+    /// the address when the host is an IP literal.
+    pub fn check_url(&self, url: &Url) -> Result<()> {
+        if !self.schemes.iter().any(|scheme| scheme == url.scheme()) {
+            return Err(anyhow!("Blocked URL scheme '{}': {}", url.scheme(), url));
+        }
+        let host = url
+            .host_str()
+            .ok_or_else(|| anyhow!("URL has no host: {}", url))?;
+        if self.is_allowed_host(host) {
+            return Ok(());
+        }
+
+        let port = url
+            .port_or_known_default()
+            .ok_or_else(|| anyhow!("URL has no port: {}", url))?;
+        if !self.ports.is_empty() && !self.ports.contains(&port) {
+            return Err(anyhow!("Blocked port {}: {}", port, url));
+        }
+
+        let literal = host.trim_start_matches('[').trim_end_matches(']');
+        if let Ok(ip) = literal.parse::<IpAddr>() {
+            if !self.is_allowed_ip(ip) {
+                return Err(anyhow!("Blocked address {}: {}", ip, url));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_allowed_ip(&self, ip: IpAddr) -> bool {
+        self.allow_private || is_public(ip)
+    }
+
+    fn is_allowed_host(&self, host: &str) -> bool {
+        self.allowed_hosts.contains(&host.to_lowercase())
+    }
+
+    /// Follows at most `max_redirects` hops, each of which must pass `check_url`.
+    pub fn redirect_policy(self: &Arc<Self>) -> redirect::Policy {
+        let policy = self.clone();
+        redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() > policy.max_redirects {
+                let error = anyhow!("Too many redirects (max {})", policy.max_redirects);
+                return attempt.error(error);
+            }
+            match policy.check_url(attempt.url()) {
+                Ok(()) => attempt.follow(),
+                Err(e) => attempt.error(e),
+            }
+        })
+    }
+}
+
+/// DNS resolver that drops addresses the policy does not allow, so a host
+/// cannot point the scraper at the local network.
+pub struct PolicyResolver {
+    policy: Arc<UrlPolicy>,
+}
+
+impl PolicyResolver {
+    pub fn new(policy: Arc<UrlPolicy>) -> Self {
+        PolicyResolver { policy }
+    }
+}
+
+impl Resolve for PolicyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let policy = self.policy.clone();
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let resolved: Vec<SocketAddr> =
+                tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            if policy.is_allowed_host(&host) {
+                let addrs: Addrs = Box::new(resolved.into_iter());
+                return Ok(addrs);
+            }
+
+            let allowed: Vec<SocketAddr> = resolved
+                .into_iter()
+                .filter(|addr| policy.is_allowed_ip(addr.ip()))
+                .collect();
+            if allowed.is_empty() {
+                let error = anyhow!("{} resolves only to blocked addresses", host);
+                return Err(error.into());
+            }
+            let addrs: Addrs = Box::new(allowed.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+/// False for loopback, private, link-local (including cloud metadata at
+/// 169.254.169.254), shared, multicast and other non-routable ranges.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match embedded_v4(ip) {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        || a == 0
+        // shared address space (carrier-grade NAT)
+        || (a == 100 && (64..128).contains(&b))
+        // benchmarking
+        || (a == 198 && (b == 18 || b == 19))
+        // reserved
+        || a >= 240)
+}
+
+// The IPv4 address an IPv6 address carries, for the ranges that reach IPv4
+// hosts: mapped, compatible, NAT64 and 6to4
+fn embedded_v4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let segments = ip.segments();
+    let from = |high: u16, low: u16| {
+        let [a, b] = high.to_be_bytes();
+        let [c, d] = low.to_be_bytes();
+        Ipv4Addr::new(a, b, c, d)
+    };
+    match segments {
+        // ::ffff:a.b.c.d and the deprecated ::a.b.c.d, which covers :: and ::1 too
+        [0, 0, 0, 0, 0, 0xffff | 0, high, low] => Some(from(high, low)),
+        // NAT64, well-known and local-use prefixes
+        [0x64, 0xff9b, 0, 0, 0, 0, _, _] | [0x64, 0xff9b, 1, ..] => {
+            Some(from(segments[6], segments[7]))
+        }
+        // 6to4
+        [0x2002, high, low, ..] => Some(from(high, low)),
+        _ => None,
+    }
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        || ip.is_unique_local()
+        || ip.is_unicast_link_local()
+        // deprecated site-local fec0::/10
+        || (ip.segments()[0] & 0xffc0) == 0xfec0)
+}
+
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.trim().is_empty())
+}
+
+fn split_list(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
@@ -1,23 +1,71 @@
 use anyhow::{anyhow, Result};
-use hora::core::ann_index::ANNIndex;
-
+use async_trait::async_trait;
+use hora::core::ann_index::{ANNIndex, SerializableIndex};
+use std::path::Path;
 
 static DIMENSION: usize = 1536;
 
+/// Retrieve stage: an index of chunk embeddings keyed by chunk id.
+#[async_trait]
+pub trait Retriever: Send + Sync {
+    async fn insert(&mut self, id: usize, embedding: Vec<f64>) -> Result<()>;
+
+    /// Called once all chunks are inserted, before the first search.
+    async fn build(&mut self) -> Result<()>;
+
+    /// Returns the ids of the `n` chunks closest to `embedding`.
+    async fn search(&self, embedding: &[f64], n: usize) -> Result<Vec<usize>>;
+
+    /// Like `search`, with a similarity per id (higher is closer). Only the
+    /// order of the scores matters; by default they are derived from the rank.
+    async fn search_scored(&self, embedding: &[f64], n: usize) -> Result<Vec<(usize, f64)>> {
+        let ids = self.search(embedding, n).await?;
+        Ok(ids
+            .into_iter()
+            .enumerate()
+            .map(|(rank, id)| (id, 1.0 / (1.0 + rank as f64)))
+            .collect())
+    }
+}
+
 pub struct VectorDB {
     hora: hora::index::hnsw_idx::HNSWIndex<f64, usize>,
 }
 
 impl VectorDB {
-    pub async fn init(dimension: Option<usize>) -> Result<Self> {
-        let dimension = dimension.unwrap_or(DIMENSION);
-
+    pub fn new(dimension: usize) -> Self {
         let index = hora::index::hnsw_idx::HNSWIndex::<f64, usize>::new(
             dimension,
             &hora::index::hnsw_params::HNSWParams::<f64>::default(),
         );
-        let instance = VectorDB { hora: index };
-        Ok(instance)
+        VectorDB { hora: index }
+    }
+
+    pub async fn init(dimension: Option<usize>) -> Result<Self> {
+        Ok(Self::new(dimension.unwrap_or(DIMENSION)))
+    }
+
+    pub async fn load(path: &Path) -> Result<Self> {
+        let path = path
+            .to_str()
+            .ok_or_else(|| anyhow!("Invalid index path: {:?}", path))?;
+        let index = hora::index::hnsw_idx::HNSWIndex::<f64, usize>::load(path)
+            .map_err(|e| anyhow!("Failed to load index: {:?}", e))?;
+        Ok(VectorDB { hora: index })
+    }
+
+    pub async fn dump(&mut self, path: &Path) -> Result<()> {
+        let path = path
+            .to_str()
+            .ok_or_else(|| anyhow!("Invalid index path: {:?}", path))?;
+        self.hora
+            .dump(path)
+            .map_err(|e| anyhow!("Failed to dump index: {:?}", e))?;
+        Ok(())
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.hora.dimension()
     }
 
     pub async fn clean_up(&self) -> Result<()> {
@@ -35,7 +83,7 @@ impl VectorDB {
     }
 
     pub async fn upsert_embedding(&mut self, embedding: Vec<f64>, id: usize) -> Result<()> {
-        log::info!("Embedded: {}", id);
+        tracing::info!("Embedded: {}", id);
 
         self.hora
             .add(&embedding, id)
@@ -43,8 +91,35 @@ impl VectorDB {
         Ok(())
     }
 
-    pub async fn search(&self, embedding: &Vec<f64>, n: usize) -> Result<Vec<usize>> {
+    pub async fn search(&self, embedding: &[f64], n: usize) -> Result<Vec<usize>> {
         let search_result = self.hora.search(embedding, n);
         Ok(search_result)
     }
+
+    pub async fn search_scored(&self, embedding: &[f64], n: usize) -> Result<Vec<(usize, f64)>> {
+        let nodes = self.hora.search_nodes(embedding, n);
+        Ok(nodes
+            .into_iter()
+            .filter_map(|(node, distance)| Some(((*node.idx())?, 1.0 / (1.0 + distance))))
+            .collect())
+    }
+}
+
+#[async_trait]
+impl Retriever for VectorDB {
+    async fn insert(&mut self, id: usize, embedding: Vec<f64>) -> Result<()> {
+        self.upsert_embedding(embedding, id).await
+    }
+
+    async fn build(&mut self) -> Result<()> {
+        self.build_index().await
+    }
+
+    async fn search(&self, embedding: &[f64], n: usize) -> Result<Vec<usize>> {
+        VectorDB::search(self, embedding, n).await
+    }
+
+    async fn search_scored(&self, embedding: &[f64], n: usize) -> Result<Vec<(usize, f64)>> {
+        VectorDB::search_scored(self, embedding, n).await
+    }
 }
@@ -0,0 +1,53 @@
+// The CLI arguments live in the binary, so they are compiled in here too
+#[allow(dead_code)]
+#[path = "../src/args.rs"]
+mod args;
+
+use args::{Args, Command};
+use clap::Parser;
+use fyin::dates::Recency;
+use std::time::Duration;
+
+#[test]
+fn pipeline_flags_work_with_subcommands() {
+    let args = Args::try_parse_from(["fyin", "--style", "concise", "serve"]).unwrap();
+    assert_eq!(args.pipeline.style.as_deref(), Some("concise"));
+    assert!(matches!(args.command, Some(Command::Serve { .. })));
+
+    let args = Args::try_parse_from(["fyin", "mcp", "--tools", "--recency", "off"]).unwrap();
+    assert!(args.pipeline.tools);
+    assert_eq!(args.pipeline.recency, Recency::Off);
+}
+
+#[test]
+fn pipeline_flags_work_without_subcommands() {
+    let args = Args::try_parse_from(["fyin", "--query", "What is HNSW?", "--translate"]).unwrap();
+    assert_eq!(args.query.as_deref(), Some("What is HNSW?"));
+    assert!(args.pipeline.translate);
+    assert!(args.command.is_none());
+}
+
+#[test]
+fn fact_checks_can_be_scoped_to_sites() {
+    let args = Args::try_parse_from(["fyin", "--site", "x", "check", "claim"]).unwrap();
+    assert_eq!(args.pipeline.sites, vec!["x"]);
+    match args.command {
+        Some(Command::Check { claim, json }) => {
+            assert_eq!(claim, "claim");
+            assert!(!json);
+        }
+        command => panic!("unexpected command {:?}", command),
+    }
+}
+
+#[test]
+fn durations_must_be_positive_seconds() {
+    let args = Args::try_parse_from(["fyin", "-q", "x", "--deadline", "1.5"]).unwrap();
+    assert_eq!(args.pipeline.deadline, Some(Duration::from_millis(1500)));
+    for deadline in ["-1", "0", "NaN", "inf", "soon"] {
+        assert!(Args::try_parse_from(["fyin", "-q", "x", "--deadline", deadline]).is_err());
+        assert!(
+            Args::try_parse_from(["fyin", "-q", "x", "--deep", "--max-time", deadline]).is_err()
+        );
+    }
+}
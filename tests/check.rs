@@ -0,0 +1,116 @@
+mod common;
+
+use common::{chunk, pipeline, MockWeb, ScriptedChat};
+use fyin::check::{FactCheck, Verdict};
+
+const CLAIM: &str = "The Great Wall of China is visible from space with the naked eye";
+const NEUTRAL: &str = "Is the Great Wall of China visible from space";
+const NEGATED: &str = "Great Wall of China not visible from space myth";
+
+const MYTH_PAGE: &str = "<html><head><title>Myth</title></head><body><article>
+<p>Astronauts report that the Great Wall of China is not visible from space with the naked eye.
+The wall is long but narrow, and its colour matches the surrounding land.</p>
+</article></body></html>";
+
+const BLOG_PAGE: &str = "<html><head><title>Travel blog</title></head><body><article>
+<p>Our guide told us the Great Wall of China is the only structure visible from space,
+which is why we booked a tour to see it.</p>
+</article></body></html>";
+
+#[test]
+fn keeps_evidence_that_cites_a_source() {
+    let reply = r#"{"evidence": [
+        {"source": 1, "stance": "supports", "reason": "Says so"},
+        {"source": 1, "stance": "refutes", "reason": "Duplicate"},
+        {"source": 2, "stance": "irrelevant", "reason": "Off topic"},
+        {"source": 9, "stance": "refutes", "reason": "No such source"}
+    ], "verdict": "Insufficient evidence", "explanation": "Only one source [1]."}"#;
+
+    let check = FactCheck::parse(
+        "claim",
+        vec![
+            chunk("https://a.example", "content"),
+            chunk("https://b.example", "content"),
+        ],
+        reply,
+    )
+    .unwrap();
+
+    assert_eq!(check.verdict, Verdict::InsufficientEvidence);
+    assert_eq!(check.supporting.len(), 1);
+    assert_eq!(check.supporting[0].source, 1);
+    assert!(check.refuting.is_empty());
+    assert_eq!(check.explanation, "Only one source [1].");
+}
+
+#[test]
+fn replaces_verdicts_the_evidence_does_not_back() {
+    let reply = r#"{"evidence": [{"source": 1, "stance": "refutes", "reason": "No"}],
+        "verdict": "supported", "explanation": ""}"#;
+    let check =
+        FactCheck::parse("claim", vec![chunk("https://a.example", "content")], reply).unwrap();
+    assert_eq!(check.verdict, Verdict::Refuted);
+
+    let reply = r#"{"evidence": [], "verdict": "mixed"}"#;
+    let check =
+        FactCheck::parse("claim", vec![chunk("https://a.example", "content")], reply).unwrap();
+    assert_eq!(check.verdict, Verdict::InsufficientEvidence);
+
+    assert!(FactCheck::parse("claim", vec![], "I cannot tell").is_err());
+}
+
+#[tokio::test]
+async fn searches_both_sides_and_classifies_evidence() {
+    let web = MockWeb::start().await;
+    web.search_results(CLAIM, &[("Travel blog", "/blog")]).await;
+    web.search_results(NEUTRAL, &[("Travel blog", "/blog")])
+        .await;
+    web.search_results(NEGATED, &[("Myth", "/myth")]).await;
+    web.page("/blog", BLOG_PAGE).await;
+    web.page("/myth", MYTH_PAGE).await;
+    let chat = ScriptedChat::new(&[
+        &format!("1. {}\n2. {}", NEUTRAL, NEGATED),
+        r#"{"evidence": [
+            {"source": 1, "stance": "refutes", "reason": "Astronauts say it cannot be seen."},
+            {"source": 2, "stance": "supports", "reason": "A tour guide repeats the claim."}
+        ], "verdict": "mixed", "explanation": "A blog repeats it [2], but astronauts deny it [1]."}"#,
+    ]);
+
+    let check = pipeline(&web, &chat)
+        .await
+        .check(CLAIM, None)
+        .await
+        .unwrap();
+
+    let urls: Vec<_> = check.chunks.iter().map(|c| c.url.clone()).collect();
+    assert_eq!(urls.len(), 2);
+    assert!(urls.contains(&web.url("/myth")));
+    assert!(urls.contains(&web.url("/blog")));
+    assert_eq!(check.verdict, Verdict::Mixed);
+    assert_eq!(check.supporting.len(), 1);
+    assert_eq!(check.refuting.len(), 1);
+
+    let prompts = chat.prompts();
+    assert_eq!(prompts.len(), 2);
+    assert!(prompts[0].contains(CLAIM));
+    assert!(prompts[1].contains("CLAIM:"));
+    assert!(prompts[1].contains("naked eye"));
+}
+
+#[tokio::test]
+async fn reports_insufficient_evidence_without_sources() {
+    let web = MockWeb::start().await;
+    web.search_results(CLAIM, &[]).await;
+    // the expansion fails, so only the claim itself is searched
+    let chat = ScriptedChat::new(&[]);
+
+    let check = pipeline(&web, &chat)
+        .await
+        .check(CLAIM, None)
+        .await
+        .unwrap();
+
+    assert_eq!(check.verdict, Verdict::InsufficientEvidence);
+    assert!(check.chunks.is_empty());
+    assert_eq!(chat.prompts().len(), 1);
+}
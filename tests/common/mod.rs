@@ -0,0 +1,233 @@
+//! Offline stand-ins for every external service the pipeline talks to: a mock
+//! HTTP server for search engines and pages, a deterministic embedder and a
+//! scripted chat provider.
+
+#![allow(dead_code)]
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures::stream;
+use fyin::llm::TokenStream;
+use fyin::scraper::WebScraper;
+use fyin::search::Searxng;
+use fyin::url_policy::UrlPolicy;
+use fyin::{ChatProvider, Chunk, Embedder, Pipeline, PipelineBuilder, PipelineEvent};
+use serde_json::json;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedReceiver;
+use wiremock::matchers::{method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+pub const DIMENSION: usize = 64;
+
+pub fn fixture(name: &str) -> String {
+    let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+    std::fs::read_to_string(&path).unwrap_or_else(|_| panic!("missing fixture {}", path))
+}
+
+/// Serves canned search results and pages from a local port.
+pub struct MockWeb {
+    pub server: MockServer,
+}
+
+impl MockWeb {
+    pub async fn start() -> Self {
+        MockWeb {
+            server: MockServer::start().await,
+        }
+    }
+
+    pub fn url(&self, page: &str) -> String {
+        format!("{}{}", self.server.uri(), page)
+    }
+
+    pub fn searxng_endpoint(&self) -> String {
+        self.url("/search")
+    }
+
+    /// Answers `q=query` on /search in SearXNG's JSON format, linking to the given pages.
+    pub async fn search_results(&self, query: &str, pages: &[(&str, &str)]) {
+        let results: Vec<_> = pages
+            .iter()
+            .map(|(title, page)| json!({ "title": title, "url": self.url(page) }))
+            .collect();
+        Mock::given(method("GET"))
+            .and(path("/search"))
+            .and(query_param("q", query))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "results": results })))
+            .mount(&self.server)
+            .await;
+    }
+
+    /// Like `search_results`, and also serves each `(title, page, html)`.
+    pub async fn search_pages(&self, query: &str, pages: &[(&str, &str, &str)]) {
+        let results: Vec<_> = pages
+            .iter()
+            .map(|(title, page, _)| (*title, *page))
+            .collect();
+        self.search_results(query, &results).await;
+        for (_, page, html) in pages {
+            self.page(page, html).await;
+        }
+    }
+
+    pub async fn page(&self, page: &str, html: &str) {
+        Mock::given(method("GET"))
+            .and(path(page))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_raw(html.as_bytes().to_vec(), "text/html"),
+            )
+            .mount(&self.server)
+            .await;
+    }
+
+    /// Like `page`, but only answers after `delay`.
+    pub async fn slow_page(&self, page: &str, html: &str, delay: Duration) {
+        Mock::given(method("GET"))
+            .and(path(page))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_raw(html.as_bytes().to_vec(), "text/html")
+                    .set_delay(delay),
+            )
+            .mount(&self.server)
+            .await;
+    }
+
+    pub async fn failing_page(&self, page: &str, status: u16) {
+        Mock::given(method("GET"))
+            .and(path(page))
+            .respond_with(ResponseTemplate::new(status))
+            .mount(&self.server)
+            .await;
+    }
+}
+
+/// Bag-of-words embedding: each lowercased word is hashed into one of
+/// `DIMENSION` buckets and the vector is normalized, so texts that share
+/// words end up close together.
+pub struct FakeEmbedder;
+
+#[async_trait]
+impl Embedder for FakeEmbedder {
+    async fn embed(&self, text: &str) -> Result<Vec<f64>> {
+        let mut vector = vec![0.0; DIMENSION];
+        for word in text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|w| !w.is_empty())
+        {
+            let mut hasher = DefaultHasher::new();
+            word.to_lowercase().hash(&mut hasher);
+            vector[(hasher.finish() as usize) % DIMENSION] += 1.0;
+        }
+        let norm = vector.iter().map(|x| x * x).sum::<f64>().sqrt();
+        if norm > 0.0 {
+            vector.iter_mut().for_each(|x| *x /= norm);
+        }
+        Ok(vector)
+    }
+}
+
+/// Replies with queued responses in order, streamed word by word, and records
+/// every prompt it was given.
+#[derive(Clone, Default)]
+pub struct ScriptedChat {
+    responses: Arc<Mutex<VecDeque<String>>>,
+    pub prompts: Arc<Mutex<Vec<String>>>,
+}
+
+impl ScriptedChat {
+    pub fn new(responses: &[&str]) -> Self {
+        ScriptedChat {
+            responses: Arc::new(Mutex::new(
+                responses.iter().map(|r| r.to_string()).collect(),
+            )),
+            prompts: Default::default(),
+        }
+    }
+
+    pub fn prompts(&self) -> Vec<String> {
+        self.prompts.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl ChatProvider for ScriptedChat {
+    async fn stream(&self, prompt: &str) -> Result<TokenStream> {
+        self.prompts.lock().unwrap().push(prompt.to_string());
+        let response = self
+            .responses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or_else(|| anyhow!("ScriptedChat ran out of responses"))?;
+
+        let tokens: Vec<Result<String>> = response
+            .split_inclusive(' ')
+            .map(|token| Ok(token.to_string()))
+            .collect();
+        Ok(Box::pin(stream::iter(tokens)))
+    }
+}
+
+/// A mock web where `query` finds the HNSW, Rust and pasta fixtures, in that
+/// order.
+pub async fn web_with_pages(query: &str) -> MockWeb {
+    let web = MockWeb::start().await;
+    web.search_pages(
+        query,
+        &[
+            ("HNSW explained", "/hnsw", &fixture("hnsw.html")),
+            ("The Rust language", "/rust", &fixture("rust.html")),
+            ("Cooking pasta", "/pasta", &fixture("pasta.html")),
+        ],
+    )
+    .await;
+    web
+}
+
+/// A chunk of `content` from `url`, named after it.
+pub fn chunk(url: &str, content: &str) -> Chunk {
+    Chunk {
+        content: content.to_string(),
+        name: url.to_string(),
+        url: url.to_string(),
+        published: None,
+    }
+}
+
+/// A scraper that trusts the mock server, which listens on loopback.
+/// It is reachable as both `127.0.0.1` and `localhost`, i.e. from two domains.
+pub fn scraper() -> WebScraper {
+    WebScraper::new(UrlPolicy {
+        allowed_hosts: vec!["127.0.0.1".to_string(), "localhost".to_string()],
+        ..Default::default()
+    })
+}
+
+/// A pipeline builder wired to the mock web with the fake embedder and `chat`.
+pub fn builder(web: &MockWeb, chat: &ScriptedChat) -> PipelineBuilder {
+    Pipeline::builder()
+        .search_provider(Searxng::new(&web.searxng_endpoint()))
+        .scraper(scraper())
+        .embedder(FakeEmbedder)
+        .chat(chat.clone())
+        .search_count(10)
+        .top_k(3)
+}
+
+pub async fn pipeline(web: &MockWeb, chat: &ScriptedChat) -> Pipeline {
+    builder(web, chat).build().await.unwrap()
+}
+
+pub async fn drain(mut receiver: UnboundedReceiver<PipelineEvent>) -> Vec<PipelineEvent> {
+    let mut events = vec![];
+    while let Some(event) = receiver.recv().await {
+        events.push(event);
+    }
+    events
+}
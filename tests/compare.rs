@@ -0,0 +1,150 @@
+mod common;
+
+use common::{builder, chunk, pipeline, MockWeb, ScriptedChat};
+use fyin::compare::{self, Comparison, NOT_COVERED};
+
+const QUERY: &str = "tokio vs async-std for web servers";
+
+const TOKIO_PAGE: &str = "<html><head><title>Tokio</title></head><body><article>
+<p>Tokio is an asynchronous runtime for Rust with a multi-threaded, work-stealing scheduler.
+Most Rust web servers, including axum and hyper, are built on tokio.</p>
+</article></body></html>";
+
+const ASYNC_STD_PAGE: &str = "<html><head><title>async-std</title></head><body><article>
+<p>async-std mirrors the standard library API with async versions of files, sockets and
+timers. Its development has slowed and the smol runtime is recommended instead.</p>
+</article></body></html>";
+
+#[test]
+fn detects_entities_of_versus_questions() {
+    assert_eq!(compare::detect_entities(QUERY), vec!["tokio", "async-std"]);
+    assert_eq!(
+        compare::detect_entities("Which is faster, Postgres vs. MySQL vs SQLite?"),
+        vec!["Postgres", "MySQL", "SQLite"]
+    );
+    assert!(compare::detect_entities("How does HNSW search work").is_empty());
+}
+
+#[test]
+fn searches_each_entity_with_the_question_context() {
+    let entities = vec!["Postgres".to_string(), "MySQL".to_string()];
+    assert_eq!(
+        compare::entity_query(
+            "Compare Postgres and MySQL for analytics",
+            "MySQL",
+            &entities
+        ),
+        "MySQL for analytics"
+    );
+    assert_eq!(
+        compare::entity_query("postgres vs mysql", "Postgres", &entities),
+        "Postgres"
+    );
+}
+
+#[test]
+fn keeps_only_cells_with_valid_citations() {
+    let entities = vec!["A".to_string(), "B".to_string()];
+    let reply = r#"Here is the table:
+{"dimensions": [
+  {"name": "Speed", "cells": ["Fast [1][2]", "Slow | steady [7]"]},
+  {"name": "Cost", "cells": ["Free [2, 1]"]}
+]}"#;
+
+    let comparison = Comparison::parse(
+        "A vs B",
+        entities,
+        vec![
+            chunk("https://a.example", "content"),
+            chunk("https://b.example", "content"),
+        ],
+        reply,
+    )
+    .unwrap();
+
+    assert_eq!(comparison.rows.len(), 2);
+    assert_eq!(comparison.rows[0].cells[0].citations, vec![1, 2]);
+    assert_eq!(comparison.rows[0].cells[1].text, NOT_COVERED);
+    assert_eq!(comparison.rows[1].cells[0].citations, vec![2, 1]);
+    assert_eq!(comparison.rows[1].cells[1].text, NOT_COVERED);
+    assert_eq!(
+        comparison.to_markdown(),
+        "| | A | B |
+| --- | --- | --- |
+| Speed | Fast [1][2] | Not covered by the sources |
+| Cost | Free [2, 1] | Not covered by the sources |
+
+Sources:
+[1] https://a.example - https://a.example
+[2] https://b.example - https://b.example"
+    );
+
+    assert!(Comparison::parse("A vs B", vec![], vec![], "no table").is_err());
+}
+
+#[tokio::test]
+async fn compares_entities_from_their_own_searches() {
+    let web = MockWeb::start().await;
+    web.search_results("tokio for web servers", &[("Tokio", "/tokio")])
+        .await;
+    web.search_results("async-std for web servers", &[("async-std", "/async-std")])
+        .await;
+    web.page("/tokio", TOKIO_PAGE).await;
+    web.page("/async-std", ASYNC_STD_PAGE).await;
+    let chat = ScriptedChat::new(&[r#"{"dimensions": [
+        {"name": "Scheduler", "cells": ["Work-stealing [1]", "Not covered by the sources"]},
+        {"name": "Ecosystem", "cells": ["axum and hyper [1]", "Slowed down [2]"]}
+    ]}"#]);
+
+    let comparison = pipeline(&web, &chat)
+        .await
+        .compare(QUERY, vec![], vec![], None)
+        .await
+        .unwrap();
+
+    assert_eq!(comparison.entities, vec!["tokio", "async-std"]);
+    assert_eq!(comparison.chunks.len(), 2);
+    assert_eq!(comparison.chunks[0].url, web.url("/tokio"));
+    assert_eq!(comparison.chunks[1].url, web.url("/async-std"));
+    assert_eq!(comparison.rows[1].cells[1].citations, vec![2]);
+
+    let prompts = chat.prompts();
+    assert_eq!(prompts.len(), 1);
+    let tokio = prompts[0].find("SOURCES ABOUT tokio:").unwrap();
+    let async_std = prompts[0].find("SOURCES ABOUT async-std:").unwrap();
+    assert!(tokio < prompts[0].find("work-stealing").unwrap());
+    assert!(async_std < prompts[0].find("smol runtime").unwrap());
+    assert!(prompts[0].contains("Pick the 4 to 6 dimensions"));
+}
+
+#[tokio::test]
+async fn asks_the_model_for_entities_and_uses_given_dimensions() {
+    let web = MockWeb::start().await;
+    web.search_results("tokio", &[("Tokio", "/tokio")]).await;
+    web.search_results("async-std", &[("async-std", "/async-std")])
+        .await;
+    web.page("/tokio", TOKIO_PAGE).await;
+    web.page("/async-std", ASYNC_STD_PAGE).await;
+    let chat = ScriptedChat::new(&[
+        "1. tokio\n2. async-std",
+        r#"{"dimensions": [{"name": "Maintenance", "cells": ["Active [1]", "Slowed [2]"]}]}"#,
+    ]);
+
+    let comparison = builder(&web, &chat)
+        .build()
+        .await
+        .unwrap()
+        .compare(
+            "Should I pick tokio or async-std?",
+            vec![],
+            vec!["Maintenance".to_string()],
+            None,
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(comparison.entities, vec!["tokio", "async-std"]);
+    assert_eq!(comparison.rows[0].dimension, "Maintenance");
+    let prompts = chat.prompts();
+    assert!(prompts[1].contains("Compare exactly these dimensions, in this order: Maintenance."));
+}
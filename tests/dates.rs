@@ -0,0 +1,239 @@
+mod common;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{Duration, NaiveDate, Utc};
+use common::{builder, MockWeb, ScriptedChat};
+use fyin::dates::{self, Recency};
+use fyin::llm::{self, ChatProvider, TokenStream};
+use fyin::Scraper;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const QUERY: &str = "latest rust release";
+const PLAIN_QUERY: &str = "rust release notes";
+
+/// Classifies every query as time-sensitive and counts how often it is asked.
+#[derive(Clone)]
+struct ClassifyingChat {
+    chat: ScriptedChat,
+    asked: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl ChatProvider for ClassifyingChat {
+    async fn stream(&self, prompt: &str) -> Result<TokenStream> {
+        self.chat.stream(prompt).await
+    }
+
+    async fn is_time_sensitive(&self, _query: &str) -> Result<bool> {
+        self.asked.fetch_add(1, Ordering::SeqCst);
+        Ok(true)
+    }
+}
+
+fn date(text: &str) -> NaiveDate {
+    NaiveDate::parse_from_str(text, "%Y-%m-%d").unwrap()
+}
+
+fn dated_page(published: NaiveDate, text: &str) -> String {
+    format!(
+        r#"<html><head><meta property="article:published_time" content="{}T09:00:00Z"></head>
+        <body><p>{}</p></body></html>"#,
+        published, text
+    )
+}
+
+#[test]
+fn takes_the_newest_declared_date() {
+    let html = r#"<html><head>
+        <meta property="article:published_time" content="2024-03-01T10:00:00+02:00">
+        <script type="application/ld+json">
+            {"@graph": [{"@type": "NewsArticle", "datePublished": "2024-03-01", "dateModified": "2024-05-02T08:00:00Z"}]}
+        </script>
+        </head><body><time datetime="2023-01-01">old</time></body></html>"#;
+    assert_eq!(dates::published_date(html), Some(date("2024-05-02")));
+}
+
+#[test]
+fn falls_back_to_time_elements_and_ignores_future_dates() {
+    let future = Utc::now().date_naive() + Duration::days(30);
+    let html = format!(
+        r#"<html><head><meta name="date" content="{}"></head>
+        <body><time>no attribute</time><time datetime="2022/11/05">5 Nov</time></body></html>"#,
+        future
+    );
+    assert_eq!(dates::published_date(&html), Some(date("2022-11-05")));
+    assert_eq!(dates::published_date("<p>undated</p>"), None);
+}
+
+#[test]
+fn parses_page_and_header_date_formats() {
+    assert_eq!(
+        dates::parse_date("2024-06-01T23:30:00-05:00"),
+        Some(date("2024-06-01"))
+    );
+    assert_eq!(
+        dates::parse_date("Wed, 21 Oct 2015 07:28:00 GMT"),
+        Some(date("2015-10-21"))
+    );
+    assert_eq!(dates::parse_date("2020-02-29"), Some(date("2020-02-29")));
+    assert_eq!(dates::parse_date("last tuesday"), None);
+}
+
+#[test]
+fn newer_sources_weigh_more() {
+    let today = date("2026-01-01");
+    let fresh = dates::recency_weight(Some(today), today);
+    let month_old = dates::recency_weight(Some(date("2025-12-01")), today);
+    let stale = dates::recency_weight(Some(date("2019-01-01")), today);
+
+    assert!((fresh - 1.5).abs() < 1e-9);
+    assert!(fresh > month_old && month_old > stale);
+    assert!(stale > 1.0 && stale < 1.01);
+    assert_eq!(dates::recency_weight(None, today), 1.0);
+}
+
+#[test]
+fn keywords_and_recent_years_mark_queries_time_sensitive() {
+    let year = Utc::now().format("%Y").to_string();
+    assert!(llm::looks_time_sensitive(
+        "What is the latest Rust release?"
+    ));
+    assert!(llm::looks_time_sensitive("bitcoin price"));
+    assert!(llm::looks_time_sensitive(&format!("best laptops {}", year)));
+    assert!(!llm::looks_time_sensitive("How does HNSW search work"));
+    assert!(!llm::looks_time_sensitive("Battle of Hastings 1066"));
+}
+
+// Two pages on the same topic; the stale one matches the query slightly better.
+async fn web_with_dated_pages() -> MockWeb {
+    let web = MockWeb::start().await;
+    web.search_results(QUERY, &[("Old news", "/old"), ("New news", "/new")])
+        .await;
+    let recent = Utc::now().date_naive() - Duration::days(3);
+    web.page(
+        "/old",
+        &dated_page(
+            date("2016-05-26"),
+            "The latest Rust release is Rust 1.9, a rust release with new features.",
+        ),
+    )
+    .await;
+    web.page(
+        "/new",
+        &dated_page(recent, "The latest Rust release adds new features."),
+    )
+    .await;
+    web
+}
+
+#[tokio::test]
+async fn time_sensitive_queries_prefer_recent_sources() {
+    let web = web_with_dated_pages().await;
+    let chat = ScriptedChat::new(&["Rust 1.9 [2]."]);
+
+    let answer = builder(&web, &chat)
+        .build()
+        .await
+        .unwrap()
+        .run(QUERY)
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+
+    let search = web.server.received_requests().await.unwrap();
+    let search = search.iter().find(|r| r.url.path() == "/search").unwrap();
+    assert!(search
+        .url
+        .query_pairs()
+        .any(|(key, value)| key == "time_range" && value == "year"));
+
+    assert_eq!(answer.chunks[0].url, web.url("/new"));
+    assert_eq!(answer.chunks[1].published, Some(date("2016-05-26")));
+    let prompt = &chat.prompts()[0];
+    assert!(prompt.contains("published: 2016-05-26"));
+    assert!(prompt.contains(&format!("Today's date is {}", Utc::now().date_naive())));
+}
+
+#[tokio::test]
+async fn recency_off_ranks_by_similarity_alone() {
+    let web = web_with_dated_pages().await;
+    let chat = ScriptedChat::new(&["Rust 1.9 [1]."]);
+
+    let answer = builder(&web, &chat)
+        .recency(Recency::Off)
+        .build()
+        .await
+        .unwrap()
+        .run(QUERY)
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+
+    let search = web.server.received_requests().await.unwrap();
+    let search = search.iter().find(|r| r.url.path() == "/search").unwrap();
+    assert!(!search.url.query_pairs().any(|(key, _)| key == "time_range"));
+    assert_eq!(answer.chunks[0].url, web.url("/old"));
+}
+
+async fn searched_for_recent_results(recency: Recency, chat: &ClassifyingChat) -> bool {
+    let web = MockWeb::start().await;
+    web.search_results(PLAIN_QUERY, &[("Notes", "/notes")])
+        .await;
+    web.page("/notes", "<p>Rust release notes.</p>").await;
+
+    builder(&web, &ScriptedChat::default())
+        .chat(chat.clone())
+        .recency(recency)
+        .build()
+        .await
+        .unwrap()
+        .run(PLAIN_QUERY)
+        .await
+        .unwrap();
+
+    let search = web.server.received_requests().await.unwrap();
+    let search = search.iter().find(|r| r.url.path() == "/search").unwrap();
+    search
+        .url
+        .query_pairs()
+        .any(|(key, value)| key == "time_range" && value == "year")
+}
+
+#[tokio::test]
+async fn only_model_recency_waits_on_the_chat_model() {
+    let chat = ClassifyingChat {
+        chat: ScriptedChat::new(&["Notes [1].", "Notes [1]."]),
+        asked: Default::default(),
+    };
+    assert!(!searched_for_recent_results(Recency::Auto, &chat).await);
+    assert_eq!(chat.asked.load(Ordering::SeqCst), 0);
+
+    assert!(searched_for_recent_results(Recency::Model, &chat).await);
+    assert_eq!(chat.asked.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn last_modified_header_dates_undated_pages() {
+    let web = MockWeb::start().await;
+    Mock::given(method("GET"))
+        .and(path("/plain"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("last-modified", "Wed, 21 Oct 2015 07:28:00 GMT")
+                .set_body_raw("<html><body><p>Plain page</p></body></html>", "text/html"),
+        )
+        .mount(&web.server)
+        .await;
+
+    let page = common::scraper().scrape(&web.url("/plain")).await.unwrap();
+    assert_eq!(page.content.trim(), "Plain page");
+    assert_eq!(page.published, Some(date("2015-10-21")));
+}
@@ -0,0 +1,148 @@
+mod common;
+
+use common::{builder, fixture, MockWeb, ScriptedChat};
+use fyin::domains::DomainPolicy;
+use fyin::search::SearchOptions;
+use serde_json::json;
+use wiremock::matchers::{method, path, query_param};
+use wiremock::{Mock, ResponseTemplate};
+
+const QUERY: &str = "How does HNSW search work";
+
+#[test]
+fn allow_and_deny_lists_cover_subdomains() {
+    let policy = DomainPolicy {
+        allow: vec!["wikipedia.org".to_string(), "rust-lang.org".to_string()],
+        deny: vec!["spam.wikipedia.org".to_string()],
+        ..Default::default()
+    };
+
+    assert!(policy.is_allowed("https://en.wikipedia.org/wiki/HNSW"));
+    assert!(policy.is_allowed("https://www.rust-lang.org/"));
+    assert!(!policy.is_allowed("https://spam.wikipedia.org/page"));
+    assert!(!policy.is_allowed("https://notwikipedia.org/"));
+    assert!(!policy.is_allowed("https://contentfarm.com/hnsw"));
+}
+
+#[test]
+fn most_specific_weight_wins() {
+    let policy = DomainPolicy {
+        weights: [("wikipedia.org", 1.5), ("en.wikipedia.org", 2.0)]
+            .into_iter()
+            .map(|(domain, weight)| (domain.to_string(), weight))
+            .collect(),
+        ..Default::default()
+    };
+
+    assert_eq!(policy.weight("https://en.wikipedia.org/wiki/HNSW"), 2.0);
+    assert_eq!(policy.weight("https://de.wikipedia.org/wiki/HNSW"), 1.5);
+    assert_eq!(policy.weight("https://example.com/"), 1.0);
+}
+
+// The same page served from two domains: 127.0.0.1 and localhost.
+async fn web_with_two_domains() -> (MockWeb, String, String) {
+    let web = MockWeb::start().await;
+    let ip = web.url("/hnsw");
+    let localhost = format!("http://localhost:{}/hnsw", web.server.address().port());
+    Mock::given(method("GET"))
+        .and(path("/search"))
+        .and(query_param("q", QUERY))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({ "results": [
+                { "title": "HNSW on ip", "url": ip },
+                { "title": "HNSW on localhost", "url": localhost },
+            ]})),
+        )
+        .mount(&web.server)
+        .await;
+    web.page("/hnsw", &fixture("hnsw.html")).await;
+    (web, ip, localhost)
+}
+
+async fn source_urls(web: &MockWeb, domains: DomainPolicy) -> Vec<String> {
+    let chat = ScriptedChat::new(&["HNSW [1]."]);
+    let answer = builder(web, &chat)
+        .domain_policy(domains)
+        .build()
+        .await
+        .unwrap()
+        .run(QUERY)
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+    answer.chunks.into_iter().map(|chunk| chunk.url).collect()
+}
+
+#[tokio::test]
+async fn denied_domains_never_enter_the_request() {
+    let (web, ip, _) = web_with_two_domains().await;
+
+    let urls = source_urls(
+        &web,
+        DomainPolicy {
+            deny: vec!["localhost".to_string()],
+            ..Default::default()
+        },
+    )
+    .await;
+
+    assert_eq!(urls, [ip]);
+}
+
+#[tokio::test]
+async fn trust_weights_reorder_retrieved_chunks() {
+    let (web, ip, localhost) = web_with_two_domains().await;
+    let weighted = |domain: &str| DomainPolicy {
+        weights: [(domain.to_string(), 2.0)].into_iter().collect(),
+        ..Default::default()
+    };
+
+    assert_eq!(
+        source_urls(&web, weighted("localhost")).await,
+        [localhost.clone(), ip.clone()]
+    );
+    assert_eq!(
+        source_urls(&web, weighted("127.0.0.1")).await,
+        [ip, localhost]
+    );
+}
+
+#[tokio::test]
+async fn site_filter_is_sent_to_the_engine_and_enforced_locally() {
+    let web = MockWeb::start().await;
+    let localhost = format!("http://localhost:{}/hnsw", web.server.address().port());
+    // the engine ignores the operator and returns both domains anyway
+    Mock::given(method("GET"))
+        .and(path("/search"))
+        .and(query_param("q", format!("{} site:localhost", QUERY)))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({ "results": [
+                { "title": "HNSW on ip", "url": web.url("/hnsw") },
+                { "title": "HNSW on localhost", "url": localhost },
+            ]})),
+        )
+        .mount(&web.server)
+        .await;
+    web.page("/hnsw", &fixture("hnsw.html")).await;
+
+    let chat = ScriptedChat::new(&["HNSW [1]."]);
+    let answer = builder(&web, &chat)
+        .search_options(SearchOptions {
+            sites: vec!["localhost".to_string()],
+            ..Default::default()
+        })
+        .build()
+        .await
+        .unwrap()
+        .run(QUERY)
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+
+    let urls: Vec<_> = answer.sources.iter().map(|s| s.url.as_str()).collect();
+    assert_eq!(urls, [localhost.as_str()]);
+}
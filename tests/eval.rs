@@ -0,0 +1,130 @@
+mod common;
+
+use common::{chunk, FakeEmbedder, ScriptedChat};
+use fyin::eval::{self, Dataset, EvalConfig};
+use fyin::Pipeline;
+use std::path::Path;
+
+#[test]
+fn parses_single_and_grouped_citations() {
+    assert_eq!(eval::citations("A [1]. B [2, 3]. C [12]"), [1, 2, 3, 12]);
+    assert!(eval::citations("no citations here").is_empty());
+}
+
+#[test]
+fn citation_accuracy_counts_citations_to_relevant_chunks() {
+    let chunks = [chunk("https://a", "x"), chunk("https://b", "y")];
+    let relevant = ["https://a".to_string()];
+
+    assert_eq!(eval::citation_accuracy("[1] [1]", &relevant, &chunks), 1.0);
+    assert_eq!(eval::citation_accuracy("[1] [2]", &relevant, &chunks), 0.5);
+    // out of range citations are wrong
+    assert_eq!(eval::citation_accuracy("[1] [7]", &relevant, &chunks), 0.5);
+    assert_eq!(eval::citation_accuracy("uncited", &relevant, &chunks), 0.0);
+}
+
+#[test]
+fn scores_answer_text() {
+    assert_eq!(eval::exact_match("It is Paris, France.", "paris"), 1.0);
+    assert_eq!(eval::exact_match("It is Lyon.", "paris"), 0.0);
+    assert_eq!(eval::f1("the cat sat", "the cat sat"), 1.0);
+    assert_eq!(eval::f1("dog", "cat"), 0.0);
+
+    let chunks = [chunk("https://a", "HNSW descends graph layers greedily")];
+    assert_eq!(
+        eval::groundedness("HNSW descends layers greedily [1].", &chunks),
+        1.0
+    );
+    assert_eq!(
+        eval::groundedness("HNSW descends layers. Bananas contain potassium.", &chunks),
+        0.5
+    );
+}
+
+#[test]
+fn retrieval_recall_is_share_of_relevant_sources_retrieved() {
+    let chunks = [chunk("https://a", "x")];
+    let relevant = ["https://a".to_string(), "https://b".to_string()];
+    assert_eq!(eval::retrieval_recall(&relevant, &chunks), 0.5);
+}
+
+#[tokio::test]
+async fn runs_dataset_against_snapshots_and_compares_configs() {
+    let dataset =
+        Dataset::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/eval.json"))
+            .unwrap();
+
+    let good = ScriptedChat::new(&[
+        "HNSW search starts at the top layer [1].",
+        "No, Rust has memory safety without a garbage collector [1].",
+    ]);
+    let bad = ScriptedChat::new(&["Boil the pasta [2].", "Yes [2]."]);
+    let judge = ScriptedChat::new(&["CORRECT", "CORRECT"]);
+
+    let config = |name: &str| EvalConfig {
+        name: name.to_string(),
+        ..Default::default()
+    };
+    let builder = |chat: &ScriptedChat| {
+        Pipeline::builder()
+            .embedder(FakeEmbedder)
+            .chat(chat.clone())
+            .top_k(1)
+    };
+
+    let baseline = eval::run(&dataset, &config("bad"), builder(&bad), None)
+        .await
+        .unwrap();
+    let candidate = eval::run(&dataset, &config("good"), builder(&good), Some(&judge))
+        .await
+        .unwrap();
+
+    let summary = candidate.summary();
+    assert_eq!(summary.retrieval_recall, 1.0);
+    assert_eq!(summary.citation_accuracy, 1.0);
+    assert_eq!(summary.exact_match, 1.0);
+    assert_eq!(summary.judge, Some(1.0));
+    assert_eq!(baseline.summary().exact_match, 0.0);
+    assert_eq!(baseline.summary().judge, None);
+
+    let report = eval::report(&baseline, Some(&candidate));
+    assert!(report.contains("| Metric | bad | good | Δ |"));
+    assert!(report.contains("| Exact match | 0.000 | 1.000 | +1.000 |"));
+    assert!(report.contains("| rust-gc | good |"));
+}
+
+#[tokio::test]
+async fn a_failed_judge_call_fails_only_its_question() {
+    let dataset =
+        Dataset::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/eval.json"))
+            .unwrap();
+    let chat = ScriptedChat::new(&[
+        "HNSW search starts at the top layer [1].",
+        "No, Rust has memory safety without a garbage collector [1].",
+    ]);
+    // runs out of replies on the second question
+    let judge = ScriptedChat::new(&["CORRECT"]);
+    let config = EvalConfig {
+        name: "judged | flaky".to_string(),
+        ..Default::default()
+    };
+    let builder = Pipeline::builder()
+        .embedder(FakeEmbedder)
+        .chat(chat)
+        .top_k(1);
+
+    let run = eval::run(&dataset, &config, builder, Some(&judge))
+        .await
+        .unwrap();
+
+    assert_eq!(run.results.len(), 2);
+    assert_eq!(run.results[0].error, None);
+    assert_eq!(run.results[0].scores.judge, Some(1.0));
+    let error = run.results[1].error.as_deref().unwrap();
+    assert!(error.starts_with("Judging failed"));
+    assert!(!run.results[1].answer.is_empty());
+
+    let report = eval::report(&run, None);
+    assert!(report.contains("| Metric | judged \\| flaky |"));
+    assert!(report.contains("| rust-gc | judged \\| flaky |"));
+}
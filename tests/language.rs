@@ -0,0 +1,123 @@
+mod common;
+
+use common::{builder, fixture, pipeline, MockWeb, ScriptedChat};
+use fyin::language;
+
+const QUERY: &str = "Wie funktioniert die Suche in einem HNSW-Index?";
+const TRANSLATION: &str = "How does HNSW search work";
+
+const GERMAN_PAGE: &str = "<html><head><title>HNSW</title></head><body><article>
+<p>Der HNSW-Index baut einen Graphen aus mehreren Ebenen auf. Die Suche beginnt auf der
+obersten Ebene und geht dann Schritt für Schritt zu den unteren Ebenen, bis sie die
+nächsten Nachbarn der Anfrage gefunden hat.</p>
+</article></body></html>";
+
+async fn web_with_pages() -> MockWeb {
+    let web = MockWeb::start().await;
+    web.search_pages(QUERY, &[("HNSW erklärt", "/hnsw-de", GERMAN_PAGE)])
+        .await;
+    web.search_pages(
+        TRANSLATION,
+        &[("HNSW explained", "/hnsw", &fixture("hnsw.html"))],
+    )
+    .await;
+    web
+}
+
+#[test]
+fn detects_the_language_of_sentences() {
+    assert_eq!(language::detect(QUERY), Some("deu"));
+    assert_eq!(
+        language::detect("量子コンピュータとは何ですか"),
+        Some("jpn")
+    );
+    assert_eq!(language::name("deu"), Some("German"));
+    // too short to tell
+    assert_eq!(language::detect("What is HNSW?"), None);
+}
+
+#[tokio::test]
+async fn stores_the_language_of_scraped_pages() {
+    let web = web_with_pages().await;
+    let chat = ScriptedChat::new(&["Die Suche geht von oben nach unten [1]."]);
+
+    let answer = pipeline(&web, &chat)
+        .await
+        .run(QUERY)
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+
+    assert_eq!(answer.sources.len(), 1);
+    assert_eq!(answer.sources[0].language.as_deref(), Some("deu"));
+    let prompts = chat.prompts();
+    assert_eq!(prompts.len(), 1);
+    assert!(prompts[0].contains("Write the answer in German."));
+}
+
+#[tokio::test]
+async fn searches_with_a_translated_query() {
+    let web = web_with_pages().await;
+    let chat = ScriptedChat::new(&[
+        &format!("\"{}\"", TRANSLATION),
+        "Die Suche beginnt oben [1] und läuft gierig durch den Graphen [2].",
+    ]);
+
+    let answer = builder(&web, &chat)
+        .translate_query(true)
+        .build()
+        .await
+        .unwrap()
+        .run(QUERY)
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+
+    let mut urls: Vec<_> = answer.sources.iter().map(|s| s.url.clone()).collect();
+    urls.sort();
+    assert_eq!(urls, vec![web.url("/hnsw"), web.url("/hnsw-de")]);
+    let english = answer
+        .sources
+        .iter()
+        .find(|s| s.url == web.url("/hnsw"))
+        .unwrap();
+    assert_eq!(english.language.as_deref(), Some("eng"));
+    assert!(answer.chunks.iter().any(|c| c.url == web.url("/hnsw")));
+
+    let prompts = chat.prompts();
+    assert_eq!(prompts.len(), 2);
+    assert!(prompts[0].contains("into English"));
+    assert!(prompts[0].contains(QUERY));
+    assert!(prompts[1].contains("Write the answer in German."));
+    assert!(prompts[1].contains("greedily walks"));
+}
+
+#[tokio::test]
+async fn does_not_translate_english_queries() {
+    let query = "Who won the world cup and how does HNSW search work";
+    let web = MockWeb::start().await;
+    web.search_results(query, &[("HNSW explained", "/hnsw")])
+        .await;
+    web.page("/hnsw", &fixture("hnsw.html")).await;
+    let chat = ScriptedChat::new(&["HNSW walks a layered graph [1]."]);
+
+    builder(&web, &chat)
+        .translate_query(true)
+        .build()
+        .await
+        .unwrap()
+        .run(query)
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+
+    let prompts = chat.prompts();
+    assert_eq!(prompts.len(), 1);
+    assert!(!prompts[0].contains("into English"));
+}
@@ -0,0 +1,212 @@
+mod common;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use common::{builder, pipeline, web_with_pages, MockWeb, ScriptedChat};
+use fyin::search::SearchOptions;
+use fyin::{mcp, Collection, Pipeline, RunOptions, SearchProvider, SearchResult};
+use serde_json::{json, Value};
+
+const QUERY: &str = "How does HNSW search work";
+
+// Sends `messages` as one session and returns the responses
+async fn session(pipeline: &Pipeline, messages: &[Value]) -> Vec<Value> {
+    let input: String = messages.iter().map(|m| format!("{}\n", m)).collect();
+    let mut output = vec![];
+    mcp::serve(pipeline, input.as_bytes(), &mut output)
+        .await
+        .unwrap();
+    String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+fn call(id: u64, name: &str, arguments: Value) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": "tools/call",
+        "params": { "name": name, "arguments": arguments }
+    })
+}
+
+fn text(response: &Value) -> &str {
+    response["result"]["content"][0]["text"].as_str().unwrap()
+}
+
+#[tokio::test]
+async fn initializes_and_lists_tools() {
+    let web = MockWeb::start().await;
+    let chat = ScriptedChat::new(&[]);
+
+    let responses = session(
+        &pipeline(&web, &chat).await,
+        &[
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {
+                "protocolVersion": "2024-11-05", "capabilities": {},
+                "clientInfo": { "name": "test", "version": "1" }
+            }}),
+            json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "tools/list" }),
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "resources/list" }),
+        ],
+    )
+    .await;
+
+    assert_eq!(responses.len(), 3);
+    assert_eq!(responses[0]["result"]["protocolVersion"], "2024-11-05");
+    assert_eq!(responses[0]["result"]["serverInfo"]["name"], "fyin");
+    let tools: Vec<_> = responses[1]["result"]["tools"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|tool| tool["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        tools,
+        ["web_search", "fetch_page", "ask", "search_collection"]
+    );
+    assert_eq!(responses[2]["id"], 3);
+    assert_eq!(responses[2]["error"]["code"], -32601);
+}
+
+#[tokio::test]
+async fn searches_fetches_and_answers() {
+    let web = web_with_pages(QUERY).await;
+    let chat = ScriptedChat::new(&["It walks a layered graph [1]."]);
+
+    let responses = session(
+        &pipeline(&web, &chat).await,
+        &[
+            call(1, "web_search", json!({ "query": QUERY })),
+            call(2, "fetch_page", json!({ "url": web.url("/hnsw") })),
+            call(3, "ask", json!({ "query": QUERY })),
+        ],
+    )
+    .await;
+
+    let results: Vec<Value> = serde_json::from_str(text(&responses[0])).unwrap();
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0]["name"], "HNSW explained");
+    assert_eq!(results[0]["url"], web.url("/hnsw"));
+    assert!(text(&responses[1]).contains("greedily walks"));
+    let answer = text(&responses[2]);
+    assert!(answer.starts_with("It walks a layered graph [1]."));
+    assert!(answer.contains(&format!("[1] HNSW explained - {}", web.url("/hnsw"))));
+}
+
+// Returns one result with its text, like Wikipedia and arXiv do
+struct Extracts;
+
+#[async_trait]
+impl SearchProvider for Extracts {
+    async fn search(
+        &self,
+        _query: &str,
+        _count: usize,
+        _options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>> {
+        Ok(vec![SearchResult {
+            name: "Ada Lovelace".to_string(),
+            url: "https://en.wikipedia.org/wiki/Ada_Lovelace".to_string(),
+            content: Some("Ada Lovelace was an English mathematician.".to_string()),
+            published: None,
+            language: None,
+        }])
+    }
+}
+
+#[tokio::test]
+async fn search_results_include_source_extracts() {
+    let web = MockWeb::start().await;
+    let chat = ScriptedChat::new(&[]);
+    let pipeline = builder(&web, &chat)
+        .search_provider(Extracts)
+        .build()
+        .await
+        .unwrap();
+
+    let responses = session(
+        &pipeline,
+        &[call(
+            1,
+            "web_search",
+            json!({ "query": "Who was Ada Lovelace?" }),
+        )],
+    )
+    .await;
+
+    let results: Vec<Value> = serde_json::from_str(text(&responses[0])).unwrap();
+    assert_eq!(
+        results[0]["content"],
+        "Ada Lovelace was an English mathematician."
+    );
+}
+
+#[tokio::test]
+async fn reports_tool_failures_as_errors() {
+    let web = MockWeb::start().await;
+    web.failing_page("/gone", 404).await;
+    let chat = ScriptedChat::new(&[]);
+
+    let responses = session(
+        &pipeline(&web, &chat).await,
+        &[
+            call(1, "fetch_page", json!({ "url": web.url("/gone") })),
+            call(2, "web_search", json!({})),
+            call(3, "delete_everything", json!({})),
+        ],
+    )
+    .await;
+
+    assert_eq!(responses[0]["result"]["isError"], true);
+    assert_eq!(responses[1]["result"]["isError"], true);
+    assert!(text(&responses[1]).contains("query"));
+    assert_eq!(responses[2]["error"]["code"], -32602);
+}
+
+#[tokio::test]
+async fn searches_a_saved_collection() {
+    let data_dir = std::env::temp_dir().join(format!("fyin-mcp-{}", std::process::id()));
+    std::env::set_var("FYIN_DATA_DIR", &data_dir);
+    let web = web_with_pages(QUERY).await;
+    let chat = ScriptedChat::new(&["It walks a layered graph [1]."]);
+    let pipeline = pipeline(&web, &chat).await;
+    let mut collection = Collection::open("graphs").unwrap();
+    let options = RunOptions {
+        collection: Some(&mut collection),
+        ..Default::default()
+    };
+    pipeline
+        .run_with(QUERY, options)
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+
+    let responses = session(
+        &pipeline,
+        &[
+            call(
+                1,
+                "search_collection",
+                json!({ "collection": "graphs", "query": "greedily walks layered graph" }),
+            ),
+            call(
+                2,
+                "search_collection",
+                json!({ "collection": "empty", "query": "anything" }),
+            ),
+        ],
+    )
+    .await;
+    std::fs::remove_dir_all(&data_dir).unwrap();
+
+    let chunks: Vec<Value> = serde_json::from_str(text(&responses[0])).unwrap();
+    assert!(!chunks.is_empty());
+    assert_eq!(chunks[0]["url"], web.url("/hnsw"));
+    assert_eq!(responses[1]["result"]["isError"], true);
+}
@@ -0,0 +1,383 @@
+mod common;
+
+use common::{builder, drain, fixture, pipeline, web_with_pages, MockWeb, ScriptedChat};
+use fyin::search::{SearchOptions, Wikipedia};
+use fyin::{scraper, PipelineEvent, RunOptions, SearchResult};
+use serde_json::json;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use wiremock::matchers::{method, path, query_param};
+use wiremock::{Mock, ResponseTemplate};
+
+const QUERY: &str = "How does HNSW search work";
+
+#[tokio::test]
+async fn answers_from_scraped_pages() {
+    let web = web_with_pages(QUERY).await;
+    let chat = ScriptedChat::new(&["HNSW walks a layered graph [1]."]);
+
+    let answer = pipeline(&web, &chat)
+        .await
+        .run(QUERY)
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+
+    assert_eq!(answer.text, "HNSW walks a layered graph [1].");
+    assert_eq!(answer.sources.len(), 3);
+    assert_eq!(answer.chunks.len(), 3);
+    assert_eq!(answer.chunks[0].url, web.url("/hnsw"));
+    assert!(answer.chunks[0].content.contains("greedily walks"));
+
+    let prompts = chat.prompts();
+    assert_eq!(prompts.len(), 1);
+    assert!(prompts[0].contains(QUERY));
+    assert!(prompts[0].contains("Hierarchical Navigable Small World"));
+}
+
+#[tokio::test]
+async fn reports_progress_events_in_stage_order() {
+    let web = web_with_pages(QUERY).await;
+    let chat = ScriptedChat::new(&["Graph search [1]."]);
+    let (sender, receiver) = mpsc::unbounded_channel();
+
+    let options = RunOptions {
+        events: Some(sender),
+        ..Default::default()
+    };
+    let output = pipeline(&web, &chat)
+        .await
+        .run_with(QUERY, options)
+        .await
+        .unwrap();
+    output.collect().await.unwrap();
+    let events = drain(receiver).await;
+
+    let position = |name: &str| {
+        events
+            .iter()
+            .position(|e| format!("{:?}", e).starts_with(name))
+            .unwrap_or_else(|| panic!("no {} event", name))
+    };
+    assert!(position("SearchStarted") < position("ResultFound"));
+    assert!(position("ResultFound") < position("PageScraped"));
+    assert!(position("PageScraped") < position("ChunkEmbedded"));
+    assert!(position("ChunkEmbedded") < position("RetrievalDone"));
+    assert!(position("RetrievalDone") < position("Token"));
+    assert!(matches!(events.last(), Some(PipelineEvent::Done)));
+
+    let tokens: String = events
+        .iter()
+        .filter_map(|e| match e {
+            PipelineEvent::Token { text } => Some(text.as_str()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(tokens, "Graph search [1].");
+}
+
+#[tokio::test]
+async fn skips_pages_that_fail_to_load() {
+    let web = MockWeb::start().await;
+    web.search_results(
+        QUERY,
+        &[("HNSW explained", "/hnsw"), ("Broken page", "/broken")],
+    )
+    .await;
+    web.page("/hnsw", &fixture("hnsw.html")).await;
+    web.failing_page("/broken", 500).await;
+
+    let chat = ScriptedChat::new(&["Only one source [1]."]);
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let options = RunOptions {
+        events: Some(sender),
+        ..Default::default()
+    };
+
+    let answer = pipeline(&web, &chat)
+        .await
+        .run_with(QUERY, options)
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+    let events = drain(receiver).await;
+
+    assert_eq!(answer.sources.len(), 2);
+    assert_eq!(answer.chunks.len(), 1);
+    assert_eq!(answer.chunks[0].url, web.url("/hnsw"));
+    assert!(events.iter().any(|e| matches!(
+        e,
+        PipelineEvent::PageFailed { url, .. } if *url == web.url("/broken")
+    )));
+}
+
+#[tokio::test]
+async fn answers_without_sources_when_search_is_empty() {
+    let web = MockWeb::start().await;
+    web.search_results(QUERY, &[]).await;
+
+    let chat = ScriptedChat::new(&["I don't know."]);
+    let answer = pipeline(&web, &chat)
+        .await
+        .run(QUERY)
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+
+    assert!(answer.sources.is_empty());
+    assert!(answer.chunks.is_empty());
+    assert_eq!(answer.text, "I don't know.");
+    assert!(!chat.prompts()[0].contains("url:"));
+}
+
+#[tokio::test]
+async fn citation_numbers_follow_chunk_order() {
+    let web = web_with_pages(QUERY).await;
+    let chat = ScriptedChat::new(&["See [1] and [2]."]);
+
+    let answer = pipeline(&web, &chat)
+        .await
+        .run(QUERY)
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+    let prompt = &chat.prompts()[0];
+
+    // every chunk appears in the prompt with id = its position + 1
+    for (i, chunk) in answer.chunks.iter().enumerate() {
+        let source = format!(
+            "<source id=\"{}\">\nName: {}\nurl: {}\nfact: {}\n</source>\n",
+            i + 1,
+            chunk.name,
+            chunk.url,
+            chunk.content
+        );
+        assert!(
+            prompt.contains(&source),
+            "chunk {} is not cited as [{}]",
+            i,
+            i + 1
+        );
+    }
+}
+
+#[tokio::test]
+async fn fails_when_search_engine_errors() {
+    let web = MockWeb::start().await;
+    let chat = ScriptedChat::new(&[]);
+
+    // nothing is mounted on /search, so the mock server answers 404
+    let result = pipeline(&web, &chat).await.run(QUERY).await;
+
+    assert!(result.is_err());
+    assert!(chat.prompts().is_empty());
+}
+
+#[tokio::test]
+async fn deadline_answers_without_slow_pages() {
+    let web = MockWeb::start().await;
+    web.search_results(
+        QUERY,
+        &[("HNSW explained", "/hnsw"), ("Slow page", "/slow")],
+    )
+    .await;
+    web.page("/hnsw", &fixture("hnsw.html")).await;
+    web.slow_page("/slow", &fixture("rust.html"), Duration::from_secs(30))
+        .await;
+
+    let chat = ScriptedChat::new(&["HNSW is a graph [1]."]);
+    let pipeline = builder(&web, &chat)
+        .deadline(Duration::from_millis(200))
+        .build()
+        .await
+        .unwrap();
+
+    let started = Instant::now();
+    let answer = pipeline.run(QUERY).await.unwrap().collect().await.unwrap();
+
+    assert!(started.elapsed() < Duration::from_secs(10));
+    assert_eq!(answer.chunks.len(), 1);
+    assert_eq!(answer.chunks[0].url, web.url("/hnsw"));
+}
+
+#[tokio::test]
+async fn deadline_waits_for_a_first_chunk() {
+    let web = MockWeb::start().await;
+    web.search_results(QUERY, &[("Slow page", "/slow")]).await;
+    web.slow_page("/slow", &fixture("hnsw.html"), Duration::from_millis(300))
+        .await;
+
+    let chat = ScriptedChat::new(&["HNSW is a graph [1]."]);
+    let pipeline = builder(&web, &chat)
+        .deadline(Duration::from_millis(10))
+        .build()
+        .await
+        .unwrap();
+
+    let answer = pipeline.run(QUERY).await.unwrap().collect().await.unwrap();
+
+    assert_eq!(answer.chunks.len(), 1);
+}
+
+#[tokio::test]
+async fn min_chunks_starts_retrieval_early() {
+    let web = MockWeb::start().await;
+    web.search_results(
+        QUERY,
+        &[("HNSW explained", "/hnsw"), ("Slow page", "/slow")],
+    )
+    .await;
+    web.page("/hnsw", &fixture("hnsw.html")).await;
+    web.slow_page("/slow", &fixture("rust.html"), Duration::from_secs(30))
+        .await;
+
+    let chat = ScriptedChat::new(&["HNSW is a graph [1]."]);
+    let pipeline = builder(&web, &chat).min_chunks(1).build().await.unwrap();
+
+    let started = Instant::now();
+    let answer = pipeline.run(QUERY).await.unwrap().collect().await.unwrap();
+
+    assert!(started.elapsed() < Duration::from_secs(10));
+    assert_eq!(answer.chunks.len(), 1);
+    assert_eq!(answer.sources.len(), 2);
+}
+
+#[tokio::test]
+async fn embeds_provider_text_without_scraping() {
+    let web = MockWeb::start().await;
+    Mock::given(method("GET"))
+        .and(path("/w/api.php"))
+        .and(query_param("list", "search"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "query": { "search": [{ "title": "HNSW" }] }
+        })))
+        .mount(&web.server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/w/api.php"))
+        .and(query_param("titles", "HNSW"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "query": { "pages": [{
+                "title": "HNSW",
+                "fullurl": web.url("/wiki/HNSW"),
+                "extract": "HNSW search greedily walks a layered graph of small worlds."
+            }]}
+        })))
+        .mount(&web.server)
+        .await;
+    let chat = ScriptedChat::new(&["It walks a graph [1]."]);
+
+    let answer = builder(&web, &chat)
+        .search_provider(Wikipedia::new(&web.url("/w/api.php")))
+        .build()
+        .await
+        .unwrap()
+        .run(QUERY)
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+
+    assert_eq!(answer.chunks.len(), 1);
+    assert_eq!(answer.chunks[0].url, web.url("/wiki/HNSW"));
+    assert!(answer.chunks[0].content.contains("layered graph"));
+    let requests = web.server.received_requests().await.unwrap();
+    assert!(requests.iter().all(|r| r.url.path() == "/w/api.php"));
+}
+
+#[tokio::test]
+async fn answers_only_from_given_documents() {
+    let web = web_with_pages(QUERY).await;
+    let notes = std::env::temp_dir().join(format!("fyin-hnsw-{}.txt", std::process::id()));
+    std::fs::write(&notes, "HNSW keeps a layered graph of small worlds.").unwrap();
+    let chat = ScriptedChat::new(&["From the notes and the page [1][2]."]);
+    let documents = vec![
+        scraper::read_file(&notes).await.unwrap(),
+        SearchResult {
+            name: "HNSW explained".to_string(),
+            url: web.url("/hnsw"),
+            content: None,
+            published: None,
+            language: None,
+        },
+    ];
+
+    let options = RunOptions {
+        documents,
+        ..Default::default()
+    };
+    let answer = builder(&web, &chat)
+        .search_options(SearchOptions {
+            sites: vec!["example.com".to_string()],
+            ..Default::default()
+        })
+        .build()
+        .await
+        .unwrap()
+        .run_with(QUERY, options)
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+    std::fs::remove_file(&notes).unwrap();
+
+    assert_eq!(answer.sources.len(), 2);
+    let mut urls: Vec<_> = answer.chunks.iter().map(|c| c.url.clone()).collect();
+    urls.sort();
+    assert_eq!(urls.len(), 2);
+    assert!(urls[0].starts_with("file://"));
+    assert_eq!(urls[1], web.url("/hnsw"));
+    let requests = web.server.received_requests().await.unwrap();
+    let paths: Vec<_> = requests.iter().map(|r| r.url.path()).collect();
+    assert_eq!(paths, ["/hnsw"]);
+}
+
+#[tokio::test]
+async fn suggests_follow_ups_grounded_in_chunks() {
+    let web = web_with_pages(QUERY).await;
+    let chat = ScriptedChat::new(&[
+        "HNSW walks a layered graph [1].",
+        "Here are some questions:\n1. How is an HNSW index built?\n2) How is an HNSW index built?\n- \"Why are small world graphs fast?\"\n\n3. a\n4. b\n5. c\n6. d",
+    ]);
+    let pipeline = pipeline(&web, &chat).await;
+
+    let answer = pipeline.run(QUERY).await.unwrap().collect().await.unwrap();
+    let questions = pipeline.follow_ups(&answer).await;
+
+    assert_eq!(
+        questions,
+        [
+            "How is an HNSW index built?",
+            "Why are small world graphs fast?",
+            "a",
+            "b",
+            "c"
+        ]
+    );
+    let prompt = &chat.prompts()[1];
+    assert!(prompt.contains("HNSW walks a layered graph [1]."));
+    assert!(prompt.contains("greedily walks"));
+}
+
+#[tokio::test]
+async fn skips_follow_ups_without_sources() {
+    let web = MockWeb::start().await;
+    web.search_results(QUERY, &[]).await;
+    let chat = ScriptedChat::new(&["I don't know."]);
+    let pipeline = pipeline(&web, &chat).await;
+
+    let answer = pipeline.run(QUERY).await.unwrap().collect().await.unwrap();
+
+    assert!(pipeline.follow_ups(&answer).await.is_empty());
+    assert_eq!(chat.prompts().len(), 1);
+}
@@ -0,0 +1,130 @@
+mod common;
+
+use common::{drain, fixture, pipeline, MockWeb, ScriptedChat};
+use fyin::research::{self, Budget, Stop};
+use fyin::PipelineEvent;
+use tokio::sync::mpsc;
+
+const QUERY: &str =
+    "Which language is the search library behind HNSW demos written in, and how fast is it";
+const LIBRARY: &str = "Which library implements HNSW search";
+const LANGUAGE: &str = "What language is the hnswlib library written in";
+const SPEED: &str = "hnswlib C++ benchmark speed";
+
+const HNSWLIB_PAGE: &str = "<html><head><title>hnswlib</title></head><body><article>
+<p>hnswlib is a header-only C++ library with Python bindings. Benchmarks show it answering
+millions of nearest-neighbor queries per second on a single machine.</p>
+</article></body></html>";
+
+async fn web_with_pages() -> MockWeb {
+    let web = MockWeb::start().await;
+    web.search_pages(
+        LIBRARY,
+        &[("HNSW explained", "/hnsw", &fixture("hnsw.html"))],
+    )
+    .await;
+    web.search_pages(LANGUAGE, &[("Rust", "/rust", &fixture("rust.html"))])
+        .await;
+    web.search_pages(SPEED, &[("hnswlib", "/hnswlib", HNSWLIB_PAGE)])
+        .await;
+    web
+}
+
+#[tokio::test]
+async fn searches_again_for_what_is_still_unknown() {
+    let web = web_with_pages().await;
+    let chat = ScriptedChat::new(&[
+        &format!("1. {}\n2. {}", LIBRARY, LANGUAGE),
+        &format!("{}\n{}", LIBRARY, SPEED),
+        "DONE",
+        "## Summary\nhnswlib is written in C++ [1].",
+    ]);
+    let (sender, receiver) = mpsc::unbounded_channel();
+
+    let research = pipeline(&web, &chat)
+        .await
+        .research(QUERY, Budget::default(), Some(sender))
+        .await
+        .unwrap();
+    let trace = research.trace.clone();
+    let report = research.output.collect().await.unwrap();
+    let events = drain(receiver).await;
+
+    assert_eq!(trace.stop, Stop::Answered);
+    assert_eq!(trace.rounds.len(), 2);
+    assert_eq!(trace.rounds[0].questions, vec![LIBRARY, LANGUAGE]);
+    assert_eq!(trace.rounds[0].pages, 2);
+    // a question that was already searched is not searched again
+    assert_eq!(trace.rounds[1].questions, vec![SPEED]);
+    assert_eq!(trace.rounds[1].pages, 1);
+    assert!(trace.tokens > 0);
+    assert_eq!(report.text, "## Summary\nhnswlib is written in C++ [1].");
+    assert_eq!(report.sources.len(), 3);
+
+    let rounds: Vec<_> = events
+        .iter()
+        .filter_map(|event| match event {
+            PipelineEvent::ResearchRound { round, .. } => Some(*round),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(rounds, vec![1, 2]);
+
+    let prompts = chat.prompts();
+    assert_eq!(prompts.len(), 4);
+    assert!(prompts[1].contains("ALREADY SEARCHED"));
+    assert!(prompts[1].contains(LANGUAGE));
+    assert!(prompts[3].contains("research report"));
+    assert!(prompts[3].contains("header-only C++ library"));
+}
+
+#[tokio::test]
+async fn stops_when_the_budget_runs_out() {
+    let web = web_with_pages().await;
+    let chat = ScriptedChat::new(&[&format!("{}\n{}", LIBRARY, LANGUAGE), "A report [1]."]);
+    let budget = Budget {
+        rounds: 1,
+        ..Budget::default()
+    };
+
+    let research = pipeline(&web, &chat)
+        .await
+        .research(QUERY, budget, None)
+        .await
+        .unwrap();
+
+    assert_eq!(research.trace.stop, Stop::Rounds);
+    assert_eq!(research.trace.rounds.len(), 1);
+    // the last round is not judged, since no search could follow it
+    assert_eq!(chat.prompts().len(), 2);
+    assert!(research.trace.to_text().contains("round budget used up"));
+}
+
+#[tokio::test]
+async fn searches_only_as_many_pages_as_the_budget_allows() {
+    let web = web_with_pages().await;
+    let chat = ScriptedChat::new(&[&format!("{}\n{}", LIBRARY, LANGUAGE), "A report [1]."]);
+    let budget = Budget {
+        pages: 1,
+        ..Budget::default()
+    };
+
+    let research = pipeline(&web, &chat)
+        .await
+        .research(QUERY, budget, None)
+        .await
+        .unwrap();
+
+    assert_eq!(research.trace.stop, Stop::Pages);
+    assert_eq!(research.trace.rounds.len(), 1);
+    assert_eq!(research.trace.rounds[0].questions, vec![LIBRARY]);
+    assert_eq!(research.trace.rounds[0].pages, 1);
+    assert_eq!(chat.prompts().len(), 2);
+}
+
+#[test]
+fn estimates_four_characters_per_token() {
+    assert_eq!(research::estimate_tokens(""), 0);
+    assert_eq!(research::estimate_tokens("abcd"), 1);
+    assert_eq!(research::estimate_tokens("abcde"), 2);
+}
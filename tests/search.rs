@@ -0,0 +1,447 @@
+mod common;
+
+use common::MockWeb;
+use fyin::scraper::WebScraper;
+use fyin::search::{
+    self, Arxiv, Bing, DuckDuckGo, Router, SafeSearch, SearchOptions, Searxng, Source, TimeRange,
+    Wikipedia,
+};
+use fyin::SearchProvider;
+use serde_json::json;
+use std::sync::Arc;
+use wiremock::matchers::{header, method, path, query_param};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn bing_sends_key_and_reads_web_pages() {
+    let web = MockWeb::start().await;
+    Mock::given(method("GET"))
+        .and(path("/bing"))
+        .and(query_param("q", "rust"))
+        .and(query_param("count", "2"))
+        .and(header("Ocp-Apim-Subscription-Key", "secret"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "webPages": { "value": [
+                { "name": "Rust", "url": "https://www.rust-lang.org/" },
+                { "name": "Rust book", "url": "https://doc.rust-lang.org/book/" }
+            ]}
+        })))
+        .mount(&web.server)
+        .await;
+
+    let results = Bing::new(&web.url("/bing"), "secret")
+        .search("rust", 2, &SearchOptions::default())
+        .await
+        .unwrap();
+
+    let urls: Vec<_> = results.iter().map(|r| r.url.as_str()).collect();
+    assert_eq!(
+        urls,
+        [
+            "https://www.rust-lang.org/",
+            "https://doc.rust-lang.org/book/"
+        ]
+    );
+    assert_eq!(results[0].name, "Rust");
+}
+
+#[tokio::test]
+async fn duckduckgo_flattens_topic_groups() {
+    let web = MockWeb::start().await;
+    Mock::given(method("GET"))
+        .and(path("/ddg"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "Results": [],
+            "RelatedTopics": [
+                { "Text": "Rust (language)", "FirstURL": "https://duckduckgo.com/Rust" },
+                { "Name": "Games", "Topics": [
+                    { "Text": "Rust (game)", "FirstURL": "https://duckduckgo.com/Rust_game" }
+                ]}
+            ]
+        })))
+        .mount(&web.server)
+        .await;
+
+    let results = DuckDuckGo::new(&web.url("/ddg"))
+        .search("rust", 10, &SearchOptions::default())
+        .await
+        .unwrap();
+
+    let names: Vec<_> = results.iter().map(|r| r.name.as_str()).collect();
+    assert_eq!(names, ["Rust (language)", "Rust (game)"]);
+}
+
+#[tokio::test]
+async fn bing_maps_site_filters_to_operators() {
+    let web = MockWeb::start().await;
+    Mock::given(method("GET"))
+        .and(path("/bing"))
+        .and(query_param(
+            "q",
+            "rust (site:rust-lang.org OR site:docs.rs) -site:pinterest.com",
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "webPages": { "value": [
+                { "name": "Rust", "url": "https://www.rust-lang.org/" }
+            ]}
+        })))
+        .mount(&web.server)
+        .await;
+
+    let options = SearchOptions {
+        sites: vec!["rust-lang.org".to_string(), "docs.rs".to_string()],
+        exclude_sites: vec!["pinterest.com".to_string()],
+        ..Default::default()
+    };
+    let results = Bing::new(&web.url("/bing"), "secret")
+        .search("rust", 2, &options)
+        .await
+        .unwrap();
+
+    assert_eq!(results.len(), 1);
+}
+
+fn options(time_range: TimeRange) -> SearchOptions {
+    SearchOptions {
+        market: Some("de-DE".to_string()),
+        time_range: Some(time_range),
+        safe_search: Some(SafeSearch::Strict),
+        offset: 20,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn bing_translates_locale_freshness_and_paging() {
+    let web = MockWeb::start().await;
+    Mock::given(method("GET"))
+        .and(path("/bing"))
+        .and(query_param("mkt", "de-DE"))
+        .and(query_param("freshness", "Week"))
+        .and(query_param("safeSearch", "Strict"))
+        .and(query_param("offset", "20"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "webPages": { "value": [{ "name": "Rust", "url": "https://www.rust-lang.org/" }]}
+        })))
+        .mount(&web.server)
+        .await;
+
+    let results = Bing::new(&web.url("/bing"), "secret")
+        .search("rust", 10, &options(TimeRange::Week))
+        .await
+        .unwrap();
+
+    assert_eq!(results.len(), 1);
+}
+
+#[tokio::test]
+async fn bing_sends_past_year_as_a_date_range() {
+    let web = MockWeb::start().await;
+    let today = chrono::Utc::now().date_naive();
+    let range = format!("{}..{}", today - chrono::Duration::days(365), today);
+    Mock::given(method("GET"))
+        .and(path("/bing"))
+        .and(query_param("freshness", range.as_str()))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({ "webPages": { "value": [] } })),
+        )
+        .expect(1)
+        .mount(&web.server)
+        .await;
+
+    Bing::new(&web.url("/bing"), "secret")
+        .search("rust", 10, &options(TimeRange::Year))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn searxng_translates_options_and_pages_by_offset() {
+    let web = MockWeb::start().await;
+    let results: Vec<_> = (0..10)
+        .map(|i| json!({ "title": format!("Result {}", i), "url": format!("https://example.com/{}", i) }))
+        .collect();
+    Mock::given(method("GET"))
+        .and(path("/search"))
+        .and(query_param("language", "de-DE"))
+        .and(query_param("time_range", "month"))
+        .and(query_param("safesearch", "2"))
+        .and(query_param("pageno", "2"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "results": results })))
+        .mount(&web.server)
+        .await;
+
+    let options = SearchOptions {
+        offset: 13,
+        ..options(TimeRange::Month)
+    };
+    let results = Searxng::new(&web.searxng_endpoint())
+        .search("rust", 2, &options)
+        .await
+        .unwrap();
+
+    let names: Vec<_> = results.iter().map(|r| r.name.as_str()).collect();
+    assert_eq!(names, ["Result 3", "Result 4"]);
+}
+
+#[tokio::test]
+async fn duckduckgo_translates_options_and_skips_offset() {
+    let web = MockWeb::start().await;
+    let topics: Vec<_> = (0..25)
+        .map(|i| json!({ "Text": format!("Topic {}", i), "FirstURL": format!("https://duckduckgo.com/{}", i) }))
+        .collect();
+    Mock::given(method("GET"))
+        .and(path("/ddg"))
+        .and(query_param("kl", "de-de"))
+        .and(query_param("df", "d"))
+        .and(query_param("kp", "1"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "Results": [],
+            "RelatedTopics": topics
+        })))
+        .mount(&web.server)
+        .await;
+
+    let results = DuckDuckGo::new(&web.url("/ddg"))
+        .search("rust", 10, &options(TimeRange::Day))
+        .await
+        .unwrap();
+
+    let names: Vec<_> = results.iter().map(|r| r.name.as_str()).collect();
+    assert_eq!(
+        names,
+        ["Topic 20", "Topic 21", "Topic 22", "Topic 23", "Topic 24"]
+    );
+}
+
+#[tokio::test]
+async fn wikipedia_returns_article_extracts() {
+    let web = MockWeb::start().await;
+    Mock::given(method("GET"))
+        .and(path("/w/api.php"))
+        .and(query_param("list", "search"))
+        .and(query_param("srsearch", "hnsw"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "query": { "search": [{ "title": "HNSW" }, { "title": "Missing" }] }
+        })))
+        .mount(&web.server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/w/api.php"))
+        .and(query_param("prop", "extracts|info"))
+        .and(query_param("titles", "HNSW"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "query": { "pages": [{
+                "title": "Hierarchical navigable small world",
+                "fullurl": "https://en.wikipedia.org/wiki/HNSW",
+                "touched": "2024-02-03T04:05:06Z",
+                "extract": "HNSW is a graph-based\u{200B} index."
+            }]}
+        })))
+        .mount(&web.server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/w/api.php"))
+        .and(query_param("titles", "Missing"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "query": { "pages": [{ "title": "Missing", "missing": true }] }
+        })))
+        .mount(&web.server)
+        .await;
+
+    let results = Wikipedia::new(&web.url("/w/api.php"))
+        .search("hnsw", 5, &SearchOptions::default())
+        .await
+        .unwrap();
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].url, "https://en.wikipedia.org/wiki/HNSW");
+    assert_eq!(
+        results[0].content.as_deref(),
+        Some("HNSW is a graph-based index.")
+    );
+    assert_eq!(results[0].published.unwrap().to_string(), "2024-02-03");
+}
+
+fn arxiv_feed(pdf_url: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom" xmlns:arxiv="http://arxiv.org/schemas/atom">
+  <title>arXiv Query</title>
+  <link href="http://arxiv.org/api/query" rel="self" type="application/atom+xml"/>
+  <entry>
+    <id>http://arxiv.org/abs/1603.09320v4</id>
+    <updated>2018-08-14T12:00:00Z</updated>
+    <published>2016-03-30T12:00:00Z</published>
+    <title>Efficient and robust approximate nearest neighbor
+      search using HNSW graphs</title>
+    <summary>  We present a new approach for approximate
+      nearest neighbor search. </summary>
+    <author><name>Yu. A. Malkov</name></author>
+    <arxiv:comment>13 pages</arxiv:comment>
+    <link href="http://arxiv.org/abs/1603.09320v4" rel="alternate" type="text/html"/>
+    <link title="pdf" href="{}" rel="related" type="application/pdf"/>
+  </entry>
+</feed>"#,
+        pdf_url
+    )
+}
+
+#[tokio::test]
+async fn arxiv_returns_abstract_and_pdf_text() {
+    let web = MockWeb::start().await;
+    let pdf = std::fs::read(format!(
+        "{}/tests/fixtures/paper.pdf",
+        env!("CARGO_MANIFEST_DIR")
+    ))
+    .unwrap();
+    Mock::given(method("GET"))
+        .and(path("/api/query"))
+        .and(query_param("search_query", "all:hnsw AND all:graphs"))
+        .and(query_param("max_results", "3"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(arxiv_feed(&web.url("/pdf/1603"))))
+        .mount(&web.server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/pdf/1603"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("content-type", "application/pdf")
+                .set_body_bytes(pdf),
+        )
+        .mount(&web.server)
+        .await;
+
+    let results = Arxiv::new(&web.url("/api/query"), Arc::new(common::scraper()))
+        .search("papers on HNSW graphs", 3, &SearchOptions::default())
+        .await
+        .unwrap();
+
+    assert_eq!(results.len(), 1);
+    let paper = &results[0];
+    assert_eq!(paper.url, "http://arxiv.org/abs/1603.09320v4");
+    assert_eq!(
+        paper.name,
+        "Efficient and robust approximate nearest neighbor search using HNSW graphs"
+    );
+    assert_eq!(paper.published.unwrap().to_string(), "2018-08-14");
+    let content = paper.content.as_deref().unwrap();
+    assert!(
+        content.starts_with("We present a new approach for approximate nearest neighbor search.")
+    );
+    assert!(content.contains("Graph indexes find nearest neighbours in logarithmic time."));
+}
+
+#[tokio::test]
+async fn arxiv_keeps_the_abstract_when_the_pdf_fails() {
+    let web = MockWeb::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/query"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_string(arxiv_feed(&web.url("/pdf/broken"))),
+        )
+        .mount(&web.server)
+        .await;
+    web.page("/pdf/broken", "not a pdf").await;
+
+    let results = Arxiv::new(&web.url("/api/query"), Arc::new(common::scraper()))
+        .search("hnsw", 3, &SearchOptions::default())
+        .await
+        .unwrap();
+
+    assert_eq!(
+        results[0].content.as_deref(),
+        Some("We present a new approach for approximate nearest neighbor search.")
+    );
+}
+
+#[tokio::test]
+async fn arxiv_fetches_pdfs_under_the_url_policy() {
+    let web = MockWeb::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/query"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(arxiv_feed(&web.url("/pdf/1603"))))
+        .mount(&web.server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/pdf/1603"))
+        .respond_with(ResponseTemplate::new(200).insert_header("content-type", "application/pdf"))
+        .expect(0)
+        .mount(&web.server)
+        .await;
+
+    // the default policy refuses loopback addresses like the mock server's
+    let results = Arxiv::new(&web.url("/api/query"), Arc::new(WebScraper::default()))
+        .search("hnsw", 3, &SearchOptions::default())
+        .await
+        .unwrap();
+
+    assert_eq!(
+        results[0].content.as_deref(),
+        Some("We present a new approach for approximate nearest neighbor search.")
+    );
+}
+
+#[test]
+fn arxiv_query_drops_question_words_and_adds_date_range() {
+    assert_eq!(
+        search::arxiv_query("What are recent papers on graph-based ANN search?", None),
+        "all:graph-based AND all:ann AND all:search"
+    );
+    let query = search::arxiv_query("diffusion models", Some(TimeRange::Week));
+    assert!(query.starts_with("all:diffusion AND all:models AND submittedDate:["));
+}
+
+#[test]
+fn routes_queries_by_intent() {
+    assert_eq!(
+        search::route("recent papers on diffusion models"),
+        Source::Arxiv
+    );
+    assert_eq!(
+        search::route("state of the art in speech recognition"),
+        Source::Arxiv
+    );
+    assert_eq!(search::route("Who was Ada Lovelace?"), Source::Wiki);
+    assert_eq!(search::route("history of the printing press"), Source::Wiki);
+    assert_eq!(search::route("How does HNSW search work"), Source::Web);
+}
+
+#[tokio::test]
+async fn router_falls_back_to_the_web() {
+    let web = MockWeb::start().await;
+    web.search_results("Who was Ada Lovelace?", &[("Ada", "/ada")])
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/w/api.php"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "query": { "search": [] }
+        })))
+        .mount(&web.server)
+        .await;
+
+    let router = Router {
+        web: Arc::new(Searxng::new(&web.searxng_endpoint())),
+        wikipedia: Arc::new(Wikipedia::new(&web.url("/w/api.php"))),
+        arxiv: Arc::new(Arxiv::new(
+            &web.url("/api/query"),
+            Arc::new(common::scraper()),
+        )),
+    };
+    let results = router
+        .search("Who was Ada Lovelace?", 5, &SearchOptions::default())
+        .await
+        .unwrap();
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].url, web.url("/ada"));
+    let wiki_searches = web
+        .server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter(|r| r.url.path() == "/w/api.php")
+        .count();
+    assert_eq!(wiki_searches, 1);
+}
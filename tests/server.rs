@@ -0,0 +1,241 @@
+mod common;
+
+use common::{fixture, pipeline, MockWeb, ScriptedChat};
+use fyin::server;
+use serde_json::{json, Value};
+
+const QUERY: &str = "How does HNSW search work";
+const FOLLOW_UPS: &str = "1. How is an HNSW index built?\n2. What is a small world graph?";
+
+fn questions() -> [&'static str; 2] {
+    [
+        "How is an HNSW index built?",
+        "What is a small world graph?",
+    ]
+}
+
+async fn start(web: &MockWeb, chat: &ScriptedChat) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let pipeline = pipeline(web, chat).await;
+    tokio::spawn(server::serve(listener, pipeline));
+    format!("http://{}", addr)
+}
+
+async fn web_with_page() -> MockWeb {
+    let web = MockWeb::start().await;
+    web.search_results(QUERY, &[("HNSW explained", "/hnsw")])
+        .await;
+    web.page("/hnsw", &fixture("hnsw.html")).await;
+    web
+}
+
+fn messages() -> Value {
+    json!([
+        { "role": "system", "content": "You are helpful." },
+        { "role": "user", "content": "What is Rust?" },
+        { "role": "assistant", "content": "A language." },
+        { "role": "user", "content": [{ "type": "text", "text": QUERY }] }
+    ])
+}
+
+#[tokio::test]
+async fn answers_the_last_user_message() {
+    let web = web_with_page().await;
+    let chat = ScriptedChat::new(&["It walks a layered graph [1].", FOLLOW_UPS]);
+    let url = format!("{}/v1/chat/completions", start(&web, &chat).await);
+
+    let response: Value = reqwest::Client::new()
+        .post(&url)
+        .json(&json!({ "model": "gpt-4o", "messages": messages(), "follow_ups": true }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(response["object"], "chat.completion");
+    assert_eq!(response["model"], "gpt-4o");
+    assert_eq!(response["choices"][0]["message"]["role"], "assistant");
+    assert_eq!(
+        response["choices"][0]["message"]["content"],
+        "It walks a layered graph [1]."
+    );
+    assert_eq!(response["choices"][0]["finish_reason"], "stop");
+    assert_eq!(response["citations"], json!([web.url("/hnsw")]));
+    assert_eq!(response["follow_ups"], json!(questions()));
+    assert!(chat.prompts()[0].contains(QUERY));
+    assert!(!chat.prompts()[0].contains("What is Rust?"));
+}
+
+#[tokio::test]
+async fn streams_chunks_in_openai_format() {
+    let web = web_with_page().await;
+    let chat = ScriptedChat::new(&["It walks a layered graph [1].", FOLLOW_UPS]);
+    let url = format!("{}/v1/chat/completions", start(&web, &chat).await);
+
+    let response = reqwest::Client::new()
+        .post(&url)
+        .json(&json!({ "messages": messages(), "stream": true, "follow_ups": true }))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/event-stream"));
+    let body = response.text().await.unwrap();
+
+    let data: Vec<&str> = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .collect();
+    assert_eq!(data.last(), Some(&"[DONE]"));
+    let chunks: Vec<Value> = data[..data.len() - 1]
+        .iter()
+        .map(|d| serde_json::from_str(d).unwrap())
+        .collect();
+    assert!(chunks
+        .iter()
+        .all(|c| c["object"] == "chat.completion.chunk" && c["model"] == server::MODEL));
+    assert_eq!(chunks[0]["choices"][0]["delta"]["role"], "assistant");
+    let text: String = chunks
+        .iter()
+        .filter_map(|c| c["choices"][0]["delta"]["content"].as_str())
+        .collect();
+    assert_eq!(text, "It walks a layered graph [1].");
+    let last = chunks.last().unwrap();
+    assert_eq!(last["choices"][0]["finish_reason"], "stop");
+    assert_eq!(last["citations"], json!([web.url("/hnsw")]));
+    assert_eq!(last["follow_ups"], json!(questions()));
+    assert!(chunks[..chunks.len() - 1]
+        .iter()
+        .all(|c| c.get("follow_ups").is_none()));
+}
+
+#[tokio::test]
+async fn suggests_follow_ups_only_when_asked() {
+    let web = web_with_page().await;
+    let chat = ScriptedChat::new(&["It walks a layered graph [1]."]);
+    let url = format!("{}/v1/chat/completions", start(&web, &chat).await);
+
+    let response: Value = reqwest::Client::new()
+        .post(&url)
+        .json(&json!({ "messages": messages() }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(
+        response["choices"][0]["message"]["content"],
+        "It walks a layered graph [1]."
+    );
+    assert!(response.get("follow_ups").is_none());
+    assert_eq!(chat.prompts().len(), 1);
+}
+
+#[tokio::test]
+async fn rejects_requests_without_a_user_message() {
+    let web = MockWeb::start().await;
+    let chat = ScriptedChat::new(&[]);
+    let url = format!("{}/v1/chat/completions", start(&web, &chat).await);
+
+    let response = reqwest::Client::new()
+        .post(&url)
+        .json(&json!({ "messages": [{ "role": "system", "content": "Hi" }] }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), 400);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["type"], "invalid_request_error");
+    assert!(chat.prompts().is_empty());
+}
+
+#[tokio::test]
+async fn serves_the_embedded_web_ui() {
+    let web = MockWeb::start().await;
+    let chat = ScriptedChat::new(&[]);
+    let base = start(&web, &chat).await;
+
+    let page = reqwest::get(&base).await.unwrap();
+    assert!(page.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+    let html = page.text().await.unwrap();
+    assert!(html.contains("<script src=\"/app.js\">"));
+    let script = reqwest::get(format!("{}/app.js", base)).await.unwrap();
+    assert!(script.status().is_success());
+    assert!(script.text().await.unwrap().contains("/api/ask"));
+}
+
+#[tokio::test]
+async fn streams_pipeline_events_to_the_web_ui() {
+    let web = web_with_page().await;
+    let chat = ScriptedChat::new(&["It walks a layered graph [1].", FOLLOW_UPS]);
+    let base = start(&web, &chat).await;
+
+    let body = reqwest::Client::new()
+        .get(format!("{}/api/ask", base))
+        .query(&[("q", QUERY)])
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    let events: Vec<Value> = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .map(|data| serde_json::from_str(data).unwrap())
+        .collect();
+    let types: Vec<_> = events.iter().map(|e| e["type"].as_str().unwrap()).collect();
+    assert_eq!(types.first(), Some(&"search_started"));
+    assert_eq!(types[types.len() - 2], "done");
+    assert_eq!(events.last().unwrap()["type"], "follow_ups");
+    assert_eq!(events.last().unwrap()["questions"], json!(questions()));
+    let retrieval = events
+        .iter()
+        .find(|e| e["type"] == "retrieval_done")
+        .unwrap();
+    assert_eq!(retrieval["chunks"][0]["url"], web.url("/hnsw"));
+    let text: String = events.iter().filter_map(|e| e["text"].as_str()).collect();
+    assert_eq!(text, "It walks a layered graph [1].");
+}
+
+#[tokio::test]
+async fn reports_failed_web_ui_queries() {
+    let web = MockWeb::start().await;
+    let chat = ScriptedChat::new(&[]);
+    let base = start(&web, &chat).await;
+
+    let body = reqwest::Client::new()
+        .get(format!("{}/api/ask", base))
+        .query(&[("q", QUERY)])
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    let last: Value = serde_json::from_str(
+        body.lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .next_back()
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(last["type"], "error");
+}
@@ -0,0 +1,175 @@
+mod common;
+
+use common::{builder, fixture, MockWeb, ScriptedChat};
+use fyin::embedding::WordChunker;
+use fyin::{scraper, summarize, Chunk, SearchResult};
+use std::path::Path;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn url_document(url: &str) -> SearchResult {
+    SearchResult {
+        name: url.to_string(),
+        url: url.to_string(),
+        content: None,
+        published: None,
+        language: None,
+    }
+}
+
+async fn text_page(web: &MockWeb, page: &str, text: &str) {
+    Mock::given(method("GET"))
+        .and(path(page))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("content-type", "text/plain; charset=utf-8")
+                .set_body_string(text),
+        )
+        .mount(&web.server)
+        .await;
+}
+
+#[tokio::test]
+async fn summarizes_every_section_and_merges_hierarchically() {
+    let web = MockWeb::start().await;
+    text_page(&web, "/rfc", "Clients MUST retry idempotent requests.").await;
+    let notes = std::env::temp_dir().join(format!("fyin-notes-{}.txt", std::process::id()));
+    std::fs::write(
+        &notes,
+        "The first part covers framing rules. The second part covers flow control.",
+    )
+    .unwrap();
+    let chat = ScriptedChat::new(&[
+        "Framing [1].",
+        "Flow control [2].",
+        "Retries [3].",
+        "Framing and flow control [1][2].",
+        "Retries [3].",
+        "Final summary [1][2][3].",
+    ]);
+    let documents = vec![
+        scraper::read_file(&notes).await.unwrap(),
+        url_document(&web.url("/rfc")),
+    ];
+
+    let answer = builder(&web, &chat)
+        .chunker(WordChunker { size: 6 })
+        .summary_fan_in(2)
+        .build()
+        .await
+        .unwrap()
+        .summarize("What do the specs require?", documents, None)
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+    std::fs::remove_file(&notes).unwrap();
+
+    assert_eq!(answer.text, "Final summary [1][2][3].");
+    let sections: Vec<_> = answer.chunks.iter().map(|c| c.url.as_str()).collect();
+    assert_eq!(sections.len(), 3);
+    assert!(sections[0].starts_with("file://") && sections[0] == sections[1]);
+    assert_eq!(sections[2], web.url("/rfc"));
+    assert!(answer.chunks[2].content.contains("MUST retry"));
+
+    let prompts = chat.prompts();
+    assert_eq!(prompts.len(), 6);
+    assert!(prompts[..3]
+        .iter()
+        .all(|p| p.contains("What do the specs require?")));
+    assert!(prompts[2].contains("<source id=\"3\">") && prompts[2].contains("[3]"));
+    assert_eq!(
+        prompts
+            .iter()
+            .filter(|p| p.contains("intermediate step"))
+            .count(),
+        2
+    );
+    let last = prompts.last().unwrap();
+    assert!(last.contains("Framing and flow control [1][2]."));
+    let file_name = notes.file_name().unwrap().to_string_lossy();
+    assert!(last.contains(&format!("[2] {}, section 2 of 2 - file://", file_name)));
+    assert!(last.contains(&format!(
+        "[3] {}, section 1 of 1 - {}",
+        web.url("/rfc"),
+        web.url("/rfc")
+    )));
+}
+
+#[tokio::test]
+async fn summarizes_search_results_without_documents() {
+    let web = MockWeb::start().await;
+    web.search_results("hnsw", &[("HNSW explained", "/hnsw"), ("Rust", "/rust")])
+        .await;
+    web.page("/hnsw", &fixture("hnsw.html")).await;
+    web.page("/rust", &fixture("rust.html")).await;
+    let chat = ScriptedChat::new(&["A [1].", "B [2].", "Both [1][2]."]);
+
+    let answer = builder(&web, &chat)
+        .build()
+        .await
+        .unwrap()
+        .summarize("hnsw", vec![], None)
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+
+    assert_eq!(answer.text, "Both [1][2].");
+    assert_eq!(answer.sources.len(), 2);
+    assert_eq!(answer.chunks.len(), 2);
+    assert_eq!(chat.prompts().len(), 3);
+}
+
+#[tokio::test]
+async fn fails_when_no_document_can_be_read() {
+    let web = MockWeb::start().await;
+    web.failing_page("/gone", 404).await;
+    let chat = ScriptedChat::new(&[]);
+
+    let result = builder(&web, &chat)
+        .build()
+        .await
+        .unwrap()
+        .summarize("anything", vec![url_document(&web.url("/gone"))], None)
+        .await;
+
+    assert!(result.is_err());
+    assert!(chat.prompts().is_empty());
+}
+
+#[tokio::test]
+async fn reads_pdf_files() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/paper.pdf");
+
+    let document = scraper::read_file(&path).await.unwrap();
+
+    assert_eq!(document.name, "paper.pdf");
+    assert!(document.url.starts_with("file:///"));
+    assert!(document
+        .content
+        .unwrap()
+        .contains("Graph indexes find nearest neighbours"));
+}
+
+#[test]
+fn citation_list_numbers_sections_per_document() {
+    let section = |url: &str| Chunk {
+        content: String::new(),
+        name: url.to_uppercase(),
+        url: url.to_string(),
+        published: None,
+    };
+    let sections = [section("a"), section("a"), section("b")];
+
+    assert_eq!(
+        summarize::citation_list(&sections),
+        [
+            "[1] A, section 1 of 2 - a",
+            "[2] A, section 2 of 2 - a",
+            "[3] B, section 1 of 1 - b"
+        ]
+    );
+}
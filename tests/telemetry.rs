@@ -0,0 +1,67 @@
+mod common;
+
+use common::{fixture, pipeline, MockWeb, ScriptedChat};
+use fyin::telemetry::{JsonTrace, Timings};
+use serde_json::Value;
+use std::fs;
+use tracing_subscriber::prelude::*;
+
+const QUERY: &str = "How does HNSW search work";
+
+#[tokio::test]
+async fn records_a_span_per_stage_page_and_chunk() {
+    let web = MockWeb::start().await;
+    web.search_results(
+        QUERY,
+        &[("HNSW explained", "/hnsw"), ("Broken page", "/broken")],
+    )
+    .await;
+    web.page("/hnsw", &fixture("hnsw.html")).await;
+    web.failing_page("/broken", 500).await;
+
+    let path = std::env::temp_dir().join(format!("fyin-trace-{}.jsonl", std::process::id()));
+    let timings = Timings::default();
+    let subscriber = tracing_subscriber::registry()
+        .with(timings.clone())
+        .with(JsonTrace::create(&path).unwrap());
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let chat = ScriptedChat::new(&["HNSW walks a layered graph [1]."]);
+    pipeline(&web, &chat)
+        .await
+        .run(QUERY)
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+
+    let stages: Vec<_> = timings
+        .stages()
+        .into_iter()
+        .map(|stage| (stage.name, stage.count))
+        .collect();
+    let count = |name: &str| stages.iter().find(|(n, _)| n == name).map(|(_, c)| *c);
+    assert_eq!(stages[0].0, "query");
+    assert_eq!(count("search"), Some(1));
+    assert_eq!(count("scrape"), Some(2));
+    assert_eq!(count("embed"), Some(1));
+    assert_eq!(count("retrieve"), Some(1));
+    assert_eq!(count("generate"), Some(1));
+    assert!(timings.table().contains("gather"));
+
+    let spans: Vec<Value> = fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    fs::remove_file(&path).unwrap();
+
+    let span = |name: &str| spans.iter().find(|s| s["name"] == name).unwrap();
+    assert_eq!(span("search")["fields"]["results"], 2);
+    assert_eq!(span("gather")["fields"]["pages"], 2);
+    assert_eq!(span("gather")["fields"]["chunks"], 1);
+    assert_eq!(span("generate")["fields"]["tokens"], 6);
+    assert!(span("generate")["fields"]["first_token_ms"].is_number());
+    assert_eq!(span("search")["parent_id"], span("query")["span_id"]);
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use common::{builder, fixture, MockWeb, ScriptedChat};
+use fyin::templates::{Template, Variables, STYLES};
+
+fn variables<'a>(sources: &'a str, question: &'a str) -> Variables<'a> {
+    Variables {
+        sources,
+        question,
+        date: "2024-05-01",
+        language: "German",
+    }
+}
+
+#[test]
+fn built_in_styles_fill_every_placeholder() {
+    for style in STYLES {
+        let prompt = Template::find(style)
+            .unwrap()
+            .render(&variables("<source id=\"1\">", "What is HNSW?"));
+
+        assert!(prompt.contains("<source id=\"1\">"), "{}", style);
+        assert!(prompt.contains("What is HNSW?"), "{}", style);
+        assert!(prompt.contains("Today's date is 2024-05-01"), "{}", style);
+        assert!(prompt.contains("Write the answer in German"), "{}", style);
+        assert!(!prompt.contains('{'), "{}", style);
+    }
+    assert_eq!(Template::default().name, "detailed");
+}
+
+#[test]
+fn leaves_placeholders_inside_values_alone() {
+    let template = Template::parse("plain", "{sources}\n---\n{question}").unwrap();
+
+    let prompt = template.render(&variables("a page mentioning {question}", "Why?"));
+
+    assert_eq!(prompt, "a page mentioning {question}\n---\nWhy?");
+}
+
+#[test]
+fn loads_custom_templates_from_files() {
+    let dir = std::env::temp_dir().join(format!("fyin-templates-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("support.txt"),
+        "Answer as our support team.\n{sources}\nQ: {question} ({date})",
+    )
+    .unwrap();
+    std::fs::write(dir.join("broken.txt"), "Just answer {question}").unwrap();
+    std::env::set_var("FYIN_TEMPLATE_DIR", &dir);
+
+    let by_name = Template::find("support").unwrap();
+    let by_path = Template::find(dir.join("support.txt").to_str().unwrap()).unwrap();
+    let broken = Template::find("broken");
+    let unknown = Template::find("poetry");
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(by_name, by_path);
+    assert_eq!(by_name.name, "support");
+    assert_eq!(
+        by_name.render(&variables("S", "Q?")),
+        "Answer as our support team.\nS\nQ: Q? (2024-05-01)"
+    );
+    assert!(broken.unwrap_err().to_string().contains("{sources}"));
+    assert!(unknown.unwrap_err().to_string().contains("concise"));
+}
+
+#[tokio::test]
+async fn pipeline_answers_with_the_chosen_style() {
+    let web = MockWeb::start().await;
+    web.search_results("hnsw", &[("HNSW explained", "/hnsw")])
+        .await;
+    web.page("/hnsw", &fixture("hnsw.html")).await;
+    let chat = ScriptedChat::new(&["A layered graph [1]."]);
+
+    builder(&web, &chat)
+        .template(Template::find("concise").unwrap())
+        .build()
+        .await
+        .unwrap()
+        .run("hnsw")
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+
+    let prompt = &chat.prompts()[0];
+    assert!(prompt.contains("at most three sentences"));
+    assert!(prompt.contains("greedily walks"));
+    assert!(prompt.contains("Write the answer in the same language as the question"));
+}
@@ -0,0 +1,358 @@
+mod common;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use common::{builder, drain, fixture, MockWeb, ScriptedChat};
+use fyin::llm::{ChatProvider, LlmAgent, TokenStream};
+use fyin::tools::{self, ChatMessage, ToolCall, ToolSpec};
+use fyin::PipelineEvent;
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const QUERY: &str = "How many years ago was Rust 1.0 released";
+const SEARCH_AGAIN: &str = "HNSW graph search";
+
+/// Replies to tool-calling turns with queued calls, then with text, and
+/// records the conversation of every turn.
+#[derive(Clone, Default)]
+struct ToolChat {
+    turns: Arc<Mutex<VecDeque<Vec<ToolCall>>>>,
+    answer: String,
+    conversations: Arc<Mutex<Vec<Vec<ChatMessage>>>>,
+}
+
+impl ToolChat {
+    fn new(turns: Vec<Vec<ToolCall>>, answer: &str) -> Self {
+        ToolChat {
+            turns: Arc::new(Mutex::new(turns.into())),
+            answer: answer.to_string(),
+            conversations: Default::default(),
+        }
+    }
+
+    fn conversations(&self) -> Vec<Vec<ChatMessage>> {
+        self.conversations.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl ChatProvider for ToolChat {
+    async fn stream(&self, _prompt: &str) -> Result<TokenStream> {
+        panic!("a tool-calling answer must not be streamed")
+    }
+
+    async fn chat_with_tools(
+        &self,
+        messages: &[ChatMessage],
+        tools: &[ToolSpec],
+    ) -> Result<ChatMessage> {
+        self.conversations.lock().unwrap().push(messages.to_vec());
+        let tool_calls = match self.turns.lock().unwrap().pop_front() {
+            Some(calls) if !tools.is_empty() => calls,
+            _ => vec![],
+        };
+        let text = if tool_calls.is_empty() {
+            self.answer.clone()
+        } else {
+            String::new()
+        };
+        Ok(ChatMessage::Assistant { text, tool_calls })
+    }
+}
+
+fn call(id: &str, name: &str, arguments: Value) -> ToolCall {
+    ToolCall {
+        id: id.to_string(),
+        name: name.to_string(),
+        arguments,
+    }
+}
+
+fn tool_results(messages: &[ChatMessage]) -> Vec<(String, String)> {
+    messages
+        .iter()
+        .filter_map(|message| match message {
+            ChatMessage::Tool {
+                call_id, content, ..
+            } => Some((call_id.clone(), content.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn calculates_arithmetic() {
+    assert_eq!(tools::calculate("1 + 2 * 3").unwrap(), 7.0);
+    assert_eq!(tools::calculate("(1 + 2) * 3").unwrap(), 9.0);
+    assert_eq!(tools::calculate("-2^2").unwrap(), -4.0);
+    assert_eq!(tools::calculate("2^3^2").unwrap(), 512.0);
+    assert_eq!(tools::calculate("10 % 4 + sqrt(16)").unwrap(), 6.0);
+    assert_eq!(tools::calculate("1.5e3 / 2").unwrap(), 750.0);
+    assert_eq!(tools::calculate("round(2 * pi)").unwrap(), 6.0);
+
+    assert_eq!(
+        tools::calculator(&json!({"expression": "26.2 * 1.609344"})).unwrap(),
+        "42.1648128"
+    );
+    assert_eq!(
+        tools::calculator(&json!({"expression": "0.1 + 0.2"})).unwrap(),
+        "0.3"
+    );
+
+    assert!(tools::calculate("1 / 0").is_err());
+    assert!(tools::calculate("2 +").is_err());
+    assert!(tools::calculate("(2").is_err());
+    assert!(tools::calculate("cosh(2)").is_err());
+    assert!(tools::calculator(&json!({})).is_err());
+
+    // deep nesting is refused instead of overflowing the stack
+    assert!(tools::calculate(&format!("{}1", "-".repeat(100_000))).is_err());
+    assert!(tools::calculate(&format!("{}1{}", "(".repeat(100_000), ")".repeat(100_000))).is_err());
+    assert_eq!(
+        tools::calculate(&format!("{}1{}", "(".repeat(50), ")".repeat(50))).unwrap(),
+        1.0
+    );
+}
+
+#[test]
+fn does_date_arithmetic() {
+    assert_eq!(
+        tools::date(&json!({"operation": "add_days", "date": "2024-02-28", "days": 2})).unwrap(),
+        "2024-03-01 (Friday)"
+    );
+    assert_eq!(
+        tools::date(
+            &json!({"operation": "days_between", "date": "1969-07-20", "end": "1972-12-11"})
+        )
+        .unwrap(),
+        "1240 days"
+    );
+    assert_eq!(
+        tools::date(&json!({"operation": "weekday", "date": "2015-05-15"})).unwrap(),
+        "Fri"
+    );
+    assert!(tools::date(&json!({"operation": "weekday", "date": "15 May 2015"})).is_err());
+    assert!(tools::date(&json!({"operation": "add_days", "date": "2024-02-28"})).is_err());
+    assert!(
+        tools::date(&json!({"operation": "add_days", "date": "2024-02-28", "days": i64::MAX}))
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn answers_with_tool_results_and_new_sources() {
+    let web = MockWeb::start().await;
+    web.search_results(QUERY, &[("Rust", "/rust")]).await;
+    web.search_results(SEARCH_AGAIN, &[("HNSW explained", "/hnsw")])
+        .await;
+    web.page("/rust", &fixture("rust.html")).await;
+    web.page("/hnsw", &fixture("hnsw.html")).await;
+    let chat = ToolChat::new(
+        vec![
+            vec![
+                call("1", tools::CALCULATOR, json!({"expression": "2026 - 2015"})),
+                call("2", "weather", json!({})),
+            ],
+            vec![call(
+                "3",
+                tools::SEARCH_AGAIN,
+                json!({"query": SEARCH_AGAIN}),
+            )],
+        ],
+        "Rust 1.0 came out 11 years ago [1].",
+    );
+    let (sender, receiver) = mpsc::unbounded_channel();
+
+    let output = builder(&web, &ScriptedChat::default())
+        .chat(chat.clone())
+        .tools(true)
+        .build()
+        .await
+        .unwrap()
+        .run_with(
+            QUERY,
+            fyin::RunOptions {
+                events: Some(sender),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    let chunks = output.chunks.clone();
+    assert_eq!(output.sources.len(), 2);
+    let answer = output.collect().await.unwrap();
+    let events = drain(receiver).await;
+
+    assert_eq!(answer.text, "Rust 1.0 came out 11 years ago [1].");
+    // sources found by search_again are numbered after the first ones
+    let first_new = chunks
+        .iter()
+        .position(|chunk| chunk.url == web.url("/hnsw"))
+        .unwrap();
+    assert!(first_new > 0);
+    assert!(chunks[..first_new]
+        .iter()
+        .all(|chunk| chunk.url == web.url("/rust")));
+
+    let conversations = chat.conversations();
+    assert_eq!(conversations.len(), 3);
+    match &conversations[0][0] {
+        ChatMessage::User(prompt) => assert!(prompt.contains("TOOLS:")),
+        message => panic!("unexpected first message {:?}", message),
+    }
+    let results = tool_results(&conversations[2]);
+    assert_eq!(results[0], ("1".to_string(), "11".to_string()));
+    assert_eq!(results[1].0, "2");
+    assert!(results[1].1.starts_with("Error: Unknown tool"));
+    assert_eq!(results[2].0, "3");
+    assert!(results[2]
+        .1
+        .contains(&format!("<source id=\"{}\">", first_new + 1)));
+
+    let called: Vec<_> = events
+        .iter()
+        .filter_map(|event| match event {
+            PipelineEvent::ToolCalled { name, .. } => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        called,
+        vec![tools::CALCULATOR, "weather", tools::SEARCH_AGAIN]
+    );
+}
+
+#[tokio::test]
+async fn providers_without_tools_answer_in_plain_text() {
+    let web = MockWeb::start().await;
+    web.search_results(QUERY, &[("Rust", "/rust")]).await;
+    web.page("/rust", &fixture("rust.html")).await;
+    let chat = ScriptedChat::new(&["Rust 1.0 was released in 2015 [1]."]);
+
+    let answer = builder(&web, &chat)
+        .tools(true)
+        .build()
+        .await
+        .unwrap()
+        .run(QUERY)
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+
+    assert_eq!(answer.text, "Rust 1.0 was released in 2015 [1].");
+    assert!(chat.prompts()[0].contains("TOOLS:"));
+}
+
+async fn mock_chat(server: &MockServer, endpoint: &str, reply: Value) {
+    Mock::given(method("POST"))
+        .and(path(endpoint))
+        .respond_with(ResponseTemplate::new(200).set_body_json(reply))
+        .mount(server)
+        .await;
+}
+
+async fn last_body(server: &MockServer) -> Value {
+    let requests = server.received_requests().await.unwrap();
+    requests.last().unwrap().body_json().unwrap()
+}
+
+// Both wire formats in one test, since the agent reads its endpoint from the
+// environment
+#[tokio::test]
+async fn speaks_openai_and_ollama_tool_calling() {
+    let server = MockServer::start().await;
+    let conversation = vec![
+        ChatMessage::User("What is 2 * 21?".to_string()),
+        ChatMessage::Assistant {
+            text: String::new(),
+            tool_calls: vec![call(
+                "call_1",
+                tools::CALCULATOR,
+                json!({"expression": "2 * 21"}),
+            )],
+        },
+        ChatMessage::Tool {
+            call_id: "call_1".to_string(),
+            name: tools::CALCULATOR.to_string(),
+            content: "42".to_string(),
+        },
+    ];
+    std::env::set_var("OPENAI_API_KEY", "test-key");
+    std::env::set_var("CHAT_MODEL_NAME", "test-model");
+    std::env::set_var("EMBEDDING_MODEL_NAME", "test-embedding");
+
+    mock_chat(
+        &server,
+        "/v1/chat/completions",
+        json!({"choices": [{"message": {"role": "assistant", "content": null, "tool_calls": [
+            {"id": "call_2", "type": "function",
+             "function": {"name": "date", "arguments": "{\"operation\": \"today\"}"}}
+        ]}}]}),
+    )
+    .await;
+    std::env::set_var("OPENAI_BASE_URL", format!("{}/v1", server.uri()));
+    let reply = LlmAgent::init()
+        .await
+        .chat_with_tools(&conversation, &tools::specs())
+        .await
+        .unwrap();
+
+    assert_eq!(
+        reply,
+        ChatMessage::Assistant {
+            text: String::new(),
+            tool_calls: vec![call("call_2", tools::DATE, json!({"operation": "today"}))],
+        }
+    );
+    let body = last_body(&server).await;
+    assert_eq!(body["model"], "test-model");
+    assert_eq!(body["tools"].as_array().unwrap().len(), 3);
+    assert_eq!(body["tools"][0]["function"]["name"], tools::CALCULATOR);
+    assert_eq!(
+        body["messages"][1]["tool_calls"][0]["function"]["arguments"],
+        "{\"expression\":\"2 * 21\"}"
+    );
+    assert_eq!(body["messages"][2]["tool_call_id"], "call_1");
+
+    mock_chat(
+        &server,
+        "/api/chat",
+        json!({"message": {"role": "assistant", "content": "", "tool_calls": [
+            {"function": {"name": "calculator", "arguments": {"expression": "6 * 7"}}}
+        ]}, "done": true}),
+    )
+    .await;
+    // local mode is chosen by a localhost base URL
+    let port = server.address().port();
+    std::env::set_var("OPENAI_BASE_URL", format!("http://localhost:{}/v1", port));
+    let reply = LlmAgent::init()
+        .await
+        .chat_with_tools(&conversation, &tools::specs())
+        .await
+        .unwrap();
+
+    assert_eq!(
+        reply,
+        ChatMessage::Assistant {
+            text: String::new(),
+            tool_calls: vec![call(
+                "call_0",
+                tools::CALCULATOR,
+                json!({"expression": "6 * 7"})
+            )],
+        }
+    );
+    let body = last_body(&server).await;
+    assert_eq!(body["stream"], false);
+    assert_eq!(
+        body["messages"][1]["tool_calls"][0]["function"]["arguments"],
+        json!({"expression": "2 * 21"})
+    );
+    assert_eq!(body["messages"][2]["tool_name"], tools::CALCULATOR);
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use common::{fixture, scraper, MockWeb};
+use fyin::scraper::WebScraper;
+use fyin::url_policy::UrlPolicy;
+use fyin::Scraper;
+use reqwest::Url;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn check(policy: &UrlPolicy, url: &str) -> bool {
+    policy.check_url(&Url::parse(url).unwrap()).is_ok()
+}
+
+#[test]
+fn blocks_internal_addresses_schemes_and_ports() {
+    let policy = UrlPolicy::default();
+
+    assert!(check(&policy, "https://93.184.216.34/page"));
+    assert!(check(&policy, "https://example.com/page"));
+    assert!(check(&policy, "https://[2002:5db8:d822::1]/page"));
+    assert!(check(&policy, "https://[64:ff9b::5db8:d822]/page"));
+
+    for url in [
+        "http://127.0.0.1/",
+        "http://10.0.0.8/",
+        "http://192.168.1.1/admin",
+        "http://169.254.169.254/latest/meta-data/",
+        "http://100.64.0.1/",
+        "http://0.0.0.0/",
+        "http://[::1]/",
+        "http://[fd00::1]/",
+        "http://[::ffff:127.0.0.1]/",
+        "http://[::127.0.0.1]/",
+        "http://[::10.0.0.8]/",
+        "http://[64:ff9b::7f00:1]/",
+        "http://[64:ff9b::169.254.169.254]/",
+        "http://[64:ff9b:1::a00:8]/",
+        "http://[2002:7f00:1::]/",
+        "http://[2002:c0a8:101::1]/",
+        "http://[fec0::1]/",
+        "file:///etc/passwd",
+        "ftp://example.com/",
+        "https://example.com:22/",
+    ] {
+        assert!(!check(&policy, url), "{} should be blocked", url);
+    }
+}
+
+#[test]
+fn allowed_hosts_and_private_networks_can_be_opted_in() {
+    let trusted = UrlPolicy {
+        allowed_hosts: vec!["192.168.1.1".to_string()],
+        ..Default::default()
+    };
+    assert!(check(&trusted, "http://192.168.1.1:9000/wiki"));
+    assert!(!check(&trusted, "http://192.168.1.2/"));
+
+    let private = UrlPolicy {
+        allow_private: true,
+        ..Default::default()
+    };
+    assert!(check(&private, "http://192.168.1.2/"));
+    assert!(!check(&private, "file:///etc/passwd"));
+}
+
+#[tokio::test]
+async fn refuses_hosts_resolving_to_loopback() {
+    let web = MockWeb::start().await;
+    web.page("/hnsw", &fixture("hnsw.html")).await;
+    let port = web.server.address().port();
+    let policy = UrlPolicy {
+        ports: vec![],
+        ..Default::default()
+    };
+
+    let result = WebScraper::new(policy)
+        .scrape(&format!("http://localhost:{}/hnsw", port))
+        .await;
+
+    let error = format!("{:?}", result.unwrap_err());
+    assert!(error.contains("blocked addresses"), "{}", error);
+}
+
+#[tokio::test]
+async fn checks_every_redirect_hop() {
+    let web = MockWeb::start().await;
+    Mock::given(method("GET"))
+        .and(path("/redirect"))
+        .respond_with(
+            ResponseTemplate::new(302)
+                .insert_header("location", "http://169.254.169.254/latest/meta-data/"),
+        )
+        .mount(&web.server)
+        .await;
+
+    let result = scraper().scrape(&web.url("/redirect")).await;
+
+    let error = format!("{:?}", result.unwrap_err());
+    assert!(
+        error.contains("Blocked address 169.254.169.254"),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn limits_redirects() {
+    let web = MockWeb::start().await;
+    Mock::given(method("GET"))
+        .and(path("/loop"))
+        .respond_with(ResponseTemplate::new(302).insert_header("location", web.url("/loop")))
+        .mount(&web.server)
+        .await;
+
+    let result = scraper().scrape(&web.url("/loop")).await;
+
+    let error = format!("{:?}", result.unwrap_err());
+    assert!(error.contains("Too many redirects"), "{}", error);
+}
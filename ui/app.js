@@ -0,0 +1,282 @@
+// fyin web UI: streams /api/ask events for each question and keeps past
+// conversations in localStorage. No external resources, so it works offline.
+
+const HISTORY_KEY = "fyin-history";
+const MAX_CONVERSATIONS = 50;
+
+const thread = document.getElementById("thread");
+const form = document.getElementById("ask");
+const input = document.getElementById("query");
+const submit = document.getElementById("submit");
+const historyList = document.getElementById("history");
+const template = document.getElementById("turn-template");
+
+// A conversation is { id, turns: [{ query, answer, chunks, followUps, error }] }
+let conversation = newConversation();
+
+function newConversation() {
+  return { id: Date.now().toString(36), turns: [] };
+}
+
+function loadHistory() {
+  try {
+    return JSON.parse(localStorage.getItem(HISTORY_KEY)) || [];
+  } catch {
+    return [];
+  }
+}
+
+function saveConversation() {
+  const history = loadHistory().filter((c) => c.id !== conversation.id);
+  history.unshift(conversation);
+  localStorage.setItem(HISTORY_KEY, JSON.stringify(history.slice(0, MAX_CONVERSATIONS)));
+  renderHistory();
+}
+
+function renderHistory() {
+  historyList.replaceChildren(
+    ...loadHistory().map((c) => {
+      const item = document.createElement("li");
+      item.textContent = c.turns[0]?.query || "Untitled";
+      item.title = item.textContent;
+      item.classList.toggle("active", c.id === conversation.id);
+      item.addEventListener("click", () => showConversation(c));
+      return item;
+    }),
+  );
+}
+
+function showConversation(c) {
+  conversation = c;
+  thread.replaceChildren(...c.turns.map((turn) => renderTurn(turn).element));
+  renderHistory();
+}
+
+function domain(url) {
+  try {
+    const parsed = new URL(url);
+    return parsed.protocol === "file:" ? "local file" : parsed.hostname.replace(/^www\./, "");
+  } catch {
+    return url;
+  }
+}
+
+// A letter badge stands in for the site's icon, since fetching favicons
+// would tell every cited site what the user asked about
+function siteBadge(url) {
+  const badge = document.createElement("span");
+  badge.className = "site-badge";
+  badge.textContent = domain(url)[0] || "?";
+  return badge;
+}
+
+function escapeHtml(text) {
+  const div = document.createElement("div");
+  div.textContent = text;
+  return div.innerHTML;
+}
+
+// Turns [n] into links to source n, when there is one
+function renderAnswer(element, turn) {
+  element.innerHTML = escapeHtml(turn.answer).replace(/\[(\d+)\]/g, (match, n) =>
+    n >= 1 && n <= turn.chunks.length
+      ? `<a href="#" class="cite" data-source="${n}">[${n}]</a>`
+      : match,
+  );
+  if (turn.error) {
+    const error = document.createElement("p");
+    error.className = "error";
+    error.textContent = turn.error;
+    element.append(error);
+  }
+}
+
+function renderSources(element, chunks) {
+  element.replaceChildren(
+    ...chunks.map((chunk, i) => {
+      const item = document.createElement("li");
+      item.className = "source";
+      item.dataset.source = i + 1;
+
+      const head = document.createElement("div");
+      head.className = "source-head";
+      const number = document.createElement("span");
+      number.className = "number";
+      number.textContent = `[${i + 1}]`;
+      const link = document.createElement("a");
+      link.href = chunk.url;
+      link.target = "_blank";
+      link.rel = "noopener noreferrer";
+      link.textContent = chunk.name || chunk.url;
+      const site = document.createElement("span");
+      site.className = "domain";
+      site.textContent = domain(chunk.url);
+      head.append(number, siteBadge(chunk.url), link, site);
+
+      const snippet = document.createElement("p");
+      snippet.className = "snippet";
+      snippet.textContent = chunk.content;
+
+      item.append(head, snippet);
+      return item;
+    }),
+  );
+}
+
+function renderFollowUps(element, questions) {
+  element.replaceChildren(
+    ...(questions || []).map((question) => {
+      const item = document.createElement("li");
+      item.textContent = question;
+      item.addEventListener("click", () => {
+        if (!submit.disabled) ask(question);
+      });
+      return item;
+    }),
+  );
+}
+
+function renderTurn(turn) {
+  const element = template.content.firstElementChild.cloneNode(true);
+  const parts = {
+    element,
+    progress: element.querySelector(".progress"),
+    answer: element.querySelector(".answer"),
+    sources: element.querySelector(".sources"),
+    followUps: element.querySelector(".follow-ups"),
+  };
+  element.querySelector(".question").textContent = turn.query;
+  renderAnswer(parts.answer, turn);
+  renderSources(parts.sources, turn.chunks);
+  renderFollowUps(parts.followUps, turn.followUps);
+
+  parts.answer.addEventListener("click", (event) => {
+    const cite = event.target.closest(".cite");
+    if (!cite) return;
+    event.preventDefault();
+    for (const source of parts.sources.children) {
+      source.classList.toggle("highlight", source.dataset.source === cite.dataset.source);
+    }
+    parts.sources
+      .querySelector(`[data-source="${cite.dataset.source}"]`)
+      ?.scrollIntoView({ behavior: "smooth", block: "nearest" });
+  });
+  return parts;
+}
+
+function progressLine(parts, text, className) {
+  const line = document.createElement("li");
+  line.textContent = text;
+  if (className) line.className = className;
+  parts.progress.append(line);
+  return line;
+}
+
+function ask(query) {
+  const turn = { query, answer: "", chunks: [], followUps: [], error: null };
+  conversation.turns.push(turn);
+  const parts = renderTurn(turn);
+  thread.append(parts.element);
+  parts.element.scrollIntoView({ behavior: "smooth" });
+  submit.disabled = true;
+
+  const results = new Map();
+  let embedded = 0;
+  let embedLine = null;
+
+  const finish = () => {
+    events.close();
+    submit.disabled = false;
+    parts.progress.replaceChildren();
+    renderAnswer(parts.answer, turn);
+    saveConversation();
+  };
+
+  const events = new EventSource(`/api/ask?q=${encodeURIComponent(query)}`);
+  events.onmessage = (message) => {
+    const event = JSON.parse(message.data);
+    switch (event.type) {
+      case "search_started":
+        progressLine(parts, `Searching for: ${event.query}`);
+        break;
+      case "result_found":
+        results.set(event.url, progressLine(parts, `Found: ${event.name} (${domain(event.url)})`));
+        break;
+      case "page_scraped":
+        if (results.has(event.url)) {
+          results.get(event.url).textContent += ` - read ${event.bytes} bytes`;
+        }
+        break;
+      case "page_failed":
+        progressLine(parts, `Failed: ${domain(event.url)} (${event.error})`, "failed");
+        break;
+      case "chunk_embedded":
+        embedded += 1;
+        embedLine ??= progressLine(parts, "");
+        embedLine.textContent = `Embedded ${embedded} chunks`;
+        break;
+      case "injection_detected":
+        progressLine(parts, `Possible prompt injection: ${domain(event.url)}`, "failed");
+        break;
+      case "retrieval_done":
+        turn.chunks = event.chunks;
+        renderSources(parts.sources, turn.chunks);
+        progressLine(parts, `Retrieved ${event.chunks.length} chunks, answering...`);
+        break;
+      case "tool_called":
+        progressLine(parts, `Tool ${event.name}: ${event.result.split("\n")[0]}`);
+        break;
+      case "token":
+        turn.answer += event.text;
+        renderAnswer(parts.answer, turn);
+        break;
+      case "done":
+        parts.progress.replaceChildren();
+        progressLine(parts, "Suggesting related questions...");
+        break;
+      case "follow_ups":
+        turn.followUps = event.questions;
+        renderFollowUps(parts.followUps, turn.followUps);
+        finish();
+        break;
+      case "error":
+        turn.error = event.message;
+        finish();
+        break;
+    }
+  };
+  // EventSource reconnects on its own, which would ask the question again
+  events.onerror = () => {
+    turn.error ??= "Lost connection to fyin";
+    finish();
+  };
+}
+
+form.addEventListener("submit", (event) => {
+  event.preventDefault();
+  const query = input.value.trim();
+  if (!query || submit.disabled) return;
+  input.value = "";
+  ask(query);
+});
+
+input.addEventListener("keydown", (event) => {
+  if (event.key === "Enter" && !event.shiftKey) {
+    event.preventDefault();
+    form.requestSubmit();
+  }
+});
+
+document.getElementById("new-chat").addEventListener("click", () => {
+  conversation = newConversation();
+  thread.replaceChildren();
+  renderHistory();
+  input.focus();
+});
+
+document.getElementById("clear-history").addEventListener("click", () => {
+  localStorage.removeItem(HISTORY_KEY);
+  renderHistory();
+});
+
+renderHistory();